@@ -1,13 +1,19 @@
 use cadence::prelude::*;
 use cadence::{
-    BufferedUdpMetricSink, Counter, Gauge, Histogram, Meter, NopMetricSink, QueuingMetricSink, Set, StatsdClient,
-    Timer, UdpMetricSink, DEFAULT_PORT,
+    BufferedUdpMetricSink, Counter, Gauge, Histogram, Meter, MetricSink, NopMetricSink, QueuingMetricSink, Set,
+    StatsdClient, StripedBufferedUdpMetricSink, Timer, UdpMetricSink, DEFAULT_PORT,
 };
-use criterion::{criterion_group, criterion_main, Criterion};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
 use std::net::UdpSocket;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::Duration;
 
 const TARGET_HOST: (&str, u16) = ("127.0.0.1", DEFAULT_PORT);
 const QUEUE_SIZE: usize = 512 * 1024;
+const WORKER_BATCH: u64 = 1000;
+const CONTENDED_THREADS: usize = 4;
 
 fn new_nop_client() -> StatsdClient {
     StatsdClient::from_sink("client.bench", NopMetricSink)
@@ -167,6 +173,126 @@ fn benchmark_statsdclient_queuing(c: &mut Criterion) {
     });
 }
 
+fn benchmark_queuing_workers(c: &mut Criterion) {
+    let mut group = c.benchmark_group("queuing_workers");
+    group.throughput(Throughput::Elements(WORKER_BATCH));
+
+    for workers in [1, 4].iter() {
+        group.bench_function(format!("buffered_udp_{}_workers", workers), |b| {
+            let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+            let buffered = BufferedUdpMetricSink::from(TARGET_HOST, socket).unwrap();
+            let queuing = if *workers == 1 {
+                QueuingMetricSink::with_capacity(buffered, QUEUE_SIZE)
+            } else {
+                QueuingMetricSink::with_workers(buffered, QUEUE_SIZE, *workers)
+            };
+            let client = StatsdClient::from_sink("client.bench", queuing.clone());
+
+            // Measure until every metric has been sent by the worker threads,
+            // not just until they've been queued.
+            b.iter(|| {
+                for _ in 0..WORKER_BATCH {
+                    client.count("some.counter", 4).unwrap();
+                }
+                queuing.flush_and_wait(Duration::from_secs(10)).unwrap();
+            });
+        });
+    }
+
+    group.finish();
+}
+
+/// Threads that emit metrics directly to a sink all at once, so that the
+/// benchmark measures time spent waiting on the sink's locks. The threads are
+/// started once and each round is synchronized with barriers so that starting
+/// threads isn't part of what's measured.
+struct Contenders {
+    start: Arc<Barrier>,
+    done: Arc<Barrier>,
+    stop: Arc<AtomicBool>,
+    flush: Box<dyn Fn()>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl Contenders {
+    fn start<T>(sink: &Arc<T>) -> Self
+    where
+        T: MetricSink + Sync + Send + 'static,
+    {
+        let start = Arc::new(Barrier::new(CONTENDED_THREADS + 1));
+        let done = Arc::new(Barrier::new(CONTENDED_THREADS + 1));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let threads = (0..CONTENDED_THREADS)
+            .map(|_| {
+                let sink = Arc::clone(sink);
+                let start = Arc::clone(&start);
+                let done = Arc::clone(&done);
+                let stop = Arc::clone(&stop);
+
+                thread::spawn(move || loop {
+                    start.wait();
+                    if stop.load(Ordering::Acquire) {
+                        break;
+                    }
+
+                    for _ in 0..WORKER_BATCH {
+                        sink.emit("client.bench.some.counter:4|c").unwrap();
+                    }
+                    done.wait();
+                })
+            })
+            .collect();
+
+        let sink = Arc::clone(sink);
+        Contenders {
+            start,
+            done,
+            stop,
+            flush: Box::new(move || sink.flush().unwrap()),
+            threads,
+        }
+    }
+
+    /// Have every thread emit a batch of metrics, then flush the sink.
+    fn round(&self) {
+        self.start.wait();
+        self.done.wait();
+        (self.flush)();
+    }
+}
+
+impl Drop for Contenders {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        self.start.wait();
+        for t in self.threads.drain(..) {
+            t.join().unwrap();
+        }
+    }
+}
+
+fn benchmark_buffered_udp_contention(c: &mut Criterion) {
+    let mut group = c.benchmark_group("buffered_udp_contention");
+    group.throughput(Throughput::Elements(WORKER_BATCH * CONTENDED_THREADS as u64));
+
+    group.bench_function("buffered_udp", |b| {
+        let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+        let sink = Arc::new(BufferedUdpMetricSink::from(TARGET_HOST, socket).unwrap());
+        let contenders = Contenders::start(&sink);
+        b.iter(|| contenders.round());
+    });
+
+    group.bench_function("striped_buffered_udp", |b| {
+        let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+        let sink = Arc::new(StripedBufferedUdpMetricSink::from(TARGET_HOST, socket).unwrap());
+        let contenders = Contenders::start(&sink);
+        b.iter(|| contenders.round());
+    });
+
+    group.finish();
+}
+
 fn benchmark_new_metric_obj(c: &mut Criterion) {
     c.bench_function("counter_new", |b| b.iter(|| Counter::new("prefix", "some.counter", 5)));
     c.bench_function("timer_new", |b| b.iter(|| Timer::new("prefix", "some.timer", 5)));
@@ -187,6 +313,8 @@ criterion_group!(
     benchmark_statsdclient_udp,
     benchmark_statsdclient_buffered_udp,
     benchmark_statsdclient_queuing,
+    benchmark_queuing_workers,
+    benchmark_buffered_udp_contention,
     benchmark_new_metric_obj
 );
 
@@ -10,51 +10,11 @@
 // except according to those terms.
 
 use crate::client::{MetricBackend, StatsdClient};
-use crate::types::{Metric, MetricError, MetricResult};
+use crate::types::{Metric, MetricError, MetricRecord, MetricResult, MetricType, MetricValue};
 use std::fmt::{self, Write};
 use std::marker::PhantomData;
 
-/// Uniform holder for values that knows how to display itself
-#[derive(Debug, Clone, Copy)]
-enum MetricValue {
-    Signed(i64),
-    Unsigned(u64),
-    Float(f64),
-}
-
-impl fmt::Display for MetricValue {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
-            MetricValue::Signed(i) => i.fmt(f),
-            MetricValue::Unsigned(i) => i.fmt(f),
-            MetricValue::Float(i) => i.fmt(f),
-        }
-    }
-}
-
-/// Type of metric that knows how to display itself
-#[derive(Debug, Clone, Copy)]
-enum MetricType {
-    Counter,
-    Timer,
-    Gauge,
-    Meter,
-    Histogram,
-    Set,
-}
-
-impl fmt::Display for MetricType {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
-            MetricType::Counter => "c".fmt(f),
-            MetricType::Timer => "ms".fmt(f),
-            MetricType::Gauge => "g".fmt(f),
-            MetricType::Meter => "m".fmt(f),
-            MetricType::Histogram => "h".fmt(f),
-            MetricType::Set => "s".fmt(f),
-        }
-    }
-}
+const TAG_PREFIX: &str = "|#";
 
 #[derive(Debug, Clone)]
 pub(crate) struct MetricFormatter<'a, T>
@@ -67,14 +27,13 @@ where
     val: MetricValue,
     type_: MetricType,
     tags: Vec<(Option<&'a str>, &'a str)>,
+    base_tags: &'a [(Option<String>, String)],
 }
 
 impl<'a, T> MetricFormatter<'a, T>
 where
     T: Metric + From<String>,
 {
-    const TAG_PREFIX: &'static str = "|#";
-
     pub(crate) fn counter(prefix: &'a str, key: &'a str, val: i64) -> Self {
         Self::from_i64(prefix, key, val, MetricType::Counter)
     }
@@ -111,6 +70,7 @@ where
             val: MetricValue::Unsigned(val),
             metric: PhantomData,
             tags: Vec::new(),
+            base_tags: &[],
         }
     }
 
@@ -122,6 +82,7 @@ where
             val: MetricValue::Signed(val),
             metric: PhantomData,
             tags: Vec::new(),
+            base_tags: &[],
         }
     }
 
@@ -133,9 +94,17 @@ where
             val: MetricValue::Float(val),
             metric: PhantomData,
             tags: Vec::new(),
+            base_tags: &[],
         }
     }
 
+    /// Set tags added to every metric by the client, written after any
+    /// tags added to this particular metric.
+    pub(crate) fn with_base_tags(mut self, tags: &'a [(Option<String>, String)]) -> Self {
+        self.base_tags = tags;
+        self
+    }
+
     fn with_tag(&mut self, key: &'a str, value: &'a str) {
         self.tags.push((Some(key), value));
     }
@@ -148,20 +117,9 @@ where
         let _ = write!(out, "{}{}:{}|{}", self.prefix, self.key, self.val, self.type_);
     }
 
-    fn write_tags(&self, out: &mut String) {
-        if !self.tags.is_empty() {
-            out.push_str(Self::TAG_PREFIX);
-            for (i, &(key, value)) in self.tags.iter().enumerate() {
-                if i > 0 {
-                    out.push(',');
-                }
-                if let Some(key) = key {
-                    out.push_str(key);
-                    out.push(':');
-                }
-                out.push_str(value);
-            }
-        }
+    fn all_tags(&self) -> impl Iterator<Item = (Option<&str>, &str)> {
+        let base = self.base_tags.iter().map(|(k, v)| (k.as_deref(), v.as_str()));
+        self.tags.iter().copied().chain(base)
     }
 
     // Don't have rustfmt do anything to this method because it keeps wrapping
@@ -183,13 +141,13 @@ where
     }
 
     fn tag_size_hint(&self) -> usize {
-        if self.tags.is_empty() {
+        let num_tags = self.tags.len() + self.base_tags.len();
+        if num_tags == 0 {
             return 0;
         }
 
         let kv_size: usize = self
-            .tags
-            .iter()
+            .all_tags()
             .map(|(key, val)| {
                 // keys are optional so either include its length and ':' or zero
                 key.map(|s| s.len() + 1 /* : */).unwrap_or(0) + val.len()
@@ -197,23 +155,56 @@ where
             .sum();
 
         // prefix, keys and values, commas
-        Self::TAG_PREFIX.len() + kv_size + self.tags.len() - 1
+        TAG_PREFIX.len() + kv_size + num_tags - 1
     }
 
     pub(crate) fn build(&self) -> T {
         let size_hint = self.base_metric_size_hint() + self.tag_size_hint();
         let mut metric_string = String::with_capacity(size_hint);
         self.write_base_metric(&mut metric_string);
-        self.write_tags(&mut metric_string);
+        let _ = write_tags(&mut metric_string, self.all_tags());
         T::from(metric_string)
     }
+
+    /// Create a structured version of the metric being formatted, with
+    /// the prefix included in its key, to pass to interceptors.
+    pub(crate) fn to_record(&self) -> MetricRecord {
+        MetricRecord {
+            key: format!("{}{}", self.prefix, self.key),
+            value: self.val,
+            metric_type: self.type_,
+            tags: self
+                .all_tags()
+                .map(|(k, v)| (k.map(str::to_string), v.to_string()))
+                .collect(),
+        }
+    }
+}
+
+/// Write Datadog style tags, including the leading `|#`, if there are any.
+pub(crate) fn write_tags<'t, W, I>(out: &mut W, tags: I) -> fmt::Result
+where
+    W: Write,
+    I: Iterator<Item = (Option<&'t str>, &'t str)>,
+{
+    for (i, (key, value)) in tags.enumerate() {
+        out.write_str(if i == 0 { TAG_PREFIX } else { "," })?;
+        if let Some(key) = key {
+            out.write_str(key)?;
+            out.write_char(':')?;
+        }
+        out.write_str(value)?;
+    }
+
+    Ok(())
 }
 
 /// Internal state of a `MetricBuilder`
 ///
 /// The builder can either be in the process of formatting a metric to send
-/// via a client or it can be simply holding on to an error that it will be
-/// dealt with when `.try_send()` or `.send()` is finally invoked.
+/// via a client, be simply holding on to an error that it will be dealt with
+/// when `.try_send()` or `.send()` is finally invoked, or be formatting a
+/// metric that won't be sent because the client that created it is disabled.
 #[derive(Debug)]
 enum BuilderRepr<'m, 'c, T>
 where
@@ -221,6 +212,7 @@ where
 {
     Success(MetricFormatter<'m, T>, &'c StatsdClient),
     Error(MetricError, &'c StatsdClient),
+    Disabled(MetricFormatter<'m, T>),
 }
 
 /// Builder for adding tags to in-progress metrics.
@@ -309,9 +301,13 @@ where
     T: Metric + From<String>,
 {
     pub(crate) fn new(formatter: MetricFormatter<'m, T>, client: &'c StatsdClient) -> Self {
-        MetricBuilder {
-            repr: BuilderRepr::Success(formatter, client),
-        }
+        let repr = if client.is_enabled() {
+            BuilderRepr::Success(formatter, client)
+        } else {
+            BuilderRepr::Disabled(formatter)
+        };
+
+        MetricBuilder { repr }
     }
 
     pub(crate) fn from_error(err: MetricError, client: &'c StatsdClient) -> Self {
@@ -339,8 +335,11 @@ where
     /// );
     /// ```
     pub fn with_tag(mut self, key: &'m str, value: &'m str) -> Self {
-        if let BuilderRepr::Success(ref mut formatter, _) = self.repr {
-            formatter.with_tag(key, value);
+        match self.repr {
+            BuilderRepr::Success(ref mut formatter, _) | BuilderRepr::Disabled(ref mut formatter) => {
+                formatter.with_tag(key, value);
+            }
+            BuilderRepr::Error(..) => {}
         }
         self
     }
@@ -364,8 +363,11 @@ where
     /// );
     /// ```
     pub fn with_tag_value(mut self, value: &'m str) -> Self {
-        if let BuilderRepr::Success(ref mut formatter, _) = self.repr {
-            formatter.with_tag_value(value);
+        match self.repr {
+            BuilderRepr::Success(ref mut formatter, _) | BuilderRepr::Disabled(ref mut formatter) => {
+                formatter.with_tag_value(value);
+            }
+            BuilderRepr::Error(..) => {}
         }
         self
     }
@@ -375,6 +377,8 @@ where
     /// Note that the builder is consumed by this method and thus `.try_send()`
     /// can only be called a single time per builder.
     ///
+    /// If the client is disabled, the metric is returned without being sent.
+    ///
     /// # Example
     ///
     /// ```
@@ -394,6 +398,13 @@ where
     pub fn try_send(self) -> MetricResult<T> {
         match self.repr {
             BuilderRepr::Error(err, _) => Err(err),
+            BuilderRepr::Disabled(ref formatter) => Ok(formatter.build()),
+            // The structured record is only needed by interceptors, skip
+            // building it (and the allocations for its key and tags) otherwise.
+            BuilderRepr::Success(ref formatter, client) if client.has_interceptors() => {
+                client.send_intercepted(formatter.to_record())?;
+                Ok(formatter.build())
+            }
             BuilderRepr::Success(ref formatter, client) => {
                 let metric: T = formatter.build();
                 client.send_metric(&metric)?;
@@ -432,7 +443,13 @@ where
     /// ```
     pub fn send(self) {
         match self.repr {
-            BuilderRepr::Error(err, client) => client.consume_error(err),
+            // Errors aren't reported while the client is disabled
+            BuilderRepr::Error(err, client) => {
+                if client.is_enabled() {
+                    client.consume_error(err);
+                }
+            }
+            BuilderRepr::Disabled(_) => {}
             BuilderRepr::Success(_, client) => {
                 if let Err(e) = self.try_send() {
                     client.consume_error(e);
@@ -445,7 +462,21 @@ where
 #[cfg(test)]
 mod tests {
     use super::MetricFormatter;
-    use crate::types::{Counter, Gauge, Histogram, Meter, Metric, Set, Timer};
+    use crate::types::{Counter, Gauge, Histogram, Meter, Metric, MetricType, MetricValue, Set, Timer};
+
+    #[test]
+    fn test_metric_formatter_to_record() {
+        let base = vec![(Some("env".to_string()), "prod".to_string())];
+        let mut fmt: MetricFormatter<'_, Counter> =
+            MetricFormatter::counter("prefix.", "some.key", 4).with_base_tags(&base);
+        fmt.with_tag_value("beta");
+
+        let record = fmt.to_record();
+        assert_eq!("prefix.some.key", record.key);
+        assert_eq!(MetricValue::Signed(4), record.value);
+        assert_eq!(MetricType::Counter, record.metric_type);
+        assert_eq!(fmt.build().as_metric_str(), record.to_string());
+    }
 
     #[test]
     fn test_metric_formatter_tag_size_hint_no_tags() {
@@ -470,6 +501,16 @@ mod tests {
         assert_eq!(19, fmt.tag_size_hint());
     }
 
+    #[test]
+    fn test_metric_formatter_tag_size_hint_base_tags() {
+        let base = vec![(Some("env".to_string()), "prod".to_string())];
+        let mut fmt: MetricFormatter<'_, Counter> =
+            MetricFormatter::counter("prefix.", "some.key", 1).with_base_tags(&base);
+        fmt.with_tag_value("test");
+
+        assert_eq!(15, fmt.tag_size_hint());
+    }
+
     #[test]
     fn test_metric_formatter_counter_no_tags() {
         let fmt = MetricFormatter::counter("prefix.", "some.key", 4);
@@ -493,6 +534,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_metric_formatter_counter_with_base_tags() {
+        let base = vec![
+            (Some("env".to_string()), "prod".to_string()),
+            (None, "canary".to_string()),
+        ];
+        let mut fmt = MetricFormatter::counter("prefix.", "some.key", 4).with_base_tags(&base);
+        fmt.with_tag("host", "app03.example.com");
+
+        let counter: Counter = fmt.build();
+
+        assert_eq!(
+            "prefix.some.key:4|c|#host:app03.example.com,env:prod,canary",
+            counter.as_metric_str()
+        );
+    }
+
+    #[test]
+    fn test_metric_formatter_counter_only_base_tags() {
+        let base = vec![(Some("env".to_string()), "prod".to_string())];
+        let fmt = MetricFormatter::counter("prefix.", "some.key", 4).with_base_tags(&base);
+        let counter: Counter = fmt.build();
+
+        assert_eq!("prefix.some.key:4|c|#env:prod", counter.as_metric_str());
+    }
+
     #[test]
     fn test_metric_formatter_timer_no_tags() {
         let fmt = MetricFormatter::timer("prefix.", "some.method", 21);
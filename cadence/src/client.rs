@@ -9,11 +9,18 @@
 // except according to those terms.
 
 use crate::builder::{MetricBuilder, MetricFormatter};
+use crate::config::SinkConfig;
+use crate::env;
+use crate::interceptor::{self, BoxedInterceptor, MetricInterceptor};
+use crate::rename::RenameRules;
 use crate::sinks::{MetricSink, UdpMetricSink};
-use crate::types::{Counter, ErrorKind, Gauge, Histogram, Meter, Metric, MetricError, MetricResult, Set, Timer};
+use crate::types::{
+    Counter, ErrorKind, Gauge, Histogram, Meter, Metric, MetricError, MetricRecord, MetricResult, Set, Timer,
+};
 use std::fmt;
 use std::net::{ToSocketAddrs, UdpSocket};
 use std::panic::RefUnwindSafe;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 use std::u64;
@@ -373,6 +380,10 @@ pub struct StatsdClientBuilder {
     prefix: String,
     sink: Box<dyn MetricSink + Sync + Send + RefUnwindSafe>,
     errors: Box<dyn Fn(MetricError) + Sync + Send + RefUnwindSafe>,
+    tags: Vec<(Option<String>, String)>,
+    interceptors: Vec<BoxedInterceptor>,
+    enabled: bool,
+    dry_run: bool,
 }
 
 impl StatsdClientBuilder {
@@ -381,16 +392,66 @@ impl StatsdClientBuilder {
     where
         T: MetricSink + Sync + Send + RefUnwindSafe + 'static,
     {
+        Self::from_boxed_sink(prefix, Box::new(sink))
+    }
+
+    // Like `::new()` but for callers that have already picked a sink at runtime
+    pub(crate) fn from_boxed_sink(prefix: &str, sink: Box<dyn MetricSink + Sync + Send + RefUnwindSafe>) -> Self {
         StatsdClientBuilder {
             // required
             prefix: Self::get_formatted_prefix(prefix),
-            sink: Box::new(sink),
+            sink,
 
             // optional with defaults
             errors: Box::new(nop_error_handler),
+            tags: Vec::new(),
+            interceptors: Vec::new(),
+            enabled: true,
+            dry_run: false,
         }
     }
 
+    /// Add a default key-value tag that will be added to every metric
+    /// emitted by the client, after any tags added to a particular metric.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cadence::prelude::*;
+    /// use cadence::{Metric, StatsdClient, NopMetricSink};
+    ///
+    /// let client = StatsdClient::builder("prefix", NopMetricSink)
+    ///     .with_tag("env", "prod")
+    ///     .build();
+    ///
+    /// let res = client.count_with_tags("some.counter", 1)
+    ///     .with_tag("region", "us-east-2")
+    ///     .try_send();
+    ///
+    /// assert_eq!(
+    ///     "prefix.some.counter:1|c|#region:us-east-2,env:prod",
+    ///     res.unwrap().as_metric_str()
+    /// );
+    /// ```
+    pub fn with_tag<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: ToString,
+        V: ToString,
+    {
+        self.tags.push((Some(key.to_string()), value.to_string()));
+        self
+    }
+
+    /// Add a default value tag that will be added to every metric emitted
+    /// by the client, after any tags added to a particular metric.
+    pub fn with_tag_value<V>(mut self, value: V) -> Self
+    where
+        V: ToString,
+    {
+        self.tags.push((None, value.to_string()));
+        self
+    }
+
     /// Set an error handler to use for metrics sent via `MetricBuilder::send()`
     ///
     /// The error handler is only invoked when metrics are not able to be sent
@@ -408,6 +469,64 @@ impl StatsdClientBuilder {
         self
     }
 
+    /// Add an interceptor that is given each metric emitted by the client
+    /// before it is sent to the sink.
+    ///
+    /// Interceptors are run in the order they are added. Each one can rewrite,
+    /// enrich, duplicate, or drop metrics, see `MetricInterceptor` for details.
+    /// Default tags of the client are part of the metrics interceptors are given.
+    ///
+    /// When a client has interceptors, `MetricBuilder::try_send()` returns the
+    /// metric as it was created, before interceptors were run. Errors from
+    /// sending any of the metrics produced by the interceptors are returned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cadence::prelude::*;
+    /// use cadence::{MetricRecord, NopMetricSink, StatsdClient};
+    ///
+    /// let client = StatsdClient::builder("prefix", NopMetricSink)
+    ///     .with_interceptor(|mut metric: MetricRecord, next: &mut dyn FnMut(MetricRecord)| {
+    ///         metric.key = metric.key.replace("http_requests", "http.requests");
+    ///         next(metric);
+    ///     })
+    ///     .build();
+    ///
+    /// client.count("http_requests", 1);
+    /// ```
+    pub fn with_interceptor<I>(mut self, interceptor: I) -> Self
+    where
+        I: MetricInterceptor + Sync + Send + RefUnwindSafe + 'static,
+    {
+        self.interceptors.push(Box::new(interceptor));
+        self
+    }
+
+    /// Rename metrics emitted by the client according to the given rules.
+    ///
+    /// This is a shortcut for adding the rules as an interceptor, see
+    /// `RenameRules` for details.
+    pub fn with_rename_rules(self, rules: RenameRules) -> Self {
+        self.with_interceptor(rules)
+    }
+
+    /// Set whether the client starts out emitting metrics, `true` by default.
+    ///
+    /// See `StatsdClient::set_enabled()` for details.
+    pub fn with_enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    /// Set whether the client starts out in dry-run mode, `false` by default.
+    ///
+    /// See `StatsdClient::set_dry_run()` for details.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
     /// Construct a new `StatsdClient` instance based on current settings.
     pub fn build(self) -> StatsdClient {
         StatsdClient::from_builder(self)
@@ -550,6 +669,10 @@ pub struct StatsdClient {
     prefix: String,
     sink: Arc<dyn MetricSink + Sync + Send + RefUnwindSafe>,
     errors: Arc<dyn Fn(MetricError) + Sync + Send + RefUnwindSafe>,
+    tags: Vec<(Option<String>, String)>,
+    interceptors: Arc<[BoxedInterceptor]>,
+    enabled: Arc<AtomicBool>,
+    dry_run: Arc<AtomicBool>,
 }
 
 impl StatsdClient {
@@ -687,14 +810,222 @@ impl StatsdClient {
         StatsdClientBuilder::new(prefix, sink)
     }
 
+    /// Create a new builder with the provided prefix and a metric sink
+    /// configured from environment variables.
+    ///
+    /// This is meant for applications running in containers, where the
+    /// address of the Statsd server or agent is typically injected via the
+    /// environment. The following variables determine where metrics are sent:
+    ///
+    /// * `DD_DOGSTATSD_SOCKET` - Path of a Unix datagram socket to send metrics
+    ///   to (only supported on Unix platforms).
+    /// * `DD_AGENT_HOST` and `DD_DOGSTATSD_PORT` - Host and optional port to send
+    ///   metrics to over UDP.
+    /// * `STATSD_HOST` and `STATSD_PORT` - Host and optional port to send metrics
+    ///   to over UDP.
+    ///
+    /// Exactly one of the socket or the hosts above must be set. If no port is
+    /// given, `DEFAULT_PORT` is used.
+    ///
+    /// By default, metrics are buffered (using a 1432 byte buffer for UDP and an
+    /// 8192 byte buffer for Unix sockets, 2048 bytes on macOS) and sent to the
+    /// socket from a separate thread via an unbounded queue. This can be changed
+    /// with the following variables:
+    ///
+    /// * `CADENCE_BUFFER_SIZE` - Size of the buffer used to batch metrics, `0`
+    ///   to send each metric as it is emitted.
+    /// * `CADENCE_QUEUE_SIZE` - Maximum number of queued metrics, `0` to send
+    ///   metrics in the thread they are emitted in.
+    ///
+    /// Finally, the following variables become default tags of the client, when
+    /// set: `DD_ENV` (`env`), `DD_SERVICE` (`service`), `DD_VERSION` (`version`),
+    /// and `DD_ENTITY_ID` (`dd.internal.entity_id`).
+    ///
+    /// Any other customizations can be set by calling methods on the returned
+    /// builder.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use cadence::prelude::*;
+    /// use cadence::StatsdClient;
+    ///
+    /// let client = StatsdClient::from_env("some.prefix")
+    ///     .unwrap()
+    ///     .with_error_handler(|e| eprintln!("metric error: {}", e))
+    ///     .build();
+    ///
+    /// client.count("some.counter", 1);
+    /// ```
+    ///
+    /// # Failures
+    ///
+    /// This method may fail if:
+    ///
+    /// * None of the variables for the socket or hosts are set.
+    /// * The variables are inconsistent, such as both a socket and a host
+    ///   being set or a port being set without the corresponding host.
+    /// * A port or size is unable to be parsed.
+    /// * It is unable to resolve the hostname of the metric server.
+    /// * It is unable to create a local socket.
+    pub fn from_env(prefix: &str) -> MetricResult<StatsdClientBuilder> {
+        env::builder_from_env(prefix, |name| std::env::var(name).ok())
+    }
+
+    /// Create a new builder with the provided prefix and a metric sink
+    /// described by a connection URL.
+    ///
+    /// This allows the destination of metrics to be controlled by a single
+    /// configuration value. Supported URLs are:
+    ///
+    /// * `udp://host:port` - Send metrics to the host over UDP. The port is
+    ///   optional and defaults to `DEFAULT_PORT`. IPv6 addresses must be
+    ///   enclosed in brackets, e.g. `udp://[::1]:8125`.
+    /// * `tcp://host:port` - Send newline separated metrics to the host over
+    ///   TCP, reconnecting as needed. The host and port are handled the same
+    ///   as for UDP.
+    /// * `unix:///path/to/socket` - Send metrics to a Unix datagram socket
+    ///   (only supported on Unix platforms).
+    ///
+    /// The following query parameters are supported for every kind of URL:
+    ///
+    /// * `buffer=N` - Buffer metrics and send them in batches of up to `N` bytes.
+    /// * `queue=N` - Send metrics from a separate thread, queuing at most `N`
+    ///   metrics. Use `queue=unbounded` to allow any number of metrics to be
+    ///   queued.
+    ///
+    /// Without any parameters, each metric is sent to the socket immediately
+    /// in the thread it was emitted in.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use cadence::prelude::*;
+    /// use cadence::StatsdClient;
+    ///
+    /// let client = StatsdClient::from_url("some.prefix", "udp://localhost:8125?buffer=1432&queue=10000")
+    ///     .unwrap()
+    ///     .build();
+    ///
+    /// client.count("some.counter", 1);
+    /// ```
+    ///
+    /// # Failures
+    ///
+    /// This method may fail if:
+    ///
+    /// * The URL is malformed, uses an unsupported scheme, or contains unknown
+    ///   or invalid parameters.
+    /// * It is unable to resolve the hostname of the metric server.
+    /// * It is unable to create a local socket.
+    pub fn from_url(prefix: &str, url: &str) -> MetricResult<StatsdClientBuilder> {
+        let sink = SinkConfig::parse_url(url)?.build()?;
+        Ok(StatsdClientBuilder::from_boxed_sink(prefix, sink))
+    }
+
     // Create a new StatsdClient by consuming the builder
     fn from_builder(builder: StatsdClientBuilder) -> Self {
         StatsdClient {
             prefix: builder.prefix,
             sink: Arc::from(builder.sink),
             errors: Arc::from(builder.errors),
+            tags: builder.tags,
+            interceptors: Arc::from(builder.interceptors),
+            enabled: Arc::new(AtomicBool::new(builder.enabled)),
+            dry_run: Arc::new(AtomicBool::new(builder.dry_run)),
         }
     }
+
+    /// Turn emission of metrics on or off for this client and all clones
+    /// of it.
+    ///
+    /// While disabled, metrics are not sent to the sink and error handlers are
+    /// not invoked for them, including for metrics that would have been
+    /// invalid. Methods that return a result, such as `.count()` or
+    /// `.try_send()` on a `MetricBuilder`, still return the metric as if it
+    /// had been sent, while `.send()` on a `MetricBuilder` does nothing and
+    /// doesn't even format the metric. This makes emitting metrics from a
+    /// disabled client close to free, allowing it to be used as a kill switch
+    /// at runtime.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cadence::prelude::*;
+    /// use cadence::{StatsdClient, NopMetricSink};
+    ///
+    /// let client = StatsdClient::from_sink("some.prefix", NopMetricSink);
+    /// let clone = client.clone();
+    ///
+    /// client.set_enabled(false);
+    /// assert!(!clone.is_enabled());
+    /// assert!(clone.count("some.counter", 1).is_ok());
+    /// ```
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Return true if this client (and all clones of it) emit metrics.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Turn dry-run mode on or off for this client and all clones of it.
+    ///
+    /// In dry-run mode metrics are formatted and validated as usual and any
+    /// errors are reported, but metrics are never sent to the sink. This is
+    /// useful for verifying the metrics an application emits without sending
+    /// them anywhere.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cadence::prelude::*;
+    /// use cadence::{Metric, StatsdClient, NopMetricSink};
+    ///
+    /// let client = StatsdClient::builder("some.prefix", NopMetricSink)
+    ///     .with_dry_run(true)
+    ///     .build();
+    ///
+    /// assert!(client.is_dry_run());
+    /// assert_eq!(
+    ///     "some.prefix.some.counter:1|c",
+    ///     client.count("some.counter", 1).unwrap().as_metric_str()
+    /// );
+    /// ```
+    pub fn set_dry_run(&self, dry_run: bool) {
+        self.dry_run.store(dry_run, Ordering::Relaxed);
+    }
+
+    /// Return true if this client (and all clones of it) are in dry-run mode.
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn has_interceptors(&self) -> bool {
+        !self.interceptors.is_empty()
+    }
+
+    // Run a metric through each interceptor, sending every metric that makes
+    // it through all of them to the sink and returning the first error, if any.
+    pub(crate) fn send_intercepted(&self, metric: MetricRecord) -> MetricResult<()> {
+        let dry_run = self.is_dry_run();
+        let mut res = Ok(());
+
+        interceptor::run_chain(&self.interceptors, metric, &mut |m| {
+            if dry_run {
+                return;
+            }
+
+            if let Err(e) = self.sink.emit(&m.to_string()) {
+                if res.is_ok() {
+                    res = Err(MetricError::from(e));
+                }
+            }
+        });
+
+        res
+    }
 }
 
 impl MetricBackend for StatsdClient {
@@ -702,6 +1033,10 @@ impl MetricBackend for StatsdClient {
     where
         M: Metric,
     {
+        if !self.is_enabled() || self.is_dry_run() {
+            return Ok(());
+        }
+
         let metric_string = metric.as_metric_str();
         self.sink.emit(metric_string)?;
         Ok(())
@@ -716,22 +1051,26 @@ impl fmt::Debug for StatsdClient {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "StatsdClient {{ prefix: {:?}, sink: ..., errors: ... }}",
-            self.prefix
+            "StatsdClient {{ prefix: {:?}, sink: ..., errors: ..., tags: {:?}, interceptors: {}, enabled: {:?}, dry_run: {:?} }}",
+            self.prefix,
+            self.tags,
+            self.interceptors.len(),
+            self.is_enabled(),
+            self.is_dry_run()
         )
     }
 }
 
 impl Counted for StatsdClient {
     fn count_with_tags<'a>(&'a self, key: &'a str, count: i64) -> MetricBuilder<'_, '_, Counter> {
-        let fmt = MetricFormatter::counter(&self.prefix, key, count);
+        let fmt = MetricFormatter::counter(&self.prefix, key, count).with_base_tags(&self.tags);
         MetricBuilder::new(fmt, self)
     }
 }
 
 impl Timed for StatsdClient {
     fn time_with_tags<'a>(&'a self, key: &'a str, time: u64) -> MetricBuilder<'_, '_, Timer> {
-        let fmt = MetricFormatter::timer(&self.prefix, key, time);
+        let fmt = MetricFormatter::timer(&self.prefix, key, time).with_base_tags(&self.tags);
         MetricBuilder::new(fmt, self)
     }
 
@@ -747,26 +1086,26 @@ impl Timed for StatsdClient {
 
 impl Gauged for StatsdClient {
     fn gauge_with_tags<'a>(&'a self, key: &'a str, value: u64) -> MetricBuilder<'_, '_, Gauge> {
-        let fmt = MetricFormatter::gauge(&self.prefix, key, value);
+        let fmt = MetricFormatter::gauge(&self.prefix, key, value).with_base_tags(&self.tags);
         MetricBuilder::new(fmt, self)
     }
 
     fn gauge_f64_with_tags<'a>(&'a self, key: &'a str, value: f64) -> MetricBuilder<'_, '_, Gauge> {
-        let fmt = MetricFormatter::gauge_f64(&self.prefix, key, value);
+        let fmt = MetricFormatter::gauge_f64(&self.prefix, key, value).with_base_tags(&self.tags);
         MetricBuilder::new(fmt, self)
     }
 }
 
 impl Metered for StatsdClient {
     fn meter_with_tags<'a>(&'a self, key: &'a str, value: u64) -> MetricBuilder<'_, '_, Meter> {
-        let fmt = MetricFormatter::meter(&self.prefix, key, value);
+        let fmt = MetricFormatter::meter(&self.prefix, key, value).with_base_tags(&self.tags);
         MetricBuilder::new(fmt, self)
     }
 }
 
 impl Histogrammed for StatsdClient {
     fn histogram_with_tags<'a>(&'a self, key: &'a str, value: u64) -> MetricBuilder<'_, '_, Histogram> {
-        let fmt = MetricFormatter::histogram(&self.prefix, key, value);
+        let fmt = MetricFormatter::histogram(&self.prefix, key, value).with_base_tags(&self.tags);
         MetricBuilder::new(fmt, self)
     }
 
@@ -786,7 +1125,7 @@ impl Histogrammed for StatsdClient {
 
 impl Setted for StatsdClient {
     fn set_with_tags<'a>(&'a self, key: &'a str, value: i64) -> MetricBuilder<'_, '_, Set> {
-        let fmt = MetricFormatter::set(&self.prefix, key, value);
+        let fmt = MetricFormatter::set(&self.prefix, key, value).with_base_tags(&self.tags);
         MetricBuilder::new(fmt, self)
     }
 }
@@ -801,8 +1140,8 @@ fn nop_error_handler(_err: MetricError) {
 #[cfg(test)]
 mod tests {
     use super::{Counted, Gauged, Histogrammed, Metered, MetricClient, Setted, StatsdClient, Timed};
-    use crate::sinks::{MetricSink, NopMetricSink, QueuingMetricSink};
-    use crate::types::{ErrorKind, Metric, MetricError};
+    use crate::sinks::{BufferedSpyMetricSink, MetricSink, NopMetricSink, QueuingMetricSink};
+    use crate::types::{ErrorKind, Metric, MetricError, MetricRecord};
     use std::cell::RefCell;
     use std::io;
     use std::panic::RefUnwindSafe;
@@ -1028,6 +1367,167 @@ mod tests {
         assert_eq!(1, count.load(Ordering::Acquire));
     }
 
+    #[test]
+    fn test_statsd_client_with_default_tags() {
+        let client = StatsdClient::builder("prefix", NopMetricSink)
+            .with_tag("env", "prod")
+            .with_tag_value("canary")
+            .build();
+
+        let res = client.count("some.counter", 1);
+        assert_eq!("prefix.some.counter:1|c|#env:prod,canary", res.unwrap().as_metric_str());
+
+        let res = client
+            .time_with_tags("some.timer", 21)
+            .with_tag("host", "web")
+            .try_send();
+        assert_eq!(
+            "prefix.some.timer:21|ms|#host:web,env:prod,canary",
+            res.unwrap().as_metric_str()
+        );
+    }
+
+    #[test]
+    fn test_statsd_client_disabled() {
+        struct ErrorSink;
+
+        impl MetricSink for ErrorSink {
+            fn emit(&self, _metric: &str) -> io::Result<usize> {
+                Err(io::Error::from(io::ErrorKind::Other))
+            }
+        }
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let count_ref = Arc::clone(&count);
+
+        let handler = move |_err: MetricError| {
+            count_ref.fetch_add(1, Ordering::Release);
+        };
+
+        let client = StatsdClient::builder("prefix", ErrorSink)
+            .with_error_handler(handler)
+            .with_enabled(false)
+            .build();
+        let clone = client.clone();
+
+        // Metrics are returned as usual but the failing sink isn't used
+        assert_eq!(
+            "prefix.some.counter:1|c",
+            client.count("some.counter", 1).unwrap().as_metric_str()
+        );
+        clone.incr_with_tags("some.key").with_tag("tier", "web").send();
+        // Invalid metrics aren't reported while disabled either
+        clone
+            .time_duration_with_tags("some.timer", Duration::from_secs(u64::MAX))
+            .send();
+        assert_eq!(0, count.load(Ordering::Acquire));
+
+        clone.set_enabled(true);
+        assert!(client.is_enabled());
+        assert!(client.count("some.counter", 1).is_err());
+    }
+
+    #[test]
+    fn test_statsd_client_dry_run() {
+        struct ErrorSink;
+
+        impl MetricSink for ErrorSink {
+            fn emit(&self, _metric: &str) -> io::Result<usize> {
+                Err(io::Error::from(io::ErrorKind::Other))
+            }
+        }
+
+        let client = StatsdClient::builder("prefix", ErrorSink).with_dry_run(true).build();
+
+        let res = client
+            .count_with_tags("some.counter", 1)
+            .with_tag("tier", "web")
+            .try_send();
+        assert_eq!("prefix.some.counter:1|c|#tier:web", res.unwrap().as_metric_str());
+
+        let res = client.time_duration("some.timer", Duration::from_secs(u64::MAX));
+        assert_eq!(ErrorKind::InvalidInput, res.unwrap_err().kind());
+
+        client.clone().set_dry_run(false);
+        assert!(!client.is_dry_run());
+        assert!(client.count("some.counter", 1).is_err());
+    }
+
+    #[test]
+    fn test_statsd_client_with_interceptors() {
+        let writer = Arc::new(Mutex::new(Vec::new()));
+        let client = StatsdClient::builder("prefix", BufferedSpyMetricSink::with_capacity(writer.clone(), 1024))
+            .with_tag("env", "prod")
+            .with_interceptor(|metric: MetricRecord, next: &mut dyn FnMut(MetricRecord)| {
+                if !metric.key.ends_with(".dropped") {
+                    next(metric);
+                }
+            })
+            .with_interceptor(|mut metric: MetricRecord, next: &mut dyn FnMut(MetricRecord)| {
+                let mut copy = metric.clone();
+                copy.key = copy.key.replace("old", "new");
+                metric.tags.push((None, "deprecated".to_string()));
+                next(metric);
+                next(copy);
+            })
+            .build();
+
+        let res = client
+            .count_with_tags("old.counter", 1)
+            .with_tag("tier", "web")
+            .try_send();
+        assert_eq!(
+            "prefix.old.counter:1|c|#tier:web,env:prod",
+            res.unwrap().as_metric_str()
+        );
+        client.count("old.dropped", 1).unwrap();
+
+        // Drop the client (and the sink with it) to flush the buffer
+        drop(client);
+
+        assert_eq!(
+            concat!(
+                "prefix.old.counter:1|c|#tier:web,env:prod,deprecated\n",
+                "prefix.new.counter:1|c|#tier:web,env:prod\n"
+            ),
+            String::from_utf8(writer.lock().unwrap().clone()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_statsd_client_with_interceptors_send_error() {
+        struct ErrorSink;
+
+        impl MetricSink for ErrorSink {
+            fn emit(&self, _metric: &str) -> io::Result<usize> {
+                Err(io::Error::from(io::ErrorKind::Other))
+            }
+        }
+
+        let client = StatsdClient::builder("prefix", ErrorSink)
+            .with_interceptor(|metric: MetricRecord, next: &mut dyn FnMut(MetricRecord)| next(metric))
+            .build();
+
+        assert_eq!(ErrorKind::IoError, client.count("some.counter", 1).unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_statsd_client_from_url() {
+        let client = StatsdClient::from_url("prefix", "udp://127.0.0.1:8125?buffer=64")
+            .unwrap()
+            .with_tag("env", "prod")
+            .build();
+
+        let res = client.count("some.counter", 1);
+        assert_eq!("prefix.some.counter:1|c|#env:prod", res.unwrap().as_metric_str());
+    }
+
+    #[test]
+    fn test_statsd_client_from_url_unsupported_scheme() {
+        let res = StatsdClient::from_url("prefix", "http://127.0.0.1:8125");
+        assert_eq!(ErrorKind::InvalidInput, res.err().unwrap().kind());
+    }
+
     #[test]
     fn test_statsd_client_set_with_tags() {
         let client = StatsdClient::from_sink("myapp", NopMetricSink);
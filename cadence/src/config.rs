@@ -0,0 +1,357 @@
+// Cadence - An extensible Statsd client for Rust!
+//
+// Copyright 2021 Nick Pillitteri
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::sinks::{
+    BufferedTcpMetricSink, BufferedUdpMetricSink, MetricSink, QueuingMetricSink, TcpMetricSink, UdpMetricSink,
+    DEFAULT_TCP_BUFFER_SIZE, DEFAULT_UDP_BUFFER_SIZE,
+};
+use crate::types::{ErrorKind, MetricError, MetricResult};
+use crate::DEFAULT_PORT;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::panic::RefUnwindSafe;
+
+#[cfg(unix)]
+use crate::sinks::{BufferedUnixMetricSink, UnixMetricSink, DEFAULT_UNIX_BUFFER_SIZE};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+
+pub(crate) type BoxedSink = Box<dyn MetricSink + Sync + Send + RefUnwindSafe>;
+
+/// Where metrics should be sent
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Transport {
+    Udp(String, u16),
+    Tcp(String, u16),
+    Unix(String),
+}
+
+impl Transport {
+    /// Size of the buffer used by the buffered sink for this transport when
+    /// no size is given, the same as the default of the sink's constructors.
+    pub(crate) fn default_buffer_size(&self) -> usize {
+        match self {
+            Transport::Udp(..) => DEFAULT_UDP_BUFFER_SIZE,
+            Transport::Tcp(..) => DEFAULT_TCP_BUFFER_SIZE,
+            #[cfg(unix)]
+            Transport::Unix(..) => DEFAULT_UNIX_BUFFER_SIZE,
+            // Creating the sink fails later on, the size doesn't matter
+            #[cfg(not(unix))]
+            Transport::Unix(..) => DEFAULT_UDP_BUFFER_SIZE,
+        }
+    }
+}
+
+/// Whether metrics should be sent from a separate thread and if so,
+/// how many of them may be queued.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Queue {
+    Disabled,
+    Bounded(usize),
+    Unbounded,
+}
+
+/// Description of a stack of sinks: a sink for a particular transport,
+/// optionally buffered, optionally wrapped by a `QueuingMetricSink`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SinkConfig {
+    pub(crate) transport: Transport,
+    pub(crate) buffer: Option<usize>,
+    pub(crate) queue: Queue,
+}
+
+impl SinkConfig {
+    /// Parse a URL such as `udp://host:8125?buffer=1432&queue=10000`,
+    /// `tcp://host:8125`, or `unix:///var/run/datadog/dsd.socket` into a
+    /// sink configuration.
+    pub(crate) fn parse_url(url: &str) -> MetricResult<SinkConfig> {
+        let (scheme, rest) = match url.find("://") {
+            Some(i) => (&url[..i], &url[i + 3..]),
+            None => return Err(invalid("URL is missing a scheme such as udp://, tcp://, or unix://")),
+        };
+
+        let (location, query) = match rest.find('?') {
+            Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+            None => (rest, None),
+        };
+
+        let transport = match scheme {
+            "udp" => {
+                let (host, port) = parse_host_port(location)?;
+                Transport::Udp(host, port)
+            }
+            "unix" => {
+                if location.is_empty() {
+                    return Err(invalid("unix:// URL is missing a socket path"));
+                }
+                Transport::Unix(location.to_string())
+            }
+            "tcp" => {
+                let (host, port) = parse_host_port(location)?;
+                Transport::Tcp(host, port)
+            }
+            _ => return Err(invalid("Unsupported URL scheme, expected udp://, tcp://, or unix://")),
+        };
+
+        let mut config = SinkConfig {
+            transport,
+            buffer: None,
+            queue: Queue::Disabled,
+        };
+
+        for param in query.into_iter().flat_map(|q| q.split('&')).filter(|p| !p.is_empty()) {
+            let (key, value) = match param.find('=') {
+                Some(i) => (&param[..i], &param[i + 1..]),
+                None => (param, ""),
+            };
+
+            match key {
+                "buffer" => config.buffer = Some(parse_size(value, "URL buffer size must be a positive integer")?),
+                "queue" if value == "unbounded" => config.queue = Queue::Unbounded,
+                "queue" => {
+                    let size = parse_size(value, "URL queue size must be a positive integer or 'unbounded'")?;
+                    config.queue = Queue::Bounded(size);
+                }
+                _ => return Err(invalid("Unknown URL parameter, expected buffer or queue")),
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Create the sockets and sinks described by this configuration.
+    pub(crate) fn build(&self) -> MetricResult<BoxedSink> {
+        let sink = match self.transport {
+            Transport::Udp(ref host, port) => new_udp_sink(host, port, self.buffer)?,
+            Transport::Tcp(ref host, port) => new_tcp_sink(host, port, self.buffer)?,
+            Transport::Unix(ref path) => new_unix_sink(path, self.buffer)?,
+        };
+
+        Ok(match self.queue {
+            Queue::Disabled => sink,
+            Queue::Bounded(v) => Box::new(QueuingMetricSink::with_capacity(sink, v)),
+            Queue::Unbounded => Box::new(QueuingMetricSink::from(sink)),
+        })
+    }
+}
+
+fn parse_host_port(location: &str) -> MetricResult<(String, u16)> {
+    // IPv6 addresses must be enclosed in brackets when a port is given,
+    // the same as they would be in any other URL.
+    let (host, port) = if let Some(stripped) = location.strip_prefix('[') {
+        let (host, rest) = match stripped.find(']') {
+            Some(i) => (&stripped[..i], &stripped[i + 1..]),
+            None => return Err(invalid("URL has an unterminated IPv6 address")),
+        };
+
+        match rest.strip_prefix(':') {
+            Some(port) => (host, Some(port)),
+            None if rest.is_empty() => (host, None),
+            None => return Err(invalid("URL has unexpected characters after an IPv6 address")),
+        }
+    } else {
+        let (host, port) = match location.find(':') {
+            Some(i) => (&location[..i], Some(&location[i + 1..])),
+            None => (location, None),
+        };
+
+        // Without brackets there's no telling where an IPv6 address ends and
+        // the port begins, so refuse to guess.
+        if port.map(|p| p.contains(':')).unwrap_or(false) {
+            return Err(invalid(
+                "URL IPv6 addresses must be enclosed in brackets, such as [::1]:8125",
+            ));
+        }
+
+        (host, port)
+    };
+
+    if host.is_empty() {
+        return Err(invalid("URL is missing a host"));
+    }
+
+    let port = match port {
+        Some(p) => p.parse().map_err(|_| invalid("URL port is not a valid port"))?,
+        None => DEFAULT_PORT,
+    };
+
+    Ok((host.to_string(), port))
+}
+
+fn parse_size(value: &str, err: &'static str) -> MetricResult<usize> {
+    match value.parse() {
+        Ok(v) if v > 0 => Ok(v),
+        _ => Err(invalid(err)),
+    }
+}
+
+fn new_udp_sink(host: &str, port: u16, buffer: Option<usize>) -> MetricResult<BoxedSink> {
+    let addr = resolve(host, port)?;
+    let socket = if addr.is_ipv4() {
+        UdpSocket::bind("0.0.0.0:0")?
+    } else {
+        UdpSocket::bind("[::]:0")?
+    };
+    socket.set_nonblocking(true)?;
+
+    Ok(match buffer {
+        Some(cap) => Box::new(BufferedUdpMetricSink::with_capacity(addr, socket, cap)?),
+        None => Box::new(UdpMetricSink::from(addr, socket)?),
+    })
+}
+
+fn new_tcp_sink(host: &str, port: u16, buffer: Option<usize>) -> MetricResult<BoxedSink> {
+    let addr = resolve(host, port)?;
+
+    Ok(match buffer {
+        Some(cap) => Box::new(BufferedTcpMetricSink::with_capacity(addr, cap)?),
+        None => Box::new(TcpMetricSink::from(addr)?),
+    })
+}
+
+fn resolve(host: &str, port: u16) -> MetricResult<SocketAddr> {
+    match (host, port).to_socket_addrs()?.next() {
+        Some(addr) => Ok(addr),
+        None => Err(invalid("No socket addresses yielded for the Statsd host")),
+    }
+}
+
+#[cfg(unix)]
+fn new_unix_sink(path: &str, buffer: Option<usize>) -> MetricResult<BoxedSink> {
+    let socket = UnixDatagram::unbound()?;
+    socket.set_nonblocking(true)?;
+
+    Ok(match buffer {
+        Some(cap) => Box::new(BufferedUnixMetricSink::with_capacity(path, socket, cap)),
+        None => Box::new(UnixMetricSink::from(path, socket)),
+    })
+}
+
+#[cfg(not(unix))]
+fn new_unix_sink(_path: &str, _buffer: Option<usize>) -> MetricResult<BoxedSink> {
+    Err(invalid("Unix sockets are only supported on Unix platforms"))
+}
+
+pub(crate) fn invalid(desc: &'static str) -> MetricError {
+    MetricError::from((ErrorKind::InvalidInput, desc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Queue, SinkConfig, Transport};
+    use crate::sinks::MetricSink;
+    use crate::types::ErrorKind;
+
+    fn assert_invalid(url: &str) {
+        let res = SinkConfig::parse_url(url);
+        assert_eq!(ErrorKind::InvalidInput, res.unwrap_err().kind(), "url: {}", url);
+    }
+
+    #[test]
+    fn test_parse_url_udp() {
+        let config = SinkConfig::parse_url("udp://metrics.example.com:9125").unwrap();
+
+        assert_eq!(
+            Transport::Udp("metrics.example.com".to_string(), 9125),
+            config.transport
+        );
+        assert_eq!(None, config.buffer);
+        assert_eq!(Queue::Disabled, config.queue);
+    }
+
+    #[test]
+    fn test_parse_url_udp_default_port() {
+        let config = SinkConfig::parse_url("udp://localhost").unwrap();
+        assert_eq!(Transport::Udp("localhost".to_string(), 8125), config.transport);
+    }
+
+    #[test]
+    fn test_parse_url_udp_ipv6() {
+        let config = SinkConfig::parse_url("udp://[::1]:8126").unwrap();
+        assert_eq!(Transport::Udp("::1".to_string(), 8126), config.transport);
+
+        let config = SinkConfig::parse_url("udp://[::1]").unwrap();
+        assert_eq!(Transport::Udp("::1".to_string(), 8125), config.transport);
+    }
+
+    #[test]
+    fn test_parse_url_ipv6_without_brackets() {
+        assert_invalid("udp://::1");
+        assert_invalid("udp://::1:8125");
+        assert_invalid("tcp://fe80::1:8125");
+        assert_invalid("udp://[::1]8125");
+    }
+
+    #[test]
+    fn test_parse_url_tcp_default_port() {
+        let config = SinkConfig::parse_url("tcp://host:8125").unwrap();
+        assert_eq!(Transport::Tcp("host".to_string(), 8125), config.transport);
+
+        let config = SinkConfig::parse_url("tcp://[::1]").unwrap();
+        assert_eq!(Transport::Tcp("::1".to_string(), 8125), config.transport);
+    }
+
+    #[test]
+    fn test_parse_url_udp_buffer_and_queue() {
+        let config = SinkConfig::parse_url("udp://localhost:8125?buffer=1432&queue=10000").unwrap();
+
+        assert_eq!(Some(1432), config.buffer);
+        assert_eq!(Queue::Bounded(10000), config.queue);
+    }
+
+    #[test]
+    fn test_parse_url_unbounded_queue() {
+        let config = SinkConfig::parse_url("udp://localhost?queue=unbounded").unwrap();
+        assert_eq!(Queue::Unbounded, config.queue);
+    }
+
+    #[test]
+    fn test_parse_url_tcp() {
+        let config = SinkConfig::parse_url("tcp://relay.example.com:9125?buffer=8192&queue=unbounded").unwrap();
+
+        assert_eq!(Transport::Tcp("relay.example.com".to_string(), 9125), config.transport);
+        assert_eq!(Some(8192), config.buffer);
+        assert_eq!(Queue::Unbounded, config.queue);
+    }
+
+    #[test]
+    fn test_parse_url_unix() {
+        let config = SinkConfig::parse_url("unix:///var/run/datadog/dsd.socket?buffer=8192").unwrap();
+
+        assert_eq!(
+            Transport::Unix("/var/run/datadog/dsd.socket".to_string()),
+            config.transport
+        );
+        assert_eq!(Some(8192), config.buffer);
+    }
+
+    #[test]
+    fn test_parse_url_invalid() {
+        assert_invalid("localhost:8125");
+        assert_invalid("http://localhost:8125");
+        assert_invalid("udp://");
+        assert_invalid("udp://:8125");
+        assert_invalid("udp://localhost:http");
+        assert_invalid("udp://localhost:99999");
+        assert_invalid("udp://[::1:8125");
+        assert_invalid("udp://localhost?buffer=0");
+        assert_invalid("udp://localhost?buffer=big");
+        assert_invalid("udp://localhost?queue=-1");
+        assert_invalid("udp://localhost?bufer=512");
+        assert_invalid("unix://");
+    }
+
+    #[test]
+    fn test_build_udp() {
+        let config = SinkConfig::parse_url("udp://127.0.0.1:8125?buffer=64&queue=16").unwrap();
+        let sink = config.build().unwrap();
+
+        assert_eq!(8, sink.emit("foo:54|c").unwrap());
+    }
+}
@@ -0,0 +1,262 @@
+// Cadence - An extensible Statsd client for Rust!
+//
+// Copyright 2021 Nick Pillitteri
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::client::StatsdClientBuilder;
+use crate::config::{invalid, Queue, SinkConfig, Transport};
+use crate::types::MetricResult;
+use crate::DEFAULT_PORT;
+
+// Environment variables that are turned into default tags and the
+// name of the tag each of them becomes, per the DogStatsD conventions.
+const TAG_VARS: &[(&str, &str)] = &[
+    ("DD_ENV", "env"),
+    ("DD_SERVICE", "service"),
+    ("DD_VERSION", "version"),
+    ("DD_ENTITY_ID", "dd.internal.entity_id"),
+];
+
+/// Create a builder for a client based on the environment, looking up
+/// variables using the provided function.
+///
+/// This is separate from `StatsdClient::from_env` so that tests don't
+/// need to modify the environment of the process they are running in.
+pub(crate) fn builder_from_env<F>(prefix: &str, lookup: F) -> MetricResult<StatsdClientBuilder>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let var = non_empty(lookup);
+    let transport = get_transport(&var)?;
+    let buffer = get_size(&var, "CADENCE_BUFFER_SIZE", "CADENCE_BUFFER_SIZE is not a valid size")?;
+    let queue = get_size(&var, "CADENCE_QUEUE_SIZE", "CADENCE_QUEUE_SIZE is not a valid size")?;
+
+    let buffer = match buffer {
+        Some(0) => None,
+        Some(v) => Some(v),
+        None => Some(transport.default_buffer_size()),
+    };
+
+    let config = SinkConfig {
+        transport,
+        buffer,
+        queue: match queue {
+            Some(0) => Queue::Disabled,
+            Some(v) => Queue::Bounded(v),
+            None => Queue::Unbounded,
+        },
+    };
+
+    let mut builder = StatsdClientBuilder::from_boxed_sink(prefix, config.build()?);
+    for (name, tag) in TAG_VARS {
+        if let Some(v) = var(name) {
+            builder = builder.with_tag(tag, v);
+        }
+    }
+
+    Ok(builder)
+}
+
+/// Treat empty variables the same as unset variables since orchestration
+/// tools will often set a variable to an empty string instead of omitting it.
+fn non_empty<F>(lookup: F) -> impl Fn(&str) -> Option<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    move |name: &str| lookup(name).filter(|v| !v.is_empty())
+}
+
+fn get_transport<F>(var: &F) -> MetricResult<Transport>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let socket = var("DD_DOGSTATSD_SOCKET");
+    let dd_host = var("DD_AGENT_HOST");
+    let dd_port = var("DD_DOGSTATSD_PORT");
+    let statsd_host = var("STATSD_HOST");
+    let statsd_port = var("STATSD_PORT");
+
+    if let Some(path) = socket {
+        if dd_host.is_some() || statsd_host.is_some() {
+            return Err(invalid(
+                "DD_DOGSTATSD_SOCKET cannot be combined with DD_AGENT_HOST or STATSD_HOST",
+            ));
+        }
+
+        if dd_port.is_some() || statsd_port.is_some() {
+            return Err(invalid(
+                "DD_DOGSTATSD_SOCKET cannot be combined with DD_DOGSTATSD_PORT or STATSD_PORT",
+            ));
+        }
+
+        return Ok(Transport::Unix(path));
+    }
+
+    if dd_host.is_some() && statsd_host.is_some() {
+        return Err(invalid("DD_AGENT_HOST and STATSD_HOST cannot both be set"));
+    }
+
+    if dd_port.is_some() && dd_host.is_none() {
+        return Err(invalid("DD_DOGSTATSD_PORT is set but DD_AGENT_HOST is not"));
+    }
+
+    if statsd_port.is_some() && statsd_host.is_none() {
+        return Err(invalid("STATSD_PORT is set but STATSD_HOST is not"));
+    }
+
+    if let Some(host) = dd_host {
+        let port = parse_port(dd_port, "DD_DOGSTATSD_PORT is not a valid port")?;
+        Ok(Transport::Udp(host, port))
+    } else if let Some(host) = statsd_host {
+        let port = parse_port(statsd_port, "STATSD_PORT is not a valid port")?;
+        Ok(Transport::Udp(host, port))
+    } else {
+        Err(invalid(
+            "No Statsd server configured: set DD_DOGSTATSD_SOCKET, DD_AGENT_HOST, or STATSD_HOST",
+        ))
+    }
+}
+
+fn parse_port(port: Option<String>, err: &'static str) -> MetricResult<u16> {
+    match port {
+        Some(v) => v.trim().parse().map_err(|_| invalid(err)),
+        None => Ok(DEFAULT_PORT),
+    }
+}
+
+fn get_size<F>(var: &F, name: &str, err: &'static str) -> MetricResult<Option<usize>>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    match var(name) {
+        Some(v) => v.trim().parse().map(Some).map_err(|_| invalid(err)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{builder_from_env, get_transport, non_empty, Transport};
+    use crate::types::{ErrorKind, Metric};
+    use crate::Counted;
+    use std::collections::HashMap;
+
+    fn lookup(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        move |name: &str| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn test_transport_dogstatsd_host() {
+        let var = lookup(&[("DD_AGENT_HOST", "127.0.0.1"), ("DD_DOGSTATSD_PORT", "9125")]);
+        assert_eq!(
+            Transport::Udp("127.0.0.1".to_string(), 9125),
+            get_transport(&var).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_transport_statsd_host_default_port() {
+        let var = lookup(&[("STATSD_HOST", "localhost")]);
+        assert_eq!(
+            Transport::Udp("localhost".to_string(), 8125),
+            get_transport(&var).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_transport_socket() {
+        let var = lookup(&[("DD_DOGSTATSD_SOCKET", "/var/run/datadog/dsd.socket")]);
+        assert_eq!(
+            Transport::Unix("/var/run/datadog/dsd.socket".to_string()),
+            get_transport(&var).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_transport_empty_vars_ignored() {
+        let var = non_empty(lookup(&[("DD_DOGSTATSD_SOCKET", ""), ("STATSD_HOST", "localhost")]));
+        assert_eq!(
+            Transport::Udp("localhost".to_string(), 8125),
+            get_transport(&var).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_transport_socket_and_host() {
+        let var = lookup(&[
+            ("DD_DOGSTATSD_SOCKET", "/tmp/dsd.socket"),
+            ("DD_AGENT_HOST", "localhost"),
+        ]);
+        assert_eq!(ErrorKind::InvalidInput, get_transport(&var).unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_transport_socket_and_port() {
+        let var = lookup(&[
+            ("DD_DOGSTATSD_SOCKET", "/tmp/dsd.socket"),
+            ("DD_DOGSTATSD_PORT", "8125"),
+        ]);
+        assert_eq!(ErrorKind::InvalidInput, get_transport(&var).unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_transport_both_hosts() {
+        let var = lookup(&[("DD_AGENT_HOST", "localhost"), ("STATSD_HOST", "localhost")]);
+        assert_eq!(ErrorKind::InvalidInput, get_transport(&var).unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_transport_port_without_host() {
+        let var = lookup(&[("STATSD_PORT", "8125")]);
+        assert_eq!(ErrorKind::InvalidInput, get_transport(&var).unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_transport_invalid_port() {
+        let var = lookup(&[("DD_AGENT_HOST", "localhost"), ("DD_DOGSTATSD_PORT", "eighty")]);
+        let err = get_transport(&var).unwrap_err();
+        assert_eq!(ErrorKind::InvalidInput, err.kind());
+        assert_eq!("DD_DOGSTATSD_PORT is not a valid port", err.to_string());
+    }
+
+    #[test]
+    fn test_transport_nothing_set() {
+        let var = lookup(&[]);
+        assert_eq!(ErrorKind::InvalidInput, get_transport(&var).unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_builder_from_env_invalid_buffer_size() {
+        let var = lookup(&[("STATSD_HOST", "127.0.0.1"), ("CADENCE_BUFFER_SIZE", "big")]);
+        let res = builder_from_env("prefix", var);
+
+        assert_eq!(ErrorKind::InvalidInput, res.err().unwrap().kind());
+    }
+
+    #[test]
+    fn test_builder_from_env_tags() {
+        let var = lookup(&[
+            ("STATSD_HOST", "127.0.0.1"),
+            ("CADENCE_BUFFER_SIZE", "0"),
+            ("CADENCE_QUEUE_SIZE", "0"),
+            ("DD_ENV", "prod"),
+            ("DD_SERVICE", "web"),
+            ("DD_VERSION", ""),
+            ("DD_ENTITY_ID", "abc123"),
+        ]);
+
+        let client = builder_from_env("prefix", var).unwrap().build();
+        let res = client.incr("some.counter");
+
+        assert_eq!(
+            "prefix.some.counter:1|c|#env:prod,service:web,dd.internal.entity_id:abc123",
+            res.unwrap().as_metric_str()
+        );
+    }
+}
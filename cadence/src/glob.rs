@@ -0,0 +1,131 @@
+// Cadence - An extensible Statsd client for Rust!
+//
+// Copyright 2021 Nick Pillitteri
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+/// Return true if the text matches the glob pattern.
+///
+/// Patterns support `*` to match any sequence of characters (including
+/// none) and `?` to match exactly one character. All other characters
+/// match themselves.
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    wildcard_ranges(pattern, text).is_some()
+}
+
+/// Return the parts of the text matched by each wildcard (`*` or `?`) of
+/// the glob pattern, in order, or `None` if the text doesn't match.
+///
+/// Each `*` matches as little of the text as possible while still allowing
+/// the rest of the pattern to match.
+pub(crate) fn glob_captures<'t>(pattern: &str, text: &'t str) -> Option<Vec<&'t str>> {
+    wildcard_ranges(pattern, text).map(|ranges| ranges.into_iter().map(|(start, end)| &text[start..end]).collect())
+}
+
+/// Match the text against the glob pattern, returning the byte range of the
+/// text matched by each wildcard of the pattern.
+///
+/// Only the most recent `*` is ever extended on a mismatch, so matching takes
+/// at most `O(pattern * text)` steps no matter how many `*` the pattern has.
+fn wildcard_ranges(pattern: &str, text: &str) -> Option<Vec<(usize, usize)>> {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let offset = |t: usize| chars.get(t).map(|&(i, _)| i).unwrap_or_else(|| text.len());
+
+    let (mut p, mut t) = (0, 0);
+    let mut ranges = Vec::new();
+    // Position of the last `*` seen in the pattern, the position in the text
+    // it has been matched up to, and the index of its range, used to backtrack
+    // on a mismatch.
+    let mut star: Option<(usize, usize, usize)> = None;
+
+    while t < chars.len() {
+        if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t, ranges.len()));
+            ranges.push((offset(t), offset(t)));
+            p += 1;
+        } else if p < pattern.len() && (pattern[p] == '?' || pattern[p] == chars[t].1) {
+            if pattern[p] == '?' {
+                ranges.push((offset(t), offset(t + 1)));
+            }
+            p += 1;
+            t += 1;
+        } else if let Some((sp, st, slot)) = star {
+            ranges.truncate(slot + 1);
+            ranges[slot].1 = offset(st + 1);
+            p = sp + 1;
+            t = st + 1;
+            star = Some((sp, st + 1, slot));
+        } else {
+            return None;
+        }
+    }
+
+    for &c in &pattern[p..] {
+        if c != '*' {
+            return None;
+        }
+        ranges.push((text.len(), text.len()));
+    }
+
+    Some(ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{glob_captures, glob_match};
+
+    #[test]
+    fn test_glob_match_literal() {
+        assert!(glob_match("some.key", "some.key"));
+        assert!(!glob_match("some.key", "some.key2"));
+        assert!(!glob_match("some.key", "some"));
+    }
+
+    #[test]
+    fn test_glob_match_star() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("lib.*", "lib.requests.count"));
+        assert!(glob_match("lib.*.count", "lib.requests.count"));
+        assert!(glob_match("*.count", "lib.a.count"));
+        assert!(glob_match("a*b*c", "aXXbYYbZc"));
+        assert!(!glob_match("lib.*.count", "lib.requests.timer"));
+        assert!(!glob_match("a*b*c", "aXXbYY"));
+    }
+
+    #[test]
+    fn test_glob_match_question_mark() {
+        assert!(glob_match("shard.?", "shard.1"));
+        assert!(!glob_match("shard.?", "shard.10"));
+        assert!(!glob_match("shard.?", "shard."));
+    }
+
+    #[test]
+    fn test_glob_captures() {
+        assert_eq!(Some(vec![]), glob_captures("a.b", "a.b"));
+        assert_eq!(
+            Some(vec!["requests"]),
+            glob_captures("lib.*.count", "lib.requests.count")
+        );
+        assert_eq!(Some(vec!["a", "b.c"]), glob_captures("*.*", "a.b.c"));
+        assert_eq!(Some(vec!["1", "x"]), glob_captures("shard.?.*", "shard.1.x"));
+        assert_eq!(Some(vec![""]), glob_captures("lib.*", "lib."));
+        assert_eq!(None, glob_captures("lib.*.count", "lib.requests.timer"));
+        assert_eq!(Some(vec!["é", "ü"]), glob_captures("?.*", "é.ü"));
+        assert_eq!(Some(vec!["a", "", "b"]), glob_captures("*.**", "a.b"));
+    }
+
+    #[test]
+    fn test_glob_captures_many_stars() {
+        // Would take exponential time with a backtracking matcher
+        let pattern = "*a*a*a*a*a*a*a*a*a*a*b";
+        let text = "a".repeat(64);
+        assert_eq!(None, glob_captures(pattern, &text));
+        assert!(!glob_match(pattern, &text));
+    }
+}
@@ -0,0 +1,141 @@
+// Cadence - An extensible Statsd client for Rust!
+//
+// Copyright 2021 Nick Pillitteri
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::types::MetricRecord;
+use std::panic::RefUnwindSafe;
+
+/// Trait for inspecting and modifying metrics before they are turned into
+/// strings and sent to a `MetricSink`.
+///
+/// Interceptors are registered with a `StatsdClientBuilder` and form a chain:
+/// each interceptor is given a metric along with the rest of the chain as
+/// `next`. Passing a (possibly modified) metric to `next` sends it on to the
+/// following interceptor and eventually the sink. An interceptor may call
+/// `next` once to rewrite or enrich a metric, more than once to duplicate
+/// it, or not at all to drop it.
+///
+/// Interceptors only see metrics created by the methods of `StatsdClient`,
+/// not metrics sent directly via `MetricBackend::send_metric()`.
+///
+/// This trait is implemented for closures with a matching signature.
+///
+/// # Example
+///
+/// ```
+/// use cadence::prelude::*;
+/// use cadence::{MetricInterceptor, MetricRecord, NopMetricSink, StatsdClient};
+///
+/// // Drop metrics for debugging and tag everything else with the region
+/// struct RegionInterceptor;
+///
+/// impl MetricInterceptor for RegionInterceptor {
+///     fn intercept(&self, mut metric: MetricRecord, next: &mut dyn FnMut(MetricRecord)) {
+///         if !metric.key.contains(".debug.") {
+///             metric.tags.push((Some("region".to_string()), "us-east-2".to_string()));
+///             next(metric);
+///         }
+///     }
+/// }
+///
+/// let client = StatsdClient::builder("some.prefix", NopMetricSink)
+///     .with_interceptor(RegionInterceptor)
+///     .with_interceptor(|metric: MetricRecord, next: &mut dyn FnMut(MetricRecord)| {
+///         next(metric.clone());
+///         next(metric);
+///     })
+///     .build();
+///
+/// client.count("some.counter", 1);
+/// ```
+pub trait MetricInterceptor {
+    /// Inspect or modify a metric before it is sent.
+    ///
+    /// Calling `next` passes a metric on to the next interceptor in the chain,
+    /// or to the sink of the client if this is the last one. It may be called
+    /// any number of times: not calling it at all drops the metric, calling it
+    /// more than once sends several metrics.
+    fn intercept(&self, metric: MetricRecord, next: &mut dyn FnMut(MetricRecord));
+}
+
+impl<F> MetricInterceptor for F
+where
+    F: Fn(MetricRecord, &mut dyn FnMut(MetricRecord)),
+{
+    fn intercept(&self, metric: MetricRecord, next: &mut dyn FnMut(MetricRecord)) {
+        (self)(metric, next)
+    }
+}
+
+pub(crate) type BoxedInterceptor = Box<dyn MetricInterceptor + Sync + Send + RefUnwindSafe>;
+
+/// Pass a metric through each interceptor in the chain, calling `last`
+/// for each metric that makes it through all of them.
+pub(crate) fn run_chain(chain: &[BoxedInterceptor], metric: MetricRecord, last: &mut dyn FnMut(MetricRecord)) {
+    match chain.split_first() {
+        Some((first, rest)) => first.intercept(metric, &mut |m| run_chain(rest, m, last)),
+        None => last(metric),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run_chain, BoxedInterceptor};
+    use crate::types::{MetricRecord, MetricType, MetricValue};
+
+    fn record(key: &str) -> MetricRecord {
+        MetricRecord::new(key, MetricValue::Signed(1), MetricType::Counter)
+    }
+
+    fn collect(chain: &[BoxedInterceptor], metric: MetricRecord) -> Vec<String> {
+        let mut out = Vec::new();
+        run_chain(chain, metric, &mut |m| out.push(m.to_string()));
+        out
+    }
+
+    #[test]
+    fn test_run_chain_empty() {
+        assert_eq!(vec!["a:1|c"], collect(&[], record("a")));
+    }
+
+    #[test]
+    fn test_run_chain_order() {
+        let chain: Vec<BoxedInterceptor> = vec![
+            Box::new(|mut m: MetricRecord, next: &mut dyn FnMut(MetricRecord)| {
+                m.key.push_str(".first");
+                next(m)
+            }),
+            Box::new(|mut m: MetricRecord, next: &mut dyn FnMut(MetricRecord)| {
+                m.key.push_str(".second");
+                next(m)
+            }),
+        ];
+
+        assert_eq!(vec!["a.first.second:1|c"], collect(&chain, record("a")));
+    }
+
+    #[test]
+    fn test_run_chain_duplicate_and_drop() {
+        let chain: Vec<BoxedInterceptor> = vec![
+            Box::new(|m: MetricRecord, next: &mut dyn FnMut(MetricRecord)| {
+                let mut copy = m.clone();
+                copy.key.push_str(".copy");
+                next(m);
+                next(copy);
+            }),
+            Box::new(|m: MetricRecord, next: &mut dyn FnMut(MetricRecord)| {
+                if !m.key.ends_with(".copy") {
+                    next(m)
+                }
+            }),
+        ];
+
+        assert_eq!(vec!["a:1|c"], collect(&chain, record("a")));
+    }
+}
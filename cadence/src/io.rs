@@ -12,6 +12,8 @@ use std::io;
 use std::io::{BufWriter, Write};
 use std::str;
 
+use crate::types::MetricTooLargeError;
+
 #[derive(Debug, Default)]
 struct WriterMetrics {
     inner_write: u64,
@@ -33,6 +35,8 @@ where
     metrics: WriterMetrics,
     inner: BufWriter<T>,
     line_ending: Vec<u8>,
+    reject_oversized: bool,
+    keep_order: bool,
 }
 
 impl<T> MultiLineWriter<T>
@@ -54,14 +58,38 @@ where
             metrics: WriterMetrics::default(),
             inner: BufWriter::with_capacity(cap, inner),
             line_ending: Vec::from(end.as_bytes()),
+            reject_oversized: false,
+            keep_order: false,
         }
     }
 
+    /// Return an error for inputs that don't fit in the buffer instead of
+    /// writing them directly to the underlying writer. Used for datagram
+    /// sockets, where the buffer size is the largest packet to send.
+    pub(crate) fn rejecting_oversized(mut self) -> Self {
+        self.reject_oversized = true;
+        self
+    }
+
+    /// Flush anything already buffered before writing an input that doesn't
+    /// fit in the buffer directly to the underlying writer. Used for stream
+    /// sockets, where metrics must arrive in the order they were written.
+    pub(crate) fn keeping_order(mut self) -> Self {
+        self.keep_order = true;
+        self
+    }
+
     #[allow(dead_code)]
-    fn get_ref(&self) -> &T {
+    pub(crate) fn get_ref(&self) -> &T {
         self.inner.get_ref()
     }
 
+    /// Return true if there is buffered data that hasn't been written to
+    /// the underlying writer yet.
+    pub(crate) fn has_pending(&self) -> bool {
+        self.written > 0
+    }
+
     #[allow(dead_code)]
     fn get_metrics(&self) -> &WriterMetrics {
         &self.metrics
@@ -76,7 +104,9 @@ where
         let left = self.capacity - self.written;
         let required = buf.len() + self.line_ending.len();
 
-        if required > self.capacity {
+        if required > self.capacity && self.reject_oversized {
+            Err(MetricTooLargeError::new(required, self.capacity).into())
+        } else if required > self.capacity {
             self.metrics.inner_write += 1;
             // If the user has given us a value bigger than our buffer
             // to write, bypass the buffer and write directly to the Write
@@ -88,6 +118,10 @@ where
             // a newline when we're only writing a single large value to
             // the underlying impl.
             // See https://github.com/56quarters/cadence/issues/87
+            if self.keep_order && self.written > 0 {
+                self.flush()?;
+            }
+
             Ok(self.inner.get_mut().write(buf)?)
         } else {
             if left < required {
@@ -123,6 +157,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::MultiLineWriter;
+    use crate::types::MetricTooLargeError;
 
     use std::io::Write;
     use std::str;
@@ -186,6 +221,35 @@ mod tests {
         assert_eq!(8, in_buffer_after_write2);
     }
 
+    #[test]
+    fn test_write_bigger_than_buffer_keeping_order() {
+        let mut buffered = MultiLineWriter::new(vec![], 16).keeping_order();
+
+        let write1 = buffered.write("abc:4|g".as_bytes()).unwrap();
+        let write2 = buffered.write("some_really_long_metric:456|c".as_bytes()).unwrap();
+
+        assert_eq!(7, write1);
+        assert_eq!(29, write2);
+        assert_eq!(
+            "abc:4|g\nsome_really_long_metric:456|c",
+            str::from_utf8(buffered.get_ref()).unwrap()
+        );
+        assert_eq!(0, buffered.written);
+    }
+
+    #[test]
+    fn test_write_bigger_than_buffer_rejected() {
+        let mut buffered = MultiLineWriter::new(vec![], 16).rejecting_oversized();
+
+        let err = buffered.write("some_really_long_metric:456|c".as_bytes()).unwrap_err();
+        let write2 = buffered.write("abc:4|g".as_bytes()).unwrap();
+
+        assert_eq!(Some(&MetricTooLargeError::new(30, 16)), MetricTooLargeError::find(&err));
+        assert_eq!(7, write2);
+        assert_eq!(0, buffered.get_ref().len());
+        assert_eq!(8, buffered.written);
+    }
+
     #[test]
     fn test_buffer_write_equal_capacity() {
         let mut buffered = MultiLineWriter::new(vec![], 8);
@@ -405,6 +405,21 @@
 //! client.set("users.uniques", 42);
 //! ```
 //!
+//! Servers that accept metrics over Unix stream sockets (`SOCK_STREAM`) using
+//! length-prefixed frames, such as DogStatsD, can be used with the `UnixStreamMetricSink`
+//! or `BufferedUnixStreamMetricSink`. These sinks connect to the socket the first time a
+//! metric is sent and reconnect if the server is restarted.
+//!
+//! ```rust,no_run
+//! use cadence::prelude::*;
+//! use cadence::{StatsdClient, BufferedUnixStreamMetricSink};
+//!
+//! let sink = BufferedUnixStreamMetricSink::from("/var/run/datadog/dsd.socket");
+//! let client = StatsdClient::from_sink("my.prefix", sink);
+//!
+//! client.count("my.counter.thing", 29);
+//! ```
+//!
 //! NOTE: This feature is only available on Unix platforms (Linux, BSD, MacOS).
 //!
 
@@ -419,25 +434,40 @@ pub use self::client::{
 };
 
 pub use self::sinks::{
-    BufferedSpyMetricSink, BufferedUdpMetricSink, MetricSink, NopMetricSink, QueuingMetricSink, SpyMetricSink,
+    AddressResolver, BufferedSpyMetricSink, BufferedTcpMetricSink, BufferedUdpMetricSink, ConnectionOptions,
+    FailoverMetricSink, FailoverMetricSinkBuilder, FanoutErrorPolicy, FanoutMetricSink, FanoutMetricSinkBuilder,
+    FilterRule, FilteringMetricSink, MetricFilter, MetricSink, NopMetricSink, OverflowPolicy, Priority,
+    QueuingMetricSink, QueuingMetricSinkBuilder, ResolveOptions, ShardedMetricSink, ShardedMetricSinkBuilder,
+    SocketSendBuffer, SpyMetricSink, StripedBufferedUdpMetricSink, SwappableMetricSink, SystemResolver, TcpMetricSink,
     UdpMetricSink,
 };
 
-pub use self::types::{Counter, ErrorKind, Gauge, Histogram, Meter, Metric, MetricError, MetricResult, Set, Timer};
+pub use self::interceptor::MetricInterceptor;
+
+pub use self::rename::{RenameRule, RenameRules};
+
+pub use self::types::{
+    Counter, ErrorKind, Gauge, Histogram, Meter, Metric, MetricError, MetricRecord, MetricResult, MetricTooLargeError,
+    MetricType, MetricValue, Set, Timer,
+};
 
 mod builder;
 mod client;
+mod config;
+mod env;
 pub mod ext;
+mod glob;
+mod interceptor;
 mod io;
 pub mod prelude;
+mod rename;
 mod sinks;
 mod types;
 
-// Utilities for running integration tests with Unix datagram sockets.
-#[cfg(unix)]
+// Utilities for running integration tests with UDP, TCP, and Unix sockets.
 #[doc(hidden)]
 pub mod test;
 
-// Sinks for sending metrics over Unix datagram sockets
+// Sinks for sending metrics over Unix datagram and stream sockets
 #[cfg(unix)]
-pub use crate::sinks::{BufferedUnixMetricSink, UnixMetricSink};
+pub use crate::sinks::{BufferedUnixMetricSink, BufferedUnixStreamMetricSink, UnixMetricSink, UnixStreamMetricSink};
@@ -0,0 +1,281 @@
+// Cadence - An extensible Statsd client for Rust!
+//
+// Copyright 2021 Nick Pillitteri
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::glob::glob_captures;
+use crate::interceptor::MetricInterceptor;
+use crate::types::MetricRecord;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// How the legacy key of a metric is matched by a `RenameRule`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LegacyKey {
+    Exact(String),
+    Glob(String),
+}
+
+/// Rule for renaming a metric from a legacy key to a new key.
+///
+/// Keys are matched against and replaced with the full key of the metric,
+/// including any prefix added by the client. Tag keys can be renamed as
+/// well with `.with_tag_rename()`.
+///
+/// By default the legacy metric is replaced by the renamed one. Rules
+/// created with `.with_dual_emit()` send the metric under both the legacy
+/// and new names instead, useful while dashboards and alerts are migrated.
+///
+/// # Example
+///
+/// ```
+/// use cadence::RenameRule;
+///
+/// // Rename a single metric and one of its tags, keeping the old name around
+/// let exact = RenameRule::exact("app.http_requests", "app.http.requests")
+///     .with_tag_rename("status_code", "status")
+///     .with_dual_emit();
+///
+/// // Rename "app.db.reads.count" to "app.database.reads", and so on
+/// let glob = RenameRule::glob("app.db.*.count", "app.database.*");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenameRule {
+    from: LegacyKey,
+    to: String,
+    tags: Vec<(String, String)>,
+    dual_emit: bool,
+}
+
+impl RenameRule {
+    /// Create a rule renaming metrics with exactly the key `from` to `to`.
+    pub fn exact<F: ToString, T: ToString>(from: F, to: T) -> Self {
+        Self::from_key(LegacyKey::Exact(from.to_string()), to.to_string())
+    }
+
+    /// Create a rule renaming metrics with keys matching the glob pattern
+    /// `from`, where `*` matches any sequence of characters and `?` matches
+    /// any single character.
+    ///
+    /// Each `*` in `to` is replaced by the text matched by the corresponding
+    /// wildcard in `from`, in order. Wildcards without a corresponding `*` in
+    /// `to` are ignored.
+    pub fn glob<F: ToString, T: ToString>(from: F, to: T) -> Self {
+        Self::from_key(LegacyKey::Glob(from.to_string()), to.to_string())
+    }
+
+    fn from_key(from: LegacyKey, to: String) -> Self {
+        RenameRule {
+            from,
+            to,
+            tags: Vec::new(),
+            dual_emit: false,
+        }
+    }
+
+    /// Rename the key of a key-value tag on metrics renamed by this rule.
+    pub fn with_tag_rename<F: ToString, T: ToString>(mut self, from: F, to: T) -> Self {
+        self.tags.push((from.to_string(), to.to_string()));
+        self
+    }
+
+    /// Send metrics matching this rule under both their legacy and new names.
+    pub fn with_dual_emit(mut self) -> Self {
+        self.dual_emit = true;
+        self
+    }
+
+    /// Return the new key for the legacy key if it matches this rule.
+    fn rename(&self, key: &str) -> Option<String> {
+        match self.from {
+            LegacyKey::Exact(ref k) if k == key => Some(self.to.clone()),
+            LegacyKey::Exact(_) => None,
+            LegacyKey::Glob(ref g) => glob_captures(g, key).map(|captures| {
+                let mut captures = captures.into_iter();
+                let mut out = String::with_capacity(self.to.len() + key.len());
+                for c in self.to.chars() {
+                    if c == '*' {
+                        out.push_str(captures.next().unwrap_or(""));
+                    } else {
+                        out.push(c);
+                    }
+                }
+                out
+            }),
+        }
+    }
+
+    fn rename_tags(&self, metric: &mut MetricRecord) {
+        for (key, _) in metric.tags.iter_mut() {
+            if let Some(k) = key {
+                if let Some((_, to)) = self.tags.iter().find(|(from, _)| from == k) {
+                    *k = to.clone();
+                }
+            }
+        }
+    }
+}
+
+/// Interceptor that renames metrics according to a list of `RenameRule`s
+/// and counts how often each rule is used.
+///
+/// The first rule matching the key of a metric is applied, metrics that
+/// don't match any rule are sent unchanged. Use `.usage()` to see whether
+/// legacy names are still in use before removing rules. Clones share the
+/// same counters.
+///
+/// # Example
+///
+/// ```
+/// use cadence::prelude::*;
+/// use cadence::{NopMetricSink, RenameRule, RenameRules, StatsdClient};
+///
+/// let rules = RenameRules::new(vec![
+///     RenameRule::exact("app.http_requests", "app.http.requests").with_dual_emit(),
+/// ]);
+///
+/// let client = StatsdClient::builder("app", NopMetricSink)
+///     .with_rename_rules(rules.clone())
+///     .build();
+///
+/// client.incr("http_requests");
+///
+/// assert_eq!(1, rules.usage()[0].1);
+/// ```
+#[derive(Clone)]
+pub struct RenameRules {
+    rules: Arc<[(RenameRule, AtomicU64)]>,
+}
+
+impl RenameRules {
+    /// Create an interceptor applying the first matching rule of `rules`,
+    /// checked in the order given.
+    pub fn new(rules: Vec<RenameRule>) -> Self {
+        RenameRules {
+            rules: rules.into_iter().map(|r| (r, AtomicU64::new(0))).collect(),
+        }
+    }
+
+    /// Return each rule along with the number of metrics it has renamed,
+    /// that is, how many times a legacy name matching it was used.
+    pub fn usage(&self) -> Vec<(RenameRule, u64)> {
+        self.rules
+            .iter()
+            .map(|(rule, count)| (rule.clone(), count.load(Ordering::Relaxed)))
+            .collect()
+    }
+}
+
+impl MetricInterceptor for RenameRules {
+    fn intercept(&self, metric: MetricRecord, next: &mut dyn FnMut(MetricRecord)) {
+        let found = self
+            .rules
+            .iter()
+            .find_map(|(rule, count)| rule.rename(&metric.key).map(|key| (rule, count, key)));
+
+        let (rule, count, key) = match found {
+            Some(v) => v,
+            None => return next(metric),
+        };
+
+        count.fetch_add(1, Ordering::Relaxed);
+
+        let mut renamed = if rule.dual_emit {
+            let renamed = metric.clone();
+            next(metric);
+            renamed
+        } else {
+            metric
+        };
+
+        renamed.key = key;
+        rule.rename_tags(&mut renamed);
+        next(renamed);
+    }
+}
+
+impl fmt::Debug for RenameRules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RenameRules").field("usage", &self.usage()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RenameRule, RenameRules};
+    use crate::interceptor::MetricInterceptor;
+    use crate::types::{MetricRecord, MetricType, MetricValue};
+
+    fn intercept(rules: &RenameRules, key: &str, tags: &[(&str, &str)]) -> Vec<String> {
+        let mut metric = MetricRecord::new(key, MetricValue::Signed(1), MetricType::Counter);
+        metric.tags = tags.iter().map(|(k, v)| (Some(k.to_string()), v.to_string())).collect();
+
+        let mut out = Vec::new();
+        rules.intercept(metric, &mut |m| out.push(m.to_string()));
+        out
+    }
+
+    #[test]
+    fn test_rename_rule_exact() {
+        let rule = RenameRule::exact("a.old", "a.new");
+        assert_eq!(Some("a.new".to_string()), rule.rename("a.old"));
+        assert_eq!(None, rule.rename("a.older"));
+    }
+
+    #[test]
+    fn test_rename_rule_glob() {
+        let rule = RenameRule::glob("db.*.count.?", "database.*.shard*");
+        assert_eq!(
+            Some("database.reads.shard1".to_string()),
+            rule.rename("db.reads.count.1")
+        );
+        assert_eq!(None, rule.rename("db.reads.timer.1"));
+    }
+
+    #[test]
+    fn test_rename_rules_rewrite() {
+        let rules = RenameRules::new(vec![
+            RenameRule::exact("a.old", "a.new").with_tag_rename("code", "status")
+        ]);
+
+        assert_eq!(
+            vec!["a.new:1|c|#status:200,host:web"],
+            intercept(&rules, "a.old", &[("code", "200"), ("host", "web")])
+        );
+        assert_eq!(vec!["a.other:1|c"], intercept(&rules, "a.other", &[]));
+    }
+
+    #[test]
+    fn test_rename_rules_dual_emit() {
+        let rules = RenameRules::new(vec![RenameRule::glob("old.*", "new.*")
+            .with_tag_rename("code", "status")
+            .with_dual_emit()]);
+
+        assert_eq!(
+            vec!["old.requests:1|c|#code:200", "new.requests:1|c|#status:200"],
+            intercept(&rules, "old.requests", &[("code", "200")])
+        );
+    }
+
+    #[test]
+    fn test_rename_rules_usage() {
+        let rules = RenameRules::new(vec![RenameRule::exact("a", "b"), RenameRule::glob("c.*", "d.*")]);
+        let clone = rules.clone();
+
+        intercept(&rules, "a", &[]);
+        intercept(&rules, "c.x", &[]);
+        intercept(&rules, "c.y", &[]);
+        intercept(&rules, "e", &[]);
+
+        assert_eq!(
+            vec![(RenameRule::exact("a", "b"), 1), (RenameRule::glob("c.*", "d.*"), 2)],
+            clone.usage()
+        );
+    }
+}
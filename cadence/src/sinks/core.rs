@@ -63,6 +63,16 @@ pub trait MetricSink {
     /// interpret this as an error.
     fn emit(&self, metric: &str) -> io::Result<usize>;
 
+    /// Send several Statsd metrics using this sink and return the total number
+    /// of bytes written or the first I/O error encountered.
+    ///
+    /// Every metric is sent even if sending an earlier one failed. The default
+    /// implementation calls `.emit()` for each metric. Sinks that are able to
+    /// send several metrics more efficiently than that should override it.
+    fn emit_batch(&self, metrics: &[&str]) -> io::Result<usize> {
+        emit_each(metrics, |metric| self.emit(metric))
+    }
+
     /// Flush any currently buffered metrics to the underlying backend, returning
     /// an I/O error if they could not be written for some reason.
     ///
@@ -73,6 +83,54 @@ pub trait MetricSink {
     }
 }
 
+/// Call `emit` for every metric (or group of metrics), even after an earlier
+/// one fails, and return the total number of bytes written or the first error
+/// encountered.
+///
+/// Shared by sinks that override `.emit_batch()` so that they only have to
+/// do their setup (such as taking a lock) once per batch.
+pub(crate) fn emit_each<I, F>(metrics: I, mut emit: F) -> io::Result<usize>
+where
+    I: IntoIterator,
+    F: FnMut(I::Item) -> io::Result<usize>,
+{
+    let mut written = 0;
+    let mut first_err = None;
+
+    for metric in metrics {
+        match emit(metric) {
+            Ok(n) => written += n,
+            Err(e) => {
+                first_err.get_or_insert(e);
+            }
+        }
+    }
+
+    match first_err {
+        Some(e) => Err(e),
+        None => Ok(written),
+    }
+}
+
+/// Boxed sinks are sinks themselves, which allows the sink used by a client
+/// or wrapped by another sink to be picked at runtime.
+impl<T> MetricSink for Box<T>
+where
+    T: MetricSink + ?Sized,
+{
+    fn emit(&self, metric: &str) -> io::Result<usize> {
+        (**self).emit(metric)
+    }
+
+    fn emit_batch(&self, metrics: &[&str]) -> io::Result<usize> {
+        (**self).emit_batch(metrics)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        (**self).flush()
+    }
+}
+
 /// Implementation of a `MetricSink` that discards all metrics.
 ///
 /// Useful for disabling metric collection or unit tests.
@@ -84,14 +142,54 @@ impl MetricSink for NopMetricSink {
     fn emit(&self, metric: &str) -> io::Result<usize> {
         Ok(0)
     }
+
+    #[allow(unused_variables)]
+    fn emit_batch(&self, metrics: &[&str]) -> io::Result<usize> {
+        Ok(0)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::{MetricSink, NopMetricSink};
+    use std::io;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
     #[test]
     fn test_nop_metric_sink() {
         let sink = NopMetricSink;
         assert_eq!(0, sink.emit("baz:4|c").unwrap());
+        assert_eq!(0, sink.emit_batch(&["foo:1|c", "baz:4|c"]).unwrap());
+    }
+
+    #[test]
+    fn test_metric_sink_emit_batch_default() {
+        struct EveryOtherMetricSink(AtomicUsize);
+
+        impl MetricSink for EveryOtherMetricSink {
+            fn emit(&self, metric: &str) -> io::Result<usize> {
+                if self.0.fetch_add(1, Ordering::Relaxed) % 2 == 1 {
+                    Err(io::Error::from(io::ErrorKind::WouldBlock))
+                } else {
+                    Ok(metric.len())
+                }
+            }
+        }
+
+        let sink = EveryOtherMetricSink(AtomicUsize::new(0));
+        assert_eq!(7, sink.emit_batch(&["foo:1|c"]).unwrap());
+
+        let sink = EveryOtherMetricSink(AtomicUsize::new(0));
+        let err = sink.emit_batch(&["foo:1|c", "bar:2|c", "baz:3|c"]).unwrap_err();
+        assert_eq!(io::ErrorKind::WouldBlock, err.kind());
+        // Metrics after the failed one are still sent
+        assert_eq!(3, sink.0.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_boxed_metric_sink() {
+        let sink: Box<dyn MetricSink> = Box::new(NopMetricSink);
+        assert_eq!(0, sink.emit("baz:4|c").unwrap());
+        assert!(sink.flush().is_ok());
     }
 }
@@ -0,0 +1,411 @@
+// Cadence - An extensible Statsd client for Rust!
+//
+// Copyright 2021 Nick Pillitteri
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::sinks::core::MetricSink;
+use std::fmt;
+use std::io;
+use std::panic::RefUnwindSafe;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+type SharedSink = Arc<dyn MetricSink + Sync + Send + RefUnwindSafe>;
+
+/// Builder for creating a `FailoverMetricSink` from a primary sink and
+/// one or more secondary sinks.
+///
+/// Created via `FailoverMetricSink::builder()`.
+pub struct FailoverMetricSinkBuilder {
+    sinks: Vec<SharedSink>,
+    error_threshold: u32,
+    probe_interval: Duration,
+}
+
+impl FailoverMetricSinkBuilder {
+    /// Add a secondary sink to switch to when the sinks added before it fail.
+    pub fn with_secondary<T>(mut self, sink: T) -> Self
+    where
+        T: MetricSink + Sync + Send + RefUnwindSafe + 'static,
+    {
+        self.sinks.push(Arc::new(sink));
+        self
+    }
+
+    /// Set the number of consecutive errors from the active sink after which
+    /// the next sink becomes active, three by default.
+    pub fn with_error_threshold(mut self, errors: u32) -> Self {
+        self.error_threshold = errors.max(1);
+        self
+    }
+
+    /// Set how often to try sending a metric to the primary sink while a
+    /// secondary sink is active, 30 seconds by default.
+    pub fn with_probe_interval(mut self, interval: Duration) -> Self {
+        self.probe_interval = interval;
+        self
+    }
+
+    /// Construct a new `FailoverMetricSink` from the sinks added so far.
+    pub fn build(self) -> FailoverMetricSink {
+        FailoverMetricSink {
+            state: Arc::new(FailoverState {
+                sinks: self.sinks,
+                error_threshold: self.error_threshold,
+                probe_interval: self.probe_interval,
+                active: AtomicUsize::new(0),
+                errors: AtomicU32::new(0),
+                failovers: AtomicU64::new(0),
+                probed_at: Mutex::new(Instant::now()),
+            }),
+        }
+    }
+}
+
+impl fmt::Debug for FailoverMetricSinkBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FailoverMetricSinkBuilder")
+            .field("sinks", &self.sinks.len())
+            .field("error_threshold", &self.error_threshold)
+            .field("probe_interval", &self.probe_interval)
+            .finish()
+    }
+}
+
+struct FailoverState {
+    sinks: Vec<SharedSink>,
+    error_threshold: u32,
+    probe_interval: Duration,
+    active: AtomicUsize,
+    errors: AtomicU32,
+    failovers: AtomicU64,
+    probed_at: Mutex<Instant>,
+}
+
+impl FailoverState {
+    /// Is it time to try the primary sink again? Only one caller at a time
+    /// gets to probe the primary, others use the active sink meanwhile.
+    fn probe_due(&self) -> bool {
+        match self.probed_at.try_lock() {
+            Ok(mut probed_at) => {
+                let now = Instant::now();
+                if now.duration_since(*probed_at) >= self.probe_interval {
+                    *probed_at = now;
+                    true
+                } else {
+                    false
+                }
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Count an error from the sink at `index`, making the next sink active
+    /// if there have been too many consecutive errors. Returns the index of
+    /// the newly active sink if it changed.
+    fn record_error(&self, index: usize) -> Option<usize> {
+        let errors = self.errors.fetch_add(1, Ordering::Relaxed) + 1;
+        if errors < self.error_threshold {
+            return None;
+        }
+
+        // Never wrap around from the last sink to the primary, which only
+        // becomes active again once probing it succeeds. This also covers
+        // having no secondary sinks to fail over to.
+        let next = index + 1;
+        if next >= self.sinks.len() {
+            return None;
+        }
+
+        // Another caller may have switched sinks already, in which case
+        // there's nothing else to do.
+        if self
+            .active
+            .compare_exchange(index, next, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+        {
+            self.errors.store(0, Ordering::Relaxed);
+            self.failovers.fetch_add(1, Ordering::Relaxed);
+            if let Ok(mut probed_at) = self.probed_at.lock() {
+                *probed_at = Instant::now();
+            }
+            Some(next)
+        } else {
+            None
+        }
+    }
+
+    fn record_success(&self) {
+        // Avoid writing to the shared counter on every successful send
+        if self.errors.load(Ordering::Relaxed) != 0 {
+            self.errors.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Implementation of a `MetricSink` that sends metrics to a primary sink and
+/// switches to secondary sinks when the primary fails, such as falling back
+/// to a remote relay over UDP when the Unix socket of a local agent is not
+/// available.
+///
+/// Metrics are sent to the active sink, the primary sink initially. After a
+/// number of consecutive errors from the active sink, the next sink (in the
+/// order they were added) becomes active and the metric that caused the
+/// switch is sent to it instead. The last sink stays active even if it keeps
+/// failing, in which case its errors are returned. While a secondary sink is
+/// active, a metric is periodically sent to the primary sink instead to check
+/// if it has recovered, making it active again if so.
+///
+/// Calling `.flush()` flushes every sink so that metrics buffered by a sink
+/// that is no longer active aren't left behind. Only errors from the active
+/// sink are returned.
+///
+/// Clones of a `FailoverMetricSink` share the same sinks and state. Thus,
+/// callers can keep a clone of the sink before using it to build a client and
+/// use that clone to check which sink is active.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use cadence::prelude::*;
+/// use cadence::{FailoverMetricSink, NopMetricSink, StatsdClient};
+///
+/// let sink = FailoverMetricSink::builder(NopMetricSink)
+///     .with_secondary(NopMetricSink)
+///     .with_error_threshold(5)
+///     .with_probe_interval(Duration::from_secs(10))
+///     .build();
+///
+/// let client = StatsdClient::from_sink("my.prefix", sink.clone());
+/// client.count("some.counter", 1).unwrap();
+///
+/// assert_eq!(0, sink.active());
+/// ```
+#[derive(Clone)]
+pub struct FailoverMetricSink {
+    state: Arc<FailoverState>,
+}
+
+impl FailoverMetricSink {
+    /// Create a builder for a new `FailoverMetricSink` with the given primary
+    /// sink. Secondary sinks are added with the builder.
+    pub fn builder<T>(primary: T) -> FailoverMetricSinkBuilder
+    where
+        T: MetricSink + Sync + Send + RefUnwindSafe + 'static,
+    {
+        FailoverMetricSinkBuilder {
+            sinks: vec![Arc::new(primary)],
+            error_threshold: 3,
+            probe_interval: Duration::from_secs(30),
+        }
+    }
+
+    /// Return the index of the active sink: zero for the primary sink and
+    /// one or more for secondary sinks, in the order they were added.
+    pub fn active(&self) -> usize {
+        self.state.active.load(Ordering::Acquire)
+    }
+
+    /// Return true if the primary sink is active.
+    pub fn is_primary_active(&self) -> bool {
+        self.active() == 0
+    }
+
+    /// Return the number of times the active sink has been switched because
+    /// of errors, not counting switching back to the primary after probing it.
+    pub fn failovers(&self) -> u64 {
+        self.state.failovers.load(Ordering::Relaxed)
+    }
+}
+
+impl MetricSink for FailoverMetricSink {
+    fn emit(&self, metric: &str) -> io::Result<usize> {
+        let state = &self.state;
+        let mut index = state.active.load(Ordering::Acquire);
+
+        if index != 0 && state.probe_due() {
+            if let Ok(n) = state.sinks[0].emit(metric) {
+                state.active.store(0, Ordering::Release);
+                state.errors.store(0, Ordering::Relaxed);
+                return Ok(n);
+            }
+        }
+
+        // Each switch moves to a later sink, so a single metric tries each
+        // sink at most once and the error of the last one tried is returned.
+        loop {
+            match state.sinks[index].emit(metric) {
+                Ok(n) => {
+                    state.record_success();
+                    return Ok(n);
+                }
+                Err(e) => match state.record_error(index) {
+                    Some(next) => index = next,
+                    None => return Err(e),
+                },
+            }
+        }
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let active = self.active();
+        let mut res = Ok(());
+
+        for (i, sink) in self.state.sinks.iter().enumerate() {
+            let flushed = sink.flush();
+            if i == active {
+                res = flushed;
+            }
+        }
+
+        res
+    }
+}
+
+impl fmt::Debug for FailoverMetricSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FailoverMetricSink")
+            .field("sinks", &self.state.sinks.len())
+            .field("active", &self.active())
+            .field("failovers", &self.failovers())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FailoverMetricSink;
+    use crate::sinks::{MetricSink, SpyMetricSink};
+    use std::io;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    /// Sink that fails while its flag is set, writing to a spy otherwise
+    struct FlakySink {
+        failing: Arc<AtomicBool>,
+        spy: SpyMetricSink,
+    }
+
+    impl MetricSink for FlakySink {
+        fn emit(&self, metric: &str) -> io::Result<usize> {
+            if self.failing.load(Ordering::SeqCst) {
+                Err(io::Error::from(io::ErrorKind::ConnectionRefused))
+            } else {
+                self.spy.emit(metric)
+            }
+        }
+    }
+
+    fn new_flaky(failing: bool) -> (FlakySink, Arc<AtomicBool>, Arc<Mutex<Vec<u8>>>) {
+        let flag = Arc::new(AtomicBool::new(failing));
+        let writer = Arc::new(Mutex::new(Vec::new()));
+        let sink = FlakySink {
+            failing: flag.clone(),
+            spy: SpyMetricSink::from(writer.clone()),
+        };
+        (sink, flag, writer)
+    }
+
+    fn written(writer: &Arc<Mutex<Vec<u8>>>) -> String {
+        String::from_utf8(writer.lock().unwrap().clone()).unwrap()
+    }
+
+    #[test]
+    fn test_failover_metric_sink_primary() {
+        let (primary, _, primary_writer) = new_flaky(false);
+        let (secondary, _, secondary_writer) = new_flaky(false);
+        let sink = FailoverMetricSink::builder(primary).with_secondary(secondary).build();
+
+        sink.emit("foo:1|c").unwrap();
+
+        assert!(sink.is_primary_active());
+        assert_eq!("foo:1|c", written(&primary_writer));
+        assert_eq!("", written(&secondary_writer));
+    }
+
+    #[test]
+    fn test_failover_metric_sink_switches_after_errors() {
+        let (primary, _, _) = new_flaky(true);
+        let (secondary, _, secondary_writer) = new_flaky(false);
+        let sink = FailoverMetricSink::builder(primary)
+            .with_secondary(secondary)
+            .with_error_threshold(2)
+            .build();
+
+        assert!(sink.emit("foo:1|c").is_err());
+        assert!(sink.is_primary_active());
+
+        // The metric that caused the switch is sent to the secondary
+        sink.emit("foo:2|c").unwrap();
+        assert_eq!(1, sink.active());
+        assert_eq!(1, sink.failovers());
+        assert_eq!("foo:2|c", written(&secondary_writer));
+    }
+
+    #[test]
+    fn test_failover_metric_sink_probes_primary() {
+        let (primary, primary_failing, primary_writer) = new_flaky(true);
+        let (secondary, _, secondary_writer) = new_flaky(false);
+        let sink = FailoverMetricSink::builder(primary)
+            .with_secondary(secondary)
+            .with_error_threshold(1)
+            .with_probe_interval(Duration::from_millis(20))
+            .build();
+
+        sink.emit("foo:1|c").unwrap();
+        assert_eq!(1, sink.active());
+
+        // Still failing when probed so the secondary stays active
+        thread::sleep(Duration::from_millis(25));
+        sink.emit("foo:2|c").unwrap();
+        assert_eq!(1, sink.active());
+
+        primary_failing.store(false, Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(25));
+        sink.emit("foo:3|c").unwrap();
+
+        assert!(sink.is_primary_active());
+        assert_eq!("foo:3|c", written(&primary_writer));
+        assert_eq!("foo:1|cfoo:2|c", written(&secondary_writer));
+    }
+
+    #[test]
+    fn test_failover_metric_sink_all_failing() {
+        let (primary, _, _) = new_flaky(true);
+        let (secondary, _, _) = new_flaky(true);
+        let sink = FailoverMetricSink::builder(primary)
+            .with_secondary(secondary)
+            .with_error_threshold(1)
+            .build();
+
+        let err = sink.emit("foo:1|c").unwrap_err();
+        assert_eq!(io::ErrorKind::ConnectionRefused, err.kind());
+        assert_eq!(1, sink.active());
+        assert_eq!(1, sink.failovers());
+
+        // The last sink stays active rather than wrapping around
+        assert!(sink.emit("foo:2|c").is_err());
+        assert_eq!(1, sink.active());
+        assert_eq!(1, sink.failovers());
+    }
+
+    #[test]
+    fn test_failover_metric_sink_primary_only() {
+        let (primary, _, _) = new_flaky(true);
+        let sink = FailoverMetricSink::builder(primary).with_error_threshold(1).build();
+
+        let err = sink.emit("foo:1|c").unwrap_err();
+        assert_eq!(io::ErrorKind::ConnectionRefused, err.kind());
+        assert!(sink.is_primary_active());
+        assert_eq!(0, sink.failovers());
+    }
+}
@@ -0,0 +1,357 @@
+// Cadence - An extensible Statsd client for Rust!
+//
+// Copyright 2021 Nick Pillitteri
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::sinks::core::MetricSink;
+use crate::sinks::filter::{FilterState, MetricFilter};
+use std::cmp;
+use std::fmt;
+use std::io;
+use std::panic::RefUnwindSafe;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// When a `FanoutMetricSink` reports an error for a metric or flush that
+/// failed for some of its child sinks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FanoutErrorPolicy {
+    /// Return an error if sending to any child sink failed, the default.
+    FailOnAny,
+    /// Return an error only if sending to every child sink failed. Child
+    /// sinks that skipped a metric because of their filter aren't counted.
+    FailOnAll,
+}
+
+/// Child sink of a `FanoutMetricSink` along with its optional filter and
+/// the number of errors it has returned.
+struct FanoutChild {
+    sink: Arc<dyn MetricSink + Sync + Send + RefUnwindSafe>,
+    filter: Option<FilterState>,
+    errors: AtomicU64,
+}
+
+impl FanoutChild {
+    fn record<T>(&self, res: io::Result<T>) -> io::Result<T> {
+        if res.is_err() {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+
+        res
+    }
+}
+
+/// Builder for creating a `FanoutMetricSink` from one or more child sinks.
+///
+/// Created via `FanoutMetricSink::builder()`.
+pub struct FanoutMetricSinkBuilder {
+    children: Vec<FanoutChild>,
+    policy: FanoutErrorPolicy,
+}
+
+impl FanoutMetricSinkBuilder {
+    fn new() -> Self {
+        FanoutMetricSinkBuilder {
+            children: Vec::new(),
+            policy: FanoutErrorPolicy::FailOnAny,
+        }
+    }
+
+    /// Send every metric to the given sink.
+    pub fn with_sink<T>(mut self, sink: T) -> Self
+    where
+        T: MetricSink + Sync + Send + RefUnwindSafe + 'static,
+    {
+        self.children.push(FanoutChild {
+            sink: Arc::new(sink),
+            filter: None,
+            errors: AtomicU64::new(0),
+        });
+        self
+    }
+
+    /// Send only metrics allowed by the filter to the given sink. See
+    /// `MetricFilter` for details.
+    pub fn with_filtered_sink<T>(mut self, sink: T, filter: MetricFilter) -> Self
+    where
+        T: MetricSink + Sync + Send + RefUnwindSafe + 'static,
+    {
+        self.children.push(FanoutChild {
+            sink: Arc::new(sink),
+            filter: Some(FilterState::new(filter)),
+            errors: AtomicU64::new(0),
+        });
+        self
+    }
+
+    /// Set when errors from child sinks are returned to the caller,
+    /// `FanoutErrorPolicy::FailOnAny` by default.
+    pub fn with_error_policy(mut self, policy: FanoutErrorPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Construct a new `FanoutMetricSink` from the child sinks added so far.
+    pub fn build(self) -> FanoutMetricSink {
+        FanoutMetricSink {
+            children: self.children.into(),
+            policy: self.policy,
+        }
+    }
+}
+
+impl fmt::Debug for FanoutMetricSinkBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FanoutMetricSinkBuilder")
+            .field("children", &self.children.len())
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+/// Implementation of a `MetricSink` that sends each metric to several child
+/// sinks, such as two Statsd servers during a migration from one to the other.
+///
+/// Each call to `.emit()` or `.flush()` is forwarded to every child sink in
+/// the order they were added, in the thread of the caller, even if some of
+/// them fail. Whether the caller sees an error depends on the configured
+/// `FanoutErrorPolicy`. When there is an error to return, it's the first one
+/// encountered. Child sinks can optionally be given a `MetricFilter` so that
+/// they only receive some metrics.
+///
+/// Clones of a `FanoutMetricSink` share the same child sinks and counters.
+/// Thus, callers can keep a clone of the sink before using it to build a
+/// client and use that clone to check how many errors each child returned.
+///
+/// # Example
+///
+/// ```
+/// use cadence::prelude::*;
+/// use cadence::{FanoutErrorPolicy, FanoutMetricSink, FilterRule, MetricFilter, NopMetricSink, StatsdClient};
+///
+/// let sink = FanoutMetricSink::builder()
+///     .with_sink(NopMetricSink)
+///     .with_filtered_sink(NopMetricSink, MetricFilter::allow(vec![FilterRule::prefix("my.prefix.api.")]))
+///     .with_error_policy(FanoutErrorPolicy::FailOnAll)
+///     .build();
+///
+/// let client = StatsdClient::from_sink("my.prefix", sink.clone());
+/// client.count("api.requests", 1).unwrap();
+///
+/// assert_eq!(vec![0, 0], sink.errors());
+/// ```
+#[derive(Clone)]
+pub struct FanoutMetricSink {
+    children: Arc<[FanoutChild]>,
+    policy: FanoutErrorPolicy,
+}
+
+impl FanoutMetricSink {
+    /// Create a builder for adding child sinks to a new `FanoutMetricSink`.
+    pub fn builder() -> FanoutMetricSinkBuilder {
+        FanoutMetricSinkBuilder::new()
+    }
+
+    /// Return the number of errors returned by each child sink, in the order
+    /// they were added.
+    pub fn errors(&self) -> Vec<u64> {
+        self.children.iter().map(|c| c.errors.load(Ordering::Relaxed)).collect()
+    }
+
+    /// Combine the results of calling each of the (non-filtered) child sinks
+    /// according to the error policy, keeping the first error seen. Every
+    /// result is consumed so that each child is called regardless of how
+    /// earlier children did.
+    fn combine<T, I>(&self, results: I, init: T, merge: fn(T, T) -> T) -> io::Result<T>
+    where
+        I: Iterator<Item = io::Result<T>>,
+    {
+        let mut out = init;
+        let mut attempts = 0;
+        let mut failures = 0;
+        let mut first_err = None;
+
+        for res in results {
+            attempts += 1;
+            match res {
+                Ok(v) => out = merge(out, v),
+                Err(e) => {
+                    failures += 1;
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+
+        let failed = match self.policy {
+            FanoutErrorPolicy::FailOnAny => failures > 0,
+            FanoutErrorPolicy::FailOnAll => failures > 0 && failures == attempts,
+        };
+
+        match first_err {
+            Some(e) if failed => Err(e),
+            _ => Ok(out),
+        }
+    }
+}
+
+impl MetricSink for FanoutMetricSink {
+    fn emit(&self, metric: &str) -> io::Result<usize> {
+        let results = self
+            .children
+            .iter()
+            .filter(|c| c.filter.as_ref().map(|f| f.accept(metric)).unwrap_or(true))
+            .map(|c| c.record(c.sink.emit(metric)));
+
+        self.combine(results, 0, cmp::max)
+    }
+
+    fn emit_batch(&self, metrics: &[&str]) -> io::Result<usize> {
+        let results = self.children.iter().filter_map(|c| match c.filter {
+            Some(ref filter) => {
+                let accepted: Vec<&str> = metrics.iter().copied().filter(|m| filter.accept(m)).collect();
+                if accepted.is_empty() {
+                    None
+                } else {
+                    Some(c.record(c.sink.emit_batch(&accepted)))
+                }
+            }
+            None => Some(c.record(c.sink.emit_batch(metrics))),
+        });
+
+        self.combine(results, 0, cmp::max)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let results = self.children.iter().map(|c| c.record(c.sink.flush()));
+        self.combine(results, (), |_, _| ())
+    }
+}
+
+impl fmt::Debug for FanoutMetricSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FanoutMetricSink")
+            .field("children", &self.children.len())
+            .field("policy", &self.policy)
+            .field("errors", &self.errors())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FanoutErrorPolicy, FanoutMetricSink};
+    use crate::sinks::{FilterRule, MetricFilter, MetricSink, SpyMetricSink};
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    struct ErrorSink;
+
+    impl MetricSink for ErrorSink {
+        fn emit(&self, _metric: &str) -> io::Result<usize> {
+            Err(io::Error::from(io::ErrorKind::ConnectionRefused))
+        }
+    }
+
+    fn new_spy() -> (SpyMetricSink, Arc<Mutex<Vec<u8>>>) {
+        let writer = Arc::new(Mutex::new(Vec::new()));
+        (SpyMetricSink::from(writer.clone()), writer)
+    }
+
+    fn written(writer: &Arc<Mutex<Vec<u8>>>) -> String {
+        String::from_utf8(writer.lock().unwrap().clone()).unwrap()
+    }
+
+    #[test]
+    fn test_fanout_metric_sink_all_children() {
+        let (first, first_writer) = new_spy();
+        let (second, second_writer) = new_spy();
+        let sink = FanoutMetricSink::builder().with_sink(first).with_sink(second).build();
+
+        assert_eq!(7, sink.emit("foo:1|c").unwrap());
+        sink.flush().unwrap();
+
+        assert_eq!("foo:1|c", written(&first_writer));
+        assert_eq!("foo:1|c", written(&second_writer));
+        assert_eq!(vec![0, 0], sink.errors());
+    }
+
+    #[test]
+    fn test_fanout_metric_sink_fail_on_any() {
+        let (spy, writer) = new_spy();
+        let sink = FanoutMetricSink::builder().with_sink(ErrorSink).with_sink(spy).build();
+
+        let err = sink.emit("foo:1|c").unwrap_err();
+        assert_eq!(io::ErrorKind::ConnectionRefused, err.kind());
+        // The second child is still sent the metric even though the first failed
+        assert_eq!("foo:1|c", written(&writer));
+        assert_eq!(vec![1, 0], sink.errors());
+    }
+
+    #[test]
+    fn test_fanout_metric_sink_fail_on_all() {
+        let (spy, _writer) = new_spy();
+        let sink = FanoutMetricSink::builder()
+            .with_sink(ErrorSink)
+            .with_sink(spy)
+            .with_error_policy(FanoutErrorPolicy::FailOnAll)
+            .build();
+
+        assert_eq!(7, sink.emit("foo:1|c").unwrap());
+
+        let failing = FanoutMetricSink::builder()
+            .with_sink(ErrorSink)
+            .with_sink(ErrorSink)
+            .with_error_policy(FanoutErrorPolicy::FailOnAll)
+            .build();
+
+        assert!(failing.emit("foo:1|c").is_err());
+        assert_eq!(vec![1, 1], failing.errors());
+    }
+
+    #[test]
+    fn test_fanout_metric_sink_filtered_child() {
+        let (spy, writer) = new_spy();
+        let sink = FanoutMetricSink::builder()
+            .with_filtered_sink(spy, MetricFilter::allow(vec![FilterRule::prefix("api.")]))
+            .with_filtered_sink(ErrorSink, MetricFilter::deny(vec![FilterRule::prefix("api.")]))
+            .with_error_policy(FanoutErrorPolicy::FailOnAll)
+            .build();
+
+        // Only the first child accepts this metric so the second can't fail it
+        sink.emit("api.requests:1|c").unwrap();
+        assert!(sink.emit("db.queries:1|c").is_err());
+
+        assert_eq!("api.requests:1|c", written(&writer));
+        assert_eq!(vec![0, 1], sink.errors());
+    }
+
+    #[test]
+    fn test_fanout_metric_sink_emit_batch_filtered_child() {
+        let (spy, writer) = new_spy();
+        let sink = FanoutMetricSink::builder()
+            .with_filtered_sink(spy, MetricFilter::allow(vec![FilterRule::prefix("api.")]))
+            .with_filtered_sink(ErrorSink, MetricFilter::deny(vec![FilterRule::prefix("api.")]))
+            .build();
+
+        // The failing child doesn't get a batch when it accepts none of the metrics
+        sink.emit_batch(&["api.requests:1|c", "api.errors:1|c"]).unwrap();
+        assert!(sink.emit_batch(&["api.requests:1|c", "db.queries:1|c"]).is_err());
+
+        assert_eq!("api.requests:1|capi.errors:1|capi.requests:1|c", written(&writer));
+        assert_eq!(vec![0, 1], sink.errors());
+    }
+
+    #[test]
+    fn test_fanout_metric_sink_clone_shares_counters() {
+        let sink = FanoutMetricSink::builder().with_sink(ErrorSink).build();
+        let clone = sink.clone();
+
+        let _ = sink.emit("foo:1|c");
+        assert_eq!(vec![1], clone.errors());
+    }
+}
@@ -0,0 +1,416 @@
+// Cadence - An extensible Statsd client for Rust!
+//
+// Copyright 2021 Nick Pillitteri
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::glob::glob_match;
+use crate::sinks::core::MetricSink;
+use std::fmt;
+use std::io;
+use std::panic::RefUnwindSafe;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// How the key of a metric is matched by a `FilterRule`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum KeyPattern {
+    Exact(String),
+    Prefix(String),
+    Glob(String),
+}
+
+/// Rule matching metrics by their key and, optionally, their tags.
+///
+/// Keys are matched against the full key of the metric, including any
+/// prefix added by the client. Tags added to a rule must all be present
+/// on a metric for it to match.
+///
+/// # Example
+///
+/// ```
+/// use cadence::FilterRule;
+///
+/// // Matches "my.prefix.http.requests" but not "my.prefix.http.requests.total"
+/// let exact = FilterRule::exact("my.prefix.http.requests");
+///
+/// // Matches any metric whose key starts with "my.prefix.db."
+/// let prefix = FilterRule::prefix("my.prefix.db.");
+///
+/// // Matches "my.prefix.cache.hits" and "my.prefix.cache.misses" only
+/// // when they are tagged with "tier:web" and "canary".
+/// let glob = FilterRule::glob("my.prefix.cache.*")
+///     .with_tag("tier", "web")
+///     .with_tag_value("canary");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterRule {
+    key: KeyPattern,
+    tags: Vec<(Option<String>, String)>,
+}
+
+impl FilterRule {
+    /// Create a rule matching metrics with exactly the given key.
+    pub fn exact<S: ToString>(key: S) -> Self {
+        Self::from_pattern(KeyPattern::Exact(key.to_string()))
+    }
+
+    /// Create a rule matching metrics with keys that start with the given prefix.
+    pub fn prefix<S: ToString>(prefix: S) -> Self {
+        Self::from_pattern(KeyPattern::Prefix(prefix.to_string()))
+    }
+
+    /// Create a rule matching metrics with keys that match the given glob
+    /// pattern, where `*` matches any sequence of characters and `?` matches
+    /// any single character.
+    pub fn glob<S: ToString>(pattern: S) -> Self {
+        Self::from_pattern(KeyPattern::Glob(pattern.to_string()))
+    }
+
+    fn from_pattern(key: KeyPattern) -> Self {
+        FilterRule { key, tags: Vec::new() }
+    }
+
+    /// Require that a metric has the given key-value tag to match this rule.
+    pub fn with_tag<K: ToString, V: ToString>(mut self, key: K, value: V) -> Self {
+        self.tags.push((Some(key.to_string()), value.to_string()));
+        self
+    }
+
+    /// Require that a metric has the given value tag to match this rule.
+    pub fn with_tag_value<V: ToString>(mut self, value: V) -> Self {
+        self.tags.push((None, value.to_string()));
+        self
+    }
+
+    pub(crate) fn matches(&self, key: &str, tags: Option<&str>) -> bool {
+        let key_matches = match self.key {
+            KeyPattern::Exact(ref k) => key == k,
+            KeyPattern::Prefix(ref p) => key.starts_with(p.as_str()),
+            KeyPattern::Glob(ref g) => glob_match(g, key),
+        };
+
+        key_matches && self.tags.iter().all(|t| has_tag(tags, t))
+    }
+}
+
+/// Set of rules used by a `FilteringMetricSink` to decide which metrics
+/// are sent to the wrapped sink.
+///
+/// In allowlist mode, only metrics that match at least one rule are sent.
+/// In denylist mode, metrics that match any rule are dropped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetricFilter {
+    allow: bool,
+    rules: Vec<FilterRule>,
+}
+
+impl MetricFilter {
+    /// Create a filter that only sends metrics matching one of the rules.
+    pub fn allow(rules: Vec<FilterRule>) -> Self {
+        MetricFilter { allow: true, rules }
+    }
+
+    /// Create a filter that drops metrics matching any of the rules.
+    pub fn deny(rules: Vec<FilterRule>) -> Self {
+        MetricFilter { allow: false, rules }
+    }
+}
+
+/// Rules currently in use along with the number of metrics dropped by each.
+pub(crate) struct FilterState {
+    filter: MetricFilter,
+    dropped: Vec<AtomicU64>,
+    unmatched: AtomicU64,
+}
+
+impl FilterState {
+    pub(crate) fn new(filter: MetricFilter) -> Self {
+        let dropped = filter.rules.iter().map(|_| AtomicU64::new(0)).collect();
+        FilterState {
+            filter,
+            dropped,
+            unmatched: AtomicU64::new(0),
+        }
+    }
+
+    /// Return true if the metric should be sent, counting it against the
+    /// responsible rule if not.
+    pub(crate) fn accept(&self, metric: &str) -> bool {
+        let (key, tags) = parse_metric(metric);
+        let matched = self.filter.rules.iter().position(|r| r.matches(key, tags));
+
+        match (self.filter.allow, matched) {
+            (true, Some(_)) => true,
+            (true, None) => {
+                self.unmatched.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+            (false, Some(i)) => {
+                self.dropped[i].fetch_add(1, Ordering::Relaxed);
+                false
+            }
+            (false, None) => true,
+        }
+    }
+}
+
+/// Implementation of a `MetricSink` that drops metrics based on their key
+/// and tags before sending the rest to a wrapped sink.
+///
+/// This is meant for turning off noisy metrics, such as those emitted by
+/// libraries that share a client with an application, without changing the
+/// code that emits them. Rules can match keys exactly, by prefix, or by glob
+/// pattern and can additionally require tags to be present. See `FilterRule`
+/// and `MetricFilter` for details.
+///
+/// Clones of a `FilteringMetricSink` share the same rules and counters. Thus,
+/// callers can keep a clone of the sink before using it to build a client and
+/// use that clone to check how many metrics were dropped or to replace the
+/// rules at runtime with `.reload()`.
+///
+/// # Example
+///
+/// ```
+/// use cadence::prelude::*;
+/// use cadence::{FilterRule, FilteringMetricSink, MetricFilter, NopMetricSink, StatsdClient};
+///
+/// let filter = MetricFilter::deny(vec![FilterRule::prefix("my.prefix.noisy.")]);
+/// let sink = FilteringMetricSink::new(NopMetricSink, filter);
+/// let client = StatsdClient::from_sink("my.prefix", sink.clone());
+///
+/// client.count("noisy.counter", 1).unwrap();
+/// client.count("useful.counter", 1).unwrap();
+///
+/// assert_eq!(vec![(FilterRule::prefix("my.prefix.noisy."), 1)], sink.dropped());
+///
+/// // Later, stop dropping anything
+/// sink.reload(MetricFilter::deny(vec![]));
+/// ```
+#[derive(Clone)]
+pub struct FilteringMetricSink {
+    sink: Arc<dyn MetricSink + Sync + Send + RefUnwindSafe>,
+    state: Arc<RwLock<Arc<FilterState>>>,
+}
+
+impl FilteringMetricSink {
+    /// Construct a new `FilteringMetricSink` that sends metrics allowed by
+    /// the filter to the provided sink.
+    pub fn new<T>(sink: T, filter: MetricFilter) -> Self
+    where
+        T: MetricSink + Sync + Send + RefUnwindSafe + 'static,
+    {
+        FilteringMetricSink {
+            sink: Arc::new(sink),
+            state: Arc::new(RwLock::new(Arc::new(FilterState::new(filter)))),
+        }
+    }
+
+    /// Replace the rules used by this sink and all clones of it.
+    ///
+    /// Counts of dropped metrics start from zero for the new rules.
+    pub fn reload(&self, filter: MetricFilter) {
+        let mut state = self.state.write().unwrap();
+        *state = Arc::new(FilterState::new(filter));
+    }
+
+    /// Return each rule currently in use along with the number of metrics
+    /// it has dropped.
+    ///
+    /// Rules of a filter in allowlist mode never drop metrics themselves,
+    /// see `.dropped_unmatched()` instead.
+    pub fn dropped(&self) -> Vec<(FilterRule, u64)> {
+        let state = self.current();
+        state
+            .filter
+            .rules
+            .iter()
+            .zip(state.dropped.iter())
+            .map(|(rule, count)| (rule.clone(), count.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    /// Return the number of metrics dropped by a filter in allowlist mode
+    /// because they did not match any rule.
+    pub fn dropped_unmatched(&self) -> u64 {
+        self.current().unmatched.load(Ordering::Relaxed)
+    }
+
+    fn current(&self) -> Arc<FilterState> {
+        self.state.read().unwrap().clone()
+    }
+}
+
+impl MetricSink for FilteringMetricSink {
+    fn emit(&self, metric: &str) -> io::Result<usize> {
+        if self.current().accept(metric) {
+            self.sink.emit(metric)
+        } else {
+            Ok(0)
+        }
+    }
+
+    fn emit_batch(&self, metrics: &[&str]) -> io::Result<usize> {
+        let state = self.current();
+        let accepted: Vec<&str> = metrics.iter().copied().filter(|m| state.accept(m)).collect();
+
+        if accepted.is_empty() {
+            Ok(0)
+        } else {
+            self.sink.emit_batch(&accepted)
+        }
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.sink.flush()
+    }
+}
+
+impl fmt::Debug for FilteringMetricSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "FilteringMetricSink {{ sink: ..., filter: {:?} }}",
+            self.current().filter
+        )
+    }
+}
+
+/// Split a metric into its key and the (unparsed) section containing its
+/// tags, if any. For example `some.key:1|c|#a:b,c` becomes `some.key` and
+/// `a:b,c`.
+pub(crate) fn parse_metric(metric: &str) -> (&str, Option<&str>) {
+    let key = match metric.find(':') {
+        Some(i) => &metric[..i],
+        None => metric,
+    };
+
+    let tags = metric.find("|#").map(|i| {
+        let section = &metric[i + 2..];
+        match section.find('|') {
+            Some(j) => &section[..j],
+            None => section,
+        }
+    });
+
+    (key, tags)
+}
+
+fn has_tag(tags: Option<&str>, expected: &(Option<String>, String)) -> bool {
+    let tags = match tags {
+        Some(t) => t,
+        None => return false,
+    };
+
+    tags.split(',').any(|tag| match expected {
+        (Some(k), v) => tag.strip_prefix(k.as_str()).and_then(|t| t.strip_prefix(':')) == Some(v.as_str()),
+        (None, v) => tag == v,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_metric, FilterRule, FilteringMetricSink, MetricFilter};
+    use crate::sinks::{MetricSink, SpyMetricSink};
+    use std::sync::{Arc, Mutex};
+
+    fn new_sink(filter: MetricFilter) -> (FilteringMetricSink, Arc<Mutex<Vec<u8>>>) {
+        let writer = Arc::new(Mutex::new(Vec::new()));
+        let sink = FilteringMetricSink::new(SpyMetricSink::from(writer.clone()), filter);
+        (sink, writer)
+    }
+
+    fn written(writer: &Arc<Mutex<Vec<u8>>>) -> String {
+        String::from_utf8(writer.lock().unwrap().clone()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_metric() {
+        assert_eq!(("some.key", None), parse_metric("some.key:1|c"));
+        assert_eq!(("some.key", Some("a:b,c")), parse_metric("some.key:1|c|#a:b,c"));
+        assert_eq!(("some.key", Some("a:b")), parse_metric("some.key:1|c|#a:b|@0.5"));
+    }
+
+    #[test]
+    fn test_filter_rule_matches() {
+        assert!(FilterRule::exact("a.b").matches("a.b", None));
+        assert!(!FilterRule::exact("a.b").matches("a.bc", None));
+        assert!(FilterRule::prefix("a.").matches("a.bc", None));
+        assert!(!FilterRule::prefix("a.").matches("b.a", None));
+        assert!(FilterRule::glob("a.*.c").matches("a.b.c", None));
+        assert!(!FilterRule::glob("a.*.c").matches("a.b.d", None));
+    }
+
+    #[test]
+    fn test_filter_rule_matches_tags() {
+        let rule = FilterRule::prefix("a.")
+            .with_tag("tier", "web")
+            .with_tag_value("canary");
+
+        assert!(rule.matches("a.b", Some("canary,tier:web")));
+        assert!(!rule.matches("a.b", Some("tier:web")));
+        assert!(!rule.matches("a.b", Some("tier:webs,canary")));
+        assert!(!rule.matches("a.b", Some("tiers:web,canary")));
+        assert!(!rule.matches("a.b", None));
+    }
+
+    #[test]
+    fn test_filtering_metric_sink_deny() {
+        let filter = MetricFilter::deny(vec![FilterRule::prefix("lib."), FilterRule::exact("app.noisy")]);
+        let (sink, writer) = new_sink(filter);
+
+        sink.emit("lib.requests:1|c").unwrap();
+        sink.emit("lib.errors:1|c").unwrap();
+        sink.emit("app.noisy:1|c").unwrap();
+        sink.emit("app.useful:1|c").unwrap();
+
+        assert_eq!("app.useful:1|c", written(&writer));
+        assert_eq!(
+            vec![(FilterRule::prefix("lib."), 2), (FilterRule::exact("app.noisy"), 1)],
+            sink.dropped()
+        );
+        assert_eq!(0, sink.dropped_unmatched());
+    }
+
+    #[test]
+    fn test_filtering_metric_sink_allow() {
+        let filter = MetricFilter::allow(vec![FilterRule::glob("app.*").with_tag("tier", "web")]);
+        let (sink, writer) = new_sink(filter);
+
+        sink.emit("app.requests:1|c|#tier:web").unwrap();
+        sink.emit("app.requests:1|c|#tier:db").unwrap();
+        sink.emit("lib.requests:1|c|#tier:web").unwrap();
+
+        assert_eq!("app.requests:1|c|#tier:web", written(&writer));
+        assert_eq!(2, sink.dropped_unmatched());
+    }
+
+    #[test]
+    fn test_filtering_metric_sink_emit_batch() {
+        let (sink, writer) = new_sink(MetricFilter::deny(vec![FilterRule::prefix("lib.")]));
+
+        sink.emit_batch(&["lib.requests:1|c", "app.useful:1|c", "lib.errors:1|c"])
+            .unwrap();
+        assert_eq!(0, sink.emit_batch(&["lib.requests:1|c"]).unwrap());
+
+        assert_eq!("app.useful:1|c", written(&writer));
+        assert_eq!(vec![(FilterRule::prefix("lib."), 3)], sink.dropped());
+    }
+
+    #[test]
+    fn test_filtering_metric_sink_reload() {
+        let (sink, writer) = new_sink(MetricFilter::deny(vec![FilterRule::exact("a")]));
+        let clone = sink.clone();
+
+        sink.emit("a:1|c").unwrap();
+        clone.reload(MetricFilter::deny(vec![FilterRule::exact("b")]));
+        sink.emit("a:1|c").unwrap();
+
+        assert_eq!("a:1|c", written(&writer));
+        assert_eq!(vec![(FilterRule::exact("b"), 0)], sink.dropped());
+    }
+}
@@ -0,0 +1,103 @@
+// Cadence - An extensible Statsd client for Rust!
+//
+// Copyright 2021 Nick Pillitteri
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::io::MultiLineWriter;
+use crossbeam_channel::{self, RecvTimeoutError, Sender};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Handle to a thread that flushes the buffer of a sink on an interval.
+///
+/// The thread only holds a weak reference to the buffer and stops as soon
+/// as this handle is dropped, along with the sink that owns it.
+#[derive(Debug)]
+pub(crate) struct PeriodicFlush {
+    // Never sent to, dropping it disconnects the channel the thread is
+    // waiting on which wakes it up to stop.
+    _stop: Sender<()>,
+}
+
+impl PeriodicFlush {
+    pub(crate) fn spawn<T>(buffer: &Arc<Mutex<MultiLineWriter<T>>>, interval: Duration) -> PeriodicFlush
+    where
+        T: Write + Send + 'static,
+    {
+        Self::spawn_all(std::slice::from_ref(buffer), interval)
+    }
+
+    /// Flush each of several buffers from a single thread, for sinks that
+    /// spread metrics over multiple buffers.
+    pub(crate) fn spawn_all<T>(buffers: &[Arc<Mutex<MultiLineWriter<T>>>], interval: Duration) -> PeriodicFlush
+    where
+        T: Write + Send + 'static,
+    {
+        let (tx, rx) = crossbeam_channel::bounded::<()>(0);
+        let buffers: Vec<_> = buffers.iter().map(Arc::downgrade).collect();
+
+        thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = rx.recv_timeout(interval) {
+                for buffer in buffers.iter() {
+                    let buffer = match buffer.upgrade() {
+                        Some(b) => b,
+                        None => return,
+                    };
+
+                    // There's no caller to return errors to from this thread, so
+                    // they're dropped. Metrics in the buffer are kept on error.
+                    let mut writer = buffer.lock().unwrap();
+                    if writer.has_pending() {
+                        let _ = writer.flush();
+                    }
+                }
+            }
+        });
+
+        PeriodicFlush { _stop: tx }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PeriodicFlush;
+    use crate::io::MultiLineWriter;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_periodic_flush() {
+        let buffer = Arc::new(Mutex::new(MultiLineWriter::new(Vec::new(), 64)));
+        let _flush = PeriodicFlush::spawn(&buffer, Duration::from_millis(10));
+
+        buffer.lock().unwrap().write_all(b"foo:1|c").unwrap();
+
+        let start = Instant::now();
+        while !buffer.lock().unwrap().get_ref().starts_with(b"foo:1|c\n") {
+            assert!(start.elapsed() < Duration::from_secs(5), "buffer was not flushed");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn test_periodic_flush_stops_with_buffer() {
+        let buffer = Arc::new(Mutex::new(MultiLineWriter::new(Vec::new(), 64)));
+        let weak = Arc::downgrade(&buffer);
+        let flush = PeriodicFlush::spawn(&buffer, Duration::from_millis(10));
+
+        drop(buffer);
+        thread::sleep(Duration::from_millis(30));
+        drop(flush);
+
+        assert!(weak.upgrade().is_none());
+    }
+}
@@ -9,17 +9,45 @@
 // except according to those terms.
 
 mod core;
+mod failover;
+mod fanout;
+mod filter;
+mod flush;
 mod queuing;
+mod reconnect;
+mod resolve;
+mod sharded;
+mod socket;
 mod spy;
+mod swappable;
+mod tcp;
 mod udp;
 
 pub use crate::sinks::core::{MetricSink, NopMetricSink};
-pub use crate::sinks::queuing::QueuingMetricSink;
+pub use crate::sinks::failover::{FailoverMetricSink, FailoverMetricSinkBuilder};
+pub use crate::sinks::fanout::{FanoutErrorPolicy, FanoutMetricSink, FanoutMetricSinkBuilder};
+pub use crate::sinks::filter::{FilterRule, FilteringMetricSink, MetricFilter};
+pub use crate::sinks::queuing::{OverflowPolicy, Priority, QueuingMetricSink, QueuingMetricSinkBuilder};
+pub use crate::sinks::reconnect::ConnectionOptions;
+pub use crate::sinks::resolve::{AddressResolver, ResolveOptions, SystemResolver};
+pub use crate::sinks::sharded::{ShardedMetricSink, ShardedMetricSinkBuilder};
+pub use crate::sinks::socket::SocketSendBuffer;
 pub use crate::sinks::spy::{BufferedSpyMetricSink, SpyMetricSink};
-pub use crate::sinks::udp::{BufferedUdpMetricSink, UdpMetricSink};
+pub use crate::sinks::swappable::SwappableMetricSink;
+pub use crate::sinks::tcp::{BufferedTcpMetricSink, TcpMetricSink};
+pub use crate::sinks::udp::{BufferedUdpMetricSink, StripedBufferedUdpMetricSink, UdpMetricSink};
+
+pub(crate) use crate::sinks::tcp::DEFAULT_BUFFER_SIZE as DEFAULT_TCP_BUFFER_SIZE;
+pub(crate) use crate::sinks::udp::DEFAULT_BUFFER_SIZE as DEFAULT_UDP_BUFFER_SIZE;
 
 #[cfg(unix)]
 mod unix;
+#[cfg(unix)]
+mod unix_stream;
 
+#[cfg(unix)]
+pub(crate) use crate::sinks::unix::DEFAULT_BUFFER_SIZE as DEFAULT_UNIX_BUFFER_SIZE;
 #[cfg(unix)]
 pub use crate::sinks::unix::{BufferedUnixMetricSink, UnixMetricSink};
+#[cfg(unix)]
+pub use crate::sinks::unix_stream::{BufferedUnixStreamMetricSink, UnixStreamMetricSink};
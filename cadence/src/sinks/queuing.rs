@@ -9,13 +9,327 @@
 // except according to those terms.
 
 use crate::sinks::core::MetricSink;
-use crossbeam_channel::{self, Receiver, Sender, TrySendError};
+use crate::sinks::filter::{self, FilterRule};
+use crate::types::MetricResult;
+use crossbeam_channel::{self, Receiver, RecvTimeoutError, Select, SendTimeoutError, Sender, TrySendError};
+use std::convert::TryFrom;
 use std::fmt;
 use std::io::{self, ErrorKind};
 use std::panic::RefUnwindSafe;
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
+
+/// Maximum number of metrics passed to the wrapped sink at once by each
+/// worker thread of a `QueuingMetricSink` with more than one thread, unless
+/// set by `QueuingMetricSinkBuilder::with_batch_size()`.
+const DEFAULT_BATCH_SIZE: usize = 64;
+
+/// What a `QueuingMetricSink` with a bounded queue does with new metrics
+/// when its queue is full.
+///
+/// The number of metrics affected by each policy is available from methods
+/// of the sink: `.dropped_newest()`, `.dropped_oldest()`, `.blocked()`, and
+/// `.sampled_out()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Reject new metrics while the queue is full, returning an error to the
+    /// caller. This is the default.
+    DropNewest,
+    /// Discard the oldest queued metric to make room for the new one. Queued
+    /// flushes are never discarded, so if the queue only holds flushes, wait
+    /// for the worker to take one off instead. The caller only sees an error
+    /// after the sink has been shut down.
+    DropOldest,
+    /// Wait up to the given amount of time for room in the queue, returning
+    /// an error to the caller if there still isn't any.
+    Block(Duration),
+    /// Once the queue is at least half full, only accept one in every `n` new
+    /// metrics, silently discarding the others, so that a sampled subset of
+    /// metrics keeps making it through while the wrapped sink catches up. New
+    /// metrics are rejected with an error while the queue is full.
+    Sampled(u32),
+}
+
+/// Priority class of metrics submitted to a `QueuingMetricSink`.
+///
+/// A queuing sink may have a separate queue ("lane") for each priority, each
+/// with its own capacity. Metrics are always taken from the highest priority
+/// lane that has any, so that when the wrapped sink can't keep up, metrics
+/// of lower priorities are the ones that wait and end up being dropped when
+/// their lanes are full. Metrics with a priority that doesn't have its own
+/// lane use the normal priority lane, which always exists.
+///
+/// Priorities are chosen for each metric by key patterns set with
+/// `QueuingMetricSinkBuilder::with_priority_rule()`, or explicitly with
+/// `QueuingMetricSink::emit_with_priority()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Metrics that should make it through even under backpressure, such as
+    /// counters used for SLOs.
+    High,
+    /// Metrics without any particular priority, the default.
+    Normal,
+    /// Metrics that are the first to be given up on, such as high volume
+    /// debugging histograms.
+    Low,
+}
+
+/// Function called with the index of a worker when it starts running.
+type WorkerHook = Arc<dyn Fn(usize) + Sync + Send + RefUnwindSafe + 'static>;
+
+/// Function used to run a worker instead of a dedicated thread.
+type WorkerExecutor = Arc<dyn Fn(Box<dyn FnOnce() + Send + 'static>) + Sync + Send + RefUnwindSafe + 'static>;
+
+/// How the threads running the workers of a queuing sink are started.
+#[derive(Clone, Default)]
+struct SpawnOptions {
+    thread_name: Option<String>,
+    stack_size: Option<usize>,
+    affinity: Option<WorkerHook>,
+    on_start: Option<WorkerHook>,
+    executor: Option<WorkerExecutor>,
+}
+
+impl SpawnOptions {
+    fn thread_name(&self, index: usize, workers: usize) -> Option<String> {
+        self.thread_name.as_ref().map(|name| {
+            if workers > 1 {
+                format!("{}-{}", name, index)
+            } else {
+                name.clone()
+            }
+        })
+    }
+
+    fn started(&self, index: usize) {
+        if let Some(affinity) = &self.affinity {
+            affinity(index);
+        }
+
+        if let Some(on_start) = &self.on_start {
+            on_start(index);
+        }
+    }
+}
+
+impl fmt::Debug for SpawnOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SpawnOptions")
+            .field("thread_name", &self.thread_name)
+            .field("stack_size", &self.stack_size)
+            .field("affinity", &self.affinity.is_some())
+            .field("on_start", &self.on_start.is_some())
+            .field("executor", &self.executor.is_some())
+            .finish()
+    }
+}
+
+/// Builder for creating a `QueuingMetricSink` with control over its queue and
+/// the threads that take metrics off of it.
+///
+/// Created via `QueuingMetricSink::builder()`. By default, the queue is unbounded
+/// and a single, unnamed, thread passes metrics to the wrapped sink one at a time,
+/// the same as `QueuingMetricSink::from()`.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use cadence::{MetricSink, NopMetricSink, OverflowPolicy, QueuingMetricSink};
+///
+/// let queuing = QueuingMetricSink::builder()
+///     .with_capacity(64 * 1024)
+///     .with_overflow_policy(OverflowPolicy::DropOldest)
+///     .with_workers(2)
+///     .with_thread_name("metrics")
+///     .with_on_start(|index| println!("metrics worker {} started", index))
+///     .build(NopMetricSink)
+///     .unwrap();
+///
+/// queuing.emit("foo.counter:1|c").unwrap();
+/// assert_eq!(Ok(()), queuing.shutdown(Duration::from_secs(1)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct QueuingMetricSinkBuilder {
+    capacity: Option<usize>,
+    policy: OverflowPolicy,
+    workers: usize,
+    batch_size: Option<usize>,
+    lanes: Vec<(Priority, usize)>,
+    rules: Vec<(FilterRule, Priority)>,
+    spawn: SpawnOptions,
+}
+
+impl QueuingMetricSinkBuilder {
+    fn new() -> Self {
+        QueuingMetricSinkBuilder {
+            capacity: None,
+            policy: OverflowPolicy::DropNewest,
+            workers: 1,
+            batch_size: None,
+            lanes: Vec::new(),
+            rules: Vec::new(),
+            spawn: SpawnOptions::default(),
+        }
+    }
+
+    /// Use a bounded queue of the given size instead of an unbounded one. See
+    /// `QueuingMetricSink::with_capacity()` for details. When there are lanes
+    /// for several priorities, this is the capacity of the normal priority lane.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Add a separate bounded lane of the given size for metrics of the given
+    /// priority. See `Priority` for details. Adding a lane for
+    /// `Priority::Normal` is the same as calling `.with_capacity()`.
+    pub fn with_lane(mut self, priority: Priority, capacity: usize) -> Self {
+        if priority == Priority::Normal {
+            return self.with_capacity(capacity);
+        }
+
+        self.lanes.retain(|(p, _)| *p != priority);
+        self.lanes.push((priority, capacity));
+        self
+    }
+
+    /// Give metrics matching the rule the given priority. Rules are checked in
+    /// the order they were added and the first match wins. Metrics that don't
+    /// match any rule have `Priority::Normal`.
+    pub fn with_priority_rule(mut self, rule: FilterRule, priority: Priority) -> Self {
+        self.rules.push((rule, priority));
+        self
+    }
+
+    /// Set what happens to new metrics when the bounded queue is full,
+    /// `OverflowPolicy::DropNewest` by default.
+    pub fn with_overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Set the number of threads taking metrics off the queue, one by default.
+    /// See `QueuingMetricSink::with_workers()` for details.
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// Set the maximum number of metrics passed to the wrapped sink at once. This
+    /// is one by default when using a single thread and 64 when using several.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = Some(batch_size.max(1));
+        self
+    }
+
+    /// Set the name of the threads running the workers. When there is more than
+    /// one worker, the index of each is appended to the name, e.g. `metrics-0`.
+    /// Threads are unnamed by default.
+    pub fn with_thread_name<S>(mut self, name: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.spawn.thread_name = Some(name.into());
+        self
+    }
+
+    /// Set the stack size, in bytes, of the threads running the workers. The
+    /// default is the same as any thread started by the standard library.
+    pub fn with_stack_size(mut self, size: usize) -> Self {
+        self.spawn.stack_size = Some(size);
+        self
+    }
+
+    /// Set a function called with the index of each worker from the thread it
+    /// is about to run in, intended for pinning the thread to a CPU. Cadence
+    /// doesn't set CPU affinity itself, use a crate suited to your platform to
+    /// do so in this function. It's called before the on-start callback.
+    pub fn with_affinity<F>(mut self, affinity: F) -> Self
+    where
+        F: Fn(usize) + Sync + Send + RefUnwindSafe + 'static,
+    {
+        self.spawn.affinity = Some(Arc::new(affinity));
+        self
+    }
+
+    /// Set a function called with the index of each worker from the thread it
+    /// is about to run in, after the affinity hook. It's called again if a worker
+    /// is restarted because the wrapped sink panicked. Panics in this function
+    /// aren't recovered from, the worker won't run.
+    pub fn with_on_start<F>(mut self, on_start: F) -> Self
+    where
+        F: Fn(usize) + Sync + Send + RefUnwindSafe + 'static,
+    {
+        self.spawn.on_start = Some(Arc::new(on_start));
+        self
+    }
+
+    /// Run the workers using the given function instead of dedicated threads.
+    ///
+    /// The function is given each worker as a closure to run, which blocks until
+    /// the sink is stopped. Thus the function must run it somewhere blocking
+    /// is acceptable, such as a thread pool meant for blocking tasks with room
+    /// for all the workers, otherwise metrics won't be taken off the queue. The
+    /// thread name and stack size options are ignored when using an executor and
+    /// threads can't be joined by `QueuingMetricSink::shutdown()`, though it still
+    /// waits for the workers to stop.
+    pub fn with_executor<F>(mut self, executor: F) -> Self
+    where
+        F: Fn(Box<dyn FnOnce() + Send + 'static>) + Sync + Send + RefUnwindSafe + 'static,
+    {
+        self.spawn.executor = Some(Arc::new(executor));
+        self
+    }
+
+    /// Construct a new `QueuingMetricSink` wrapping the given sink and start its
+    /// workers, returning an error if any threads couldn't be started.
+    pub fn build<T>(self, sink: T) -> MetricResult<QueuingMetricSink>
+    where
+        T: MetricSink + Sync + Send + RefUnwindSafe + 'static,
+    {
+        let sink = Arc::new(sink);
+        let flush_sink = Arc::clone(&sink);
+        let batch_size = match self.batch_size {
+            Some(v) => v,
+            None if self.workers > 1 => DEFAULT_BATCH_SIZE,
+            None => 1,
+        };
+
+        let mut worker = Worker::batched(self.capacity, move |batch: Vec<String>| {
+            let metrics: Vec<&str> = batch.iter().map(|v| v.as_str()).collect();
+            let _r = sink.emit_batch(&metrics);
+        })
+        .with_flush(move || {
+            let _r = flush_sink.flush();
+        })
+        .with_policy(self.policy)
+        .with_workers(self.workers)
+        .with_batch_size(batch_size)
+        .with_spawn_options(self.spawn);
+
+        for (priority, capacity) in self.lanes {
+            worker = worker.with_lane(priority, Some(capacity));
+        }
+
+        let worker = Arc::new(worker);
+
+        for index in 0..worker.workers {
+            if let Err(e) = spawn_worker(Arc::clone(&worker), index) {
+                // Make any threads that did start exit
+                worker.stop();
+                return Err(e.into());
+            }
+        }
+
+        Ok(QueuingMetricSink {
+            worker,
+            rules: self.rules.into(),
+        })
+    }
+}
 
 /// Implementation of a `MetricSink` that wraps another implementation
 /// and uses it to emit metrics asynchronously, in another thread.
@@ -40,7 +354,9 @@ use std::thread;
 /// created with a bounded queue, entries submitted to the sink will not be
 /// accepted if the queue is full. This means that the network thread must
 /// be able to keep up with the rate of entries submit to the queue or writes
-/// to this sink will begin to fail.
+/// to this sink will begin to fail. What happens to entries submitted while
+/// the queue is full can be changed with an `OverflowPolicy`. Metrics can
+/// also be split across several bounded queues by `Priority`.
 ///
 /// Entries already queued are guaranteed to be sent to the wrapped sink
 /// before the queuing sink is stopped. Meaning, the following code ends up
@@ -63,10 +379,19 @@ use std::thread;
 ///
 /// At the end of this code block, all metrics are guaranteed to be sent to
 /// the underlying wrapped metric sink before the thread used by the queuing
-/// sink is stopped.
+/// sink is stopped. However, the thread isn't waited on when the queuing
+/// sink is dropped so metrics still queued may be lost if the program exits
+/// right after. Use `.shutdown()` to wait for them to be sent.
+///
+/// Calling `.flush()` on this sink queues a request to flush the wrapped
+/// sink after every metric submitted before it, and returns without waiting
+/// for it. Use `.flush_and_wait()` to block until the flush has happened. If
+/// a bounded queue is full, the flush is rejected with an error with the kind
+/// `ErrorKind::WouldBlock` so that it can be told apart from a rejected metric.
 #[derive(Debug, Clone)]
 pub struct QueuingMetricSink {
     worker: Arc<Worker>,
+    rules: Arc<[(FilterRule, Priority)]>,
 }
 
 impl QueuingMetricSink {
@@ -104,7 +429,7 @@ impl QueuingMetricSink {
     where
         T: MetricSink + Sync + Send + RefUnwindSafe + 'static,
     {
-        Self::with_optional_capacity(sink, None)
+        Self::spawn(sink, Self::builder())
     }
 
     /// Construct a new `QueuingMetricSink` instance wrapping another sink
@@ -141,19 +466,83 @@ impl QueuingMetricSink {
     where
         T: MetricSink + Sync + Send + RefUnwindSafe + 'static,
     {
-        Self::with_optional_capacity(sink, Some(capacity))
+        Self::spawn(sink, Self::builder().with_capacity(capacity))
     }
 
-    fn with_optional_capacity<T>(sink: T, capacity: Option<usize>) -> Self
+    /// Construct a new `QueuingMetricSink` instance wrapping another sink
+    /// implementation with a queue of the given size connecting them and
+    /// the given policy for handling metrics when the queue is full.
+    ///
+    /// This is otherwise the same as `QueuingMetricSink::with_capacity()`,
+    /// which uses `OverflowPolicy::DropNewest`. See `OverflowPolicy` for the
+    /// available policies.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cadence::{MetricSink, NopMetricSink, OverflowPolicy, QueuingMetricSink};
+    ///
+    /// let queuing = QueuingMetricSink::with_overflow_policy(NopMetricSink, 1024, OverflowPolicy::DropOldest);
+    /// queuing.emit("foo.counter:1|c").unwrap();
+    /// ```
+    pub fn with_overflow_policy<T>(sink: T, capacity: usize, policy: OverflowPolicy) -> Self
     where
         T: MetricSink + Sync + Send + RefUnwindSafe + 'static,
     {
-        let worker = Arc::new(Worker::new(capacity, move |v: String| {
-            let _r = sink.emit(&v);
-        }));
-        spawn_worker_in_thread(Arc::clone(&worker));
+        Self::spawn(
+            sink,
+            Self::builder().with_capacity(capacity).with_overflow_policy(policy),
+        )
+    }
+
+    /// Construct a new `QueuingMetricSink` instance wrapping another sink
+    /// implementation with a queue of the given size connecting them, and
+    /// the given number of threads taking metrics off the queue.
+    ///
+    /// Each thread takes up to 64 metrics off the queue at once, as long as
+    /// they are available without waiting, and passes them to the wrapped
+    /// sink with a single call to `.emit_batch()`. Sinks that implement that
+    /// method efficiently, like the buffered sinks, can then send them with
+    /// less overhead than one call to `.emit()` per metric. The wrapped sink
+    /// is called concurrently from all threads so metrics may be emitted in
+    /// a different order than they were queued.
+    ///
+    /// A flush of this sink still only flushes the wrapped sink after every
+    /// metric queued before it has been passed to the wrapped sink. If the
+    /// wrapped sink panics, the remaining metrics of the batch it was given
+    /// are lost.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::net::UdpSocket;
+    /// use cadence::{BufferedUdpMetricSink, QueuingMetricSink, DEFAULT_PORT};
+    ///
+    /// let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+    /// let host = ("metrics.example.com", DEFAULT_PORT);
+    /// let udp_sink = BufferedUdpMetricSink::from(host, socket).unwrap();
+    /// let queuing_sink = QueuingMetricSink::with_workers(udp_sink, 512 * 1024, 4);
+    /// ```
+    pub fn with_workers<T>(sink: T, capacity: usize, workers: usize) -> Self
+    where
+        T: MetricSink + Sync + Send + RefUnwindSafe + 'static,
+    {
+        Self::spawn(sink, Self::builder().with_capacity(capacity).with_workers(workers))
+    }
+
+    /// Create a builder for a `QueuingMetricSink` to control the options that
+    /// don't have a dedicated constructor, like the names of worker threads.
+    pub fn builder() -> QueuingMetricSinkBuilder {
+        QueuingMetricSinkBuilder::new()
+    }
 
-        QueuingMetricSink { worker }
+    // Build a sink for constructors that, like `thread::spawn()`, panic if threads
+    // can't be started.
+    fn spawn<T>(sink: T, builder: QueuingMetricSinkBuilder) -> Self
+    where
+        T: MetricSink + Sync + Send + RefUnwindSafe + 'static,
+    {
+        builder.build(sink).expect("failed to spawn worker thread")
     }
 
     /// Return the number of times the wrapped sink or underlying worker thread
@@ -182,23 +571,210 @@ impl QueuingMetricSink {
     pub fn drained(&self) -> u64 {
         self.worker.stats.drained()
     }
-}
 
-impl MetricSink for QueuingMetricSink {
-    fn emit(&self, metric: &str) -> io::Result<usize> {
-        match self.worker.submit(metric.to_string()) {
+    /// Return the number of new metrics rejected because the queue was full.
+    /// This includes metrics that couldn't be queued before the timeout when
+    /// using `OverflowPolicy::Block`.
+    pub fn dropped_newest(&self) -> u64 {
+        self.worker.stats.dropped_newest()
+    }
+
+    /// Return the number of queued metrics discarded to make room for new ones
+    /// when using `OverflowPolicy::DropOldest`.
+    pub fn dropped_oldest(&self) -> u64 {
+        self.worker.stats.dropped_oldest()
+    }
+
+    /// Return the number of metrics that had to wait for room in the queue when
+    /// using `OverflowPolicy::Block`, whether or not they were queued in the end.
+    pub fn blocked(&self) -> u64 {
+        self.worker.stats.blocked()
+    }
+
+    /// Return the number of metrics discarded by sampling when using
+    /// `OverflowPolicy::Sampled`.
+    pub fn sampled_out(&self) -> u64 {
+        self.worker.stats.sampled_out()
+    }
+
+    /// Return the number of metrics rejected because the worker thread was no
+    /// longer accepting them, after `.shutdown()` was called.
+    pub fn dropped_disconnected(&self) -> u64 {
+        self.worker.stats.dropped_disconnected()
+    }
+
+    /// Return the total number of metrics submitted to this sink that will never
+    /// be passed to the wrapped sink, for any reason. This is the sum of
+    /// `.dropped_newest()`, `.dropped_oldest()`, `.sampled_out()`, and
+    /// `.dropped_disconnected()`.
+    pub fn dropped(&self) -> u64 {
+        self.worker.stats.dropped()
+    }
+
+    /// Return the largest number of metrics that have been queued at once. Like
+    /// `.queued()`, this is approximate.
+    pub fn queued_high_water(&self) -> u64 {
+        self.worker.stats.queued_high_water()
+    }
+
+    /// Return the average amount of time metrics spent in the queue before
+    /// being passed to the wrapped sink, or zero if none have been yet.
+    pub fn queue_time_avg(&self) -> Duration {
+        self.worker.stats.queue_time_avg()
+    }
+
+    /// Return the longest amount of time a metric spent in the queue before
+    /// being passed to the wrapped sink.
+    pub fn queue_time_max(&self) -> Duration {
+        self.worker.stats.queue_time_max()
+    }
+
+    /// Return the number of metrics successfully submitted to the lane used for
+    /// the given priority. See `Priority` for details.
+    pub fn submitted_for(&self, priority: Priority) -> u64 {
+        self.worker.lane(priority).submitted.load(Ordering::Acquire)
+    }
+
+    /// Return the number of metrics submitted to the lane used for the given
+    /// priority that will never be passed to the wrapped sink, for any of the
+    /// reasons counted by `.dropped()`.
+    pub fn dropped_for(&self, priority: Priority) -> u64 {
+        self.worker.lane(priority).dropped.load(Ordering::Acquire)
+    }
+
+    /// Submit a metric with the given priority instead of the one picked by the
+    /// priority rules of this sink. See `Priority` for details.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use cadence::{NopMetricSink, Priority, QueuingMetricSink};
+    ///
+    /// let queuing = QueuingMetricSink::builder()
+    ///     .with_capacity(1024)
+    ///     .with_lane(Priority::High, 128)
+    ///     .build(NopMetricSink)
+    ///     .unwrap();
+    ///
+    /// queuing.emit_with_priority("api.errors:1|c", Priority::High).unwrap();
+    /// assert_eq!(1, queuing.submitted_for(Priority::High));
+    /// ```
+    pub fn emit_with_priority(&self, metric: &str, priority: Priority) -> io::Result<usize> {
+        match self.worker.submit(metric.to_string(), priority) {
             Err(TrySendError::Disconnected(_)) => Err(io::Error::new(ErrorKind::Other, "channel disconnected")),
             Err(TrySendError::Full(_)) => Err(io::Error::new(ErrorKind::Other, "channel full")),
             Ok(_) => Ok(metric.len()),
         }
     }
+
+    // Priority of the metric according to the first rule it matches.
+    fn priority(&self, metric: &str) -> Priority {
+        if self.rules.is_empty() {
+            return Priority::Normal;
+        }
+
+        let (key, tags) = filter::parse_metric(metric);
+        self.rules
+            .iter()
+            .find(|(rule, _)| rule.matches(key, tags))
+            .map(|(_, priority)| *priority)
+            .unwrap_or(Priority::Normal)
+    }
+
+    /// Flush the wrapped sink and block until every metric submitted to this
+    /// sink before the call has been passed to the wrapped sink and it has
+    /// been flushed, or until the timeout expires.
+    ///
+    /// If the queue is bounded and full, this waits for space in it as part of
+    /// the same timeout. An error with the kind `ErrorKind::TimedOut` is returned
+    /// if the flush didn't complete in time. Note that the flush will still
+    /// happen eventually in that case. Like emitted metrics, errors from
+    /// flushing the wrapped sink are discarded.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use cadence::{MetricSink, NopMetricSink, QueuingMetricSink};
+    ///
+    /// let queuing = QueuingMetricSink::from(NopMetricSink);
+    /// queuing.emit("foo.counter:1|c").unwrap();
+    /// queuing.flush_and_wait(Duration::from_secs(1)).unwrap();
+    ///
+    /// assert_eq!(0, queuing.queued());
+    /// ```
+    pub fn flush_and_wait(&self, timeout: Duration) -> io::Result<()> {
+        let deadline = Instant::now() + timeout;
+        let (tx, rx) = crossbeam_channel::bounded(1);
+
+        match self.worker.send_flush(Some(tx), Some(deadline)) {
+            Err(SendTimeoutError::Disconnected(_)) => {
+                return Err(io::Error::new(ErrorKind::Other, "channel disconnected"))
+            }
+            Err(SendTimeoutError::Timeout(_)) => return Err(io::Error::new(ErrorKind::TimedOut, "channel full")),
+            Ok(_) => {}
+        }
+
+        match rx.recv_deadline(deadline) {
+            // The worker drops the acknowledgement channel without using it if
+            // it stopped or the wrapped sink panicked while flushing.
+            Err(RecvTimeoutError::Disconnected) => Err(io::Error::new(ErrorKind::Other, "flush not completed")),
+            Err(RecvTimeoutError::Timeout) => Err(io::Error::new(ErrorKind::TimedOut, "flush timed out")),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    /// Stop the worker thread after sending all queued metrics to the wrapped
+    /// sink, waiting up to the given timeout for it to finish.
+    ///
+    /// After this method is called, all metrics and flushes submitted to this
+    /// sink (or any of its clones) are rejected. Metrics already queued are
+    /// passed to the wrapped sink, the wrapped sink is flushed, and the worker
+    /// thread is joined. Return `Ok(())` if all of this happened in time.
+    ///
+    /// If the timeout expires first, return an error with the number of
+    /// metrics that were still queued. Note that this may be `0` when the
+    /// worker thread is still busy with the last metrics it took off the
+    /// queue or with flushing the wrapped sink. Metrics and the flush that
+    /// weren't done in time are abandoned to the worker thread which keeps
+    /// running in the background until they are processed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use cadence::{MetricSink, NopMetricSink, QueuingMetricSink};
+    ///
+    /// let queuing = QueuingMetricSink::from(NopMetricSink);
+    /// queuing.emit("foo.counter:1|c").unwrap();
+    ///
+    /// assert_eq!(Ok(()), queuing.shutdown(Duration::from_secs(1)));
+    /// assert!(queuing.emit("foo.counter:2|c").is_err());
+    /// ```
+    pub fn shutdown(&self, timeout: Duration) -> Result<(), u64> {
+        self.worker.shutdown(timeout)
+    }
+}
+
+impl MetricSink for QueuingMetricSink {
+    fn emit(&self, metric: &str) -> io::Result<usize> {
+        self.emit_with_priority(metric, self.priority(metric))
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        match self.worker.send_flush(None, None) {
+            Err(SendTimeoutError::Disconnected(_)) => Err(io::Error::new(ErrorKind::Other, "channel disconnected")),
+            Err(SendTimeoutError::Timeout(_)) => Err(io::Error::new(ErrorKind::WouldBlock, "channel full")),
+            Ok(_) => Ok(()),
+        }
+    }
 }
 
 impl Drop for QueuingMetricSink {
     /// Send the worker a signal to stop processing metrics.
     ///
     /// Note that this destructor only sends the worker thread a signal to
-    /// stop, it doesn't wait for it to stop.
+    /// stop, it doesn't wait for it to stop. See `.shutdown()` for that.
     fn drop(&mut self) {
         self.worker.stop();
     }
@@ -206,14 +782,22 @@ impl Drop for QueuingMetricSink {
 
 /// Statistics about the worker running.
 ///
-/// These statistics are only used for unit testing to verify that our
-/// sentinel can handle thread panics and restart the thread the worker
-/// is running in.
+/// Besides being exposed by the queuing sink, the number of panics is used
+/// for unit testing to verify that our sentinel can handle thread panics and
+/// restart the thread the worker is running in.
 #[derive(Debug)]
 struct WorkerStats {
     panics: AtomicU64,
     submitted: AtomicU64,
     drained: AtomicU64,
+    dropped_newest: AtomicU64,
+    dropped_oldest: AtomicU64,
+    blocked: AtomicU64,
+    sampled_out: AtomicU64,
+    dropped_disconnected: AtomicU64,
+    queued_high_water: AtomicU64,
+    queue_time_total_nanos: AtomicU64,
+    queue_time_max_nanos: AtomicU64,
 }
 
 impl WorkerStats {
@@ -222,6 +806,14 @@ impl WorkerStats {
             panics: AtomicU64::new(0),
             submitted: AtomicU64::new(0),
             drained: AtomicU64::new(0),
+            dropped_newest: AtomicU64::new(0),
+            dropped_oldest: AtomicU64::new(0),
+            blocked: AtomicU64::new(0),
+            sampled_out: AtomicU64::new(0),
+            dropped_disconnected: AtomicU64::new(0),
+            queued_high_water: AtomicU64::new(0),
+            queue_time_total_nanos: AtomicU64::new(0),
+            queue_time_max_nanos: AtomicU64::new(0),
         }
     }
 
@@ -249,29 +841,125 @@ impl WorkerStats {
         self.drained.load(Ordering::Acquire)
     }
 
+    fn incr_dropped_newest(&self) {
+        self.dropped_newest.fetch_add(1, Ordering::Release);
+    }
+
+    fn dropped_newest(&self) -> u64 {
+        self.dropped_newest.load(Ordering::Acquire)
+    }
+
+    fn incr_dropped_oldest(&self) {
+        self.dropped_oldest.fetch_add(1, Ordering::Release);
+    }
+
+    fn dropped_oldest(&self) -> u64 {
+        self.dropped_oldest.load(Ordering::Acquire)
+    }
+
+    fn incr_blocked(&self) {
+        self.blocked.fetch_add(1, Ordering::Release);
+    }
+
+    fn blocked(&self) -> u64 {
+        self.blocked.load(Ordering::Acquire)
+    }
+
+    fn incr_sampled_out(&self) {
+        self.sampled_out.fetch_add(1, Ordering::Release);
+    }
+
+    fn sampled_out(&self) -> u64 {
+        self.sampled_out.load(Ordering::Acquire)
+    }
+
+    fn incr_dropped_disconnected(&self) {
+        self.dropped_disconnected.fetch_add(1, Ordering::Release);
+    }
+
+    fn dropped_disconnected(&self) -> u64 {
+        self.dropped_disconnected.load(Ordering::Acquire)
+    }
+
+    fn dropped(&self) -> u64 {
+        self.dropped_newest() + self.dropped_oldest() + self.sampled_out() + self.dropped_disconnected()
+    }
+
+    fn update_queued_high_water(&self) {
+        self.queued_high_water.fetch_max(self.queued(), Ordering::AcqRel);
+    }
+
+    fn queued_high_water(&self) -> u64 {
+        self.queued_high_water.load(Ordering::Acquire)
+    }
+
+    fn record_queue_time(&self, elapsed: Duration) {
+        // Saturate instead of wrapping, ~584 years of queue time won't happen.
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        self.queue_time_total_nanos.fetch_add(nanos, Ordering::Release);
+        self.queue_time_max_nanos.fetch_max(nanos, Ordering::AcqRel);
+    }
+
+    fn queue_time_avg(&self) -> Duration {
+        let total = self.queue_time_total_nanos.load(Ordering::Acquire);
+        match self.drained() {
+            0 => Duration::from_nanos(0),
+            drained => Duration::from_nanos(total / drained),
+        }
+    }
+
+    fn queue_time_max(&self) -> Duration {
+        Duration::from_nanos(self.queue_time_max_nanos.load(Ordering::Acquire))
+    }
+
     fn queued(&self) -> u64 {
         let submitted = self.submitted.load(Ordering::Acquire);
-        let drained = self.drained.load(Ordering::Acquire);
+        // Metrics discarded from the queue were submitted but won't ever be drained
+        let removed = self.drained.load(Ordering::Acquire) + self.dropped_oldest.load(Ordering::Acquire);
 
-        if submitted > drained {
-            submitted - drained
-        } else {
-            0
-        }
+        submitted.saturating_sub(removed)
     }
 }
 
-/// Create a thread and run the worker in it to completion
+/// Create a thread, or use the configured executor, and run the worker in it
+/// to completion
 ///
 /// This function uses a `Sentinel` struct to make sure that any panics from
 /// running the worker result in another thread being spawned to start running
-/// the worker again.
-fn spawn_worker_in_thread(worker: Arc<Worker>) -> thread::JoinHandle<()> {
-    thread::spawn(move || {
-        let mut sentinel = Sentinel::new(&worker);
-        worker.run();
+/// the worker again. The index identifies which of the worker threads this is
+/// so that its handle can be replaced when that happens.
+fn spawn_worker(worker: Arc<Worker>, index: usize) -> io::Result<()> {
+    // Hold the lock while spawning so that a handle for a thread started by the
+    // sentinel (if the new thread panics right away) can't be replaced by this one.
+    let mut handles = worker.handles.lock().unwrap();
+    let runner = Arc::clone(&worker);
+
+    let body = move || {
+        runner.spawn.started(index);
+        let mut sentinel = Sentinel::new(&runner, index);
+        runner.run();
         sentinel.cancel();
-    })
+    };
+
+    if let Some(executor) = &worker.spawn.executor {
+        executor(Box::new(body));
+        return Ok(());
+    }
+
+    let mut builder = thread::Builder::new();
+    if let Some(name) = worker.spawn.thread_name(index, worker.workers) {
+        builder = builder.name(name);
+    }
+    if let Some(size) = worker.spawn.stack_size {
+        builder = builder.stack_size(size);
+    }
+
+    if handles.len() <= index {
+        handles.resize_with(index + 1, || None);
+    }
+
+    handles[index] = Some(builder.spawn(body)?);
+    Ok(())
 }
 
 /// Struct for ensuring a worker runs to completion correctly, without
@@ -283,12 +971,17 @@ fn spawn_worker_in_thread(worker: Arc<Worker>) -> thread::JoinHandle<()> {
 #[derive(Debug)]
 struct Sentinel<'a> {
     worker: &'a Arc<Worker>,
+    index: usize,
     active: bool,
 }
 
 impl<'a> Sentinel<'a> {
-    fn new(worker: &'a Arc<Worker>) -> Sentinel<'a> {
-        Sentinel { worker, active: true }
+    fn new(worker: &'a Arc<Worker>, index: usize) -> Sentinel<'a> {
+        Sentinel {
+            worker,
+            index,
+            active: true,
+        }
     }
 
     fn cancel(&mut self) {
@@ -304,91 +997,612 @@ impl<'a> Drop for Sentinel<'a> {
             // that this was a panic and spawn a new thread with an Arc of
             // the worker.
             self.worker.stats.incr_panic();
-            spawn_worker_in_thread(Arc::clone(self.worker));
+            // There's nobody to report an error to if a new thread can't be
+            // started, the queue will fill up and new metrics will be dropped.
+            let _ = spawn_worker(Arc::clone(self.worker), self.index);
         }
     }
 }
 
-/// Worker to repeatedly run a method consuming entries via a channel.
-///
-/// The `.run()` method of the worker is intended to be in a separate
-/// thread (thread B). Meanwhile, the `.submit()`, `.stop()`,
-/// `.stop_and_wait()`, and `.is_stopped()` methods are meant to be called
-/// from the main thread (thread A).
-///
-/// This worker is stopped by receiving a "poison pill" message in the
-/// channel that it is consuming messages from. Thus, calls to `.submit()`,
-/// consuming messages in '.run()`, and `.stop()` typically involve no
-/// locking.
-///
-/// However, in order to enable easier testing, after it stops receiving
-/// messages the `.run()` method will use an atomic "stopped" flag to
-/// allow callers waiting on a conditional variable (callers using
-/// `.stop_and_wait()`) to wake up after the worker finally stops.
+/// Entries in the lanes consumed by a worker.
+#[derive(Debug)]
+enum Message {
+    /// A metric along with the time it was submitted.
+    Metric(String, Instant),
+    /// Run the flush method of the worker, then signal the sender of the
+    /// marker, if any.
+    Flush(Arc<Marker>),
+    /// Stop one of the threads running the worker.
+    Stop(Arc<Marker>),
+}
+
+impl Message {
+    fn marker(&self) -> Option<&Marker> {
+        match self {
+            Message::Metric(..) => None,
+            Message::Flush(m) | Message::Stop(m) => Some(m),
+        }
+    }
+}
+
+/// Flush or stop request sent to every lane of a worker.
 ///
-/// If you're just trying to make use of this worker you don't need to
-/// worry about this, just call `.submit()`, `.run()`, and `.stop()`.
-/// But, if you're wondering why the stopped flag and methods to wait
-/// for it or inspect it even exist: testing is the reason.
-struct Worker {
-    task: Box<dyn Fn(String) + Sync + Send + RefUnwindSafe + 'static>,
-    sender: Sender<Option<String>>,
-    receiver: Receiver<Option<String>>,
-    stopped: AtomicBool,
-    stats: WorkerStats,
+/// Lanes are drained in priority order rather than in the order entries were
+/// sent so a request only applies once it has been taken off of every lane,
+/// at which point every metric sent before it has been taken as well. The
+/// thread that takes it off of the last lane carries out the request.
+#[derive(Debug)]
+struct Marker {
+    remaining: AtomicUsize,
+    cancelled: AtomicBool,
+    ack: Option<Sender<()>>,
+}
+
+impl Marker {
+    fn new(lanes: usize, ack: Option<Sender<()>>) -> Arc<Marker> {
+        Arc::new(Marker {
+            remaining: AtomicUsize::new(lanes),
+            cancelled: AtomicBool::new(false),
+            ack,
+        })
+    }
+
+    // Record that the marker was taken off a lane, returning true if the
+    // request should be carried out by the caller.
+    fn take(&self) -> bool {
+        self.remaining.fetch_sub(1, Ordering::AcqRel) == 1 && !self.cancelled.load(Ordering::Acquire)
+    }
+
+    // Give up on the request since it couldn't be sent to some of the lanes.
+    fn cancel(&self, unsent: usize) {
+        self.cancelled.store(true, Ordering::Release);
+        self.remaining.fetch_sub(unsent, Ordering::AcqRel);
+    }
+}
+
+/// Channel for metrics of one priority along with counts of the metrics
+/// submitted to it and dropped from it.
+#[derive(Debug)]
+struct Lane {
+    priority: Priority,
+    sender: Sender<Message>,
+    receiver: Receiver<Message>,
+    submitted: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl Lane {
+    fn new(priority: Priority, capacity: Option<usize>) -> Lane {
+        let (sender, receiver) = if let Some(v) = capacity {
+            crossbeam_channel::bounded(v)
+        } else {
+            crossbeam_channel::unbounded()
+        };
+
+        Lane {
+            priority,
+            sender,
+            receiver,
+            submitted: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    fn incr_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// Marks a batch of metrics taken off a lane as processed when dropped, even
+/// if processing them panicked, so that flushes waiting on the batch don't
+/// wait forever.
+struct Completion<'a> {
+    worker: &'a Worker,
+    start: u64,
+}
+
+impl<'a> Drop for Completion<'a> {
+    fn drop(&mut self) {
+        let (lock, cvar) = &self.worker.in_flight;
+        let mut in_flight = lock.lock().unwrap();
+        if let Some(i) = in_flight.iter().position(|s| *s == self.start) {
+            in_flight.swap_remove(i);
+        }
+
+        cvar.notify_all();
+    }
+}
+
+/// Hands a flush or stop request taken along with a batch of metrics over to
+/// the thread that replaces this one if processing the batch panics, so that
+/// the request isn't lost.
+struct Handoff<'a> {
+    worker: &'a Worker,
+    request: Option<(Message, u64)>,
+}
+
+impl<'a> Drop for Handoff<'a> {
+    fn drop(&mut self) {
+        if let Some(request) = self.request.take() {
+            self.worker.orphaned.lock().unwrap().push(request);
+        }
+    }
+}
+
+/// Worker to repeatedly run a method consuming entries via channels.
+///
+/// The `.run()` method of the worker is intended to be in one or more
+/// separate threads (thread B). Meanwhile, the `.submit()`, `.stop()`,
+/// `.stop_and_wait()`, and `.is_stopped()` methods are meant to be called
+/// from the main thread (thread A).
+///
+/// Entries are submitted to one of several channels ("lanes"), one for
+/// each priority, and always taken from the highest priority lane that has
+/// any. This worker is stopped by receiving a "poison pill" message in every
+/// lane, once for each thread running it. Thus, calls to `.submit()` and
+/// `.stop()` typically involve no locking. Consuming messages in `.run()`
+/// holds a lock while taking a batch of entries off a lane so that a flush
+/// knows exactly which entries came before it, and can wait for other threads
+/// to finish processing them.
+///
+/// However, in order to enable easier testing, after it stops receiving
+/// messages the `.run()` method will use an atomic "stopped" flag to
+/// allow callers waiting on a conditional variable (callers using
+/// `.stop_and_wait()`) to wake up after the worker finally stops.
+///
+/// If you're just trying to make use of this worker you don't need to
+/// worry about this, just call `.submit()`, `.run()`, and `.stop()`.
+/// But, if you're wondering why the stopped flag and methods to wait
+/// for it or inspect it even exist: testing is the reason.
+///
+/// The `.shutdown()` method is the exception to this: it closes the worker
+/// to new entries, blocks to send the poison pills even if the lanes are
+/// full, and then waits for the threads running the worker to exit.
+struct Worker {
+    task: Box<dyn Fn(Vec<String>) + Sync + Send + RefUnwindSafe + 'static>,
+    flush: Box<dyn Fn() + Sync + Send + RefUnwindSafe + 'static>,
+    lanes: Vec<Lane>,
+    policy: OverflowPolicy,
+    sampled: AtomicU64,
+    workers: usize,
+    batch_size: usize,
+    taken: Mutex<u64>,
+    in_flight: (Mutex<Vec<u64>>, Condvar),
+    orphaned: Mutex<Vec<(Message, u64)>>,
+    running: AtomicUsize,
+    closed: AtomicBool,
+    stopped: AtomicBool,
+    joined: AtomicBool,
+    done: (Sender<()>, Receiver<()>),
+    handles: Mutex<Vec<Option<thread::JoinHandle<()>>>>,
+    spawn: SpawnOptions,
+    stats: WorkerStats,
 }
 
 impl Worker {
+    // Create a worker that runs the task for one entry at a time, which is
+    // simpler for unit testing.
+    #[cfg(test)]
     fn new<F>(capacity: Option<usize>, task: F) -> Self
     where
         F: Fn(String) + Sync + Send + RefUnwindSafe + 'static,
     {
-        let (tx, rx) = Self::get_channels(capacity);
+        Self::batched(capacity, move |batch: Vec<String>| {
+            for v in batch {
+                task(v);
+            }
+        })
+    }
+
+    fn batched<F>(capacity: Option<usize>, task: F) -> Self
+    where
+        F: Fn(Vec<String>) + Sync + Send + RefUnwindSafe + 'static,
+    {
         Worker {
             task: Box::new(task),
-            sender: tx,
-            receiver: rx,
+            flush: Box::new(|| {}),
+            lanes: vec![Lane::new(Priority::Normal, capacity)],
+            policy: OverflowPolicy::DropNewest,
+            sampled: AtomicU64::new(0),
+            workers: 1,
+            batch_size: 1,
+            taken: Mutex::new(0),
+            in_flight: (Mutex::new(Vec::new()), Condvar::new()),
+            orphaned: Mutex::new(Vec::new()),
+            running: AtomicUsize::new(1),
+            closed: AtomicBool::new(false),
             stopped: AtomicBool::new(false),
+            joined: AtomicBool::new(false),
+            done: crossbeam_channel::unbounded(),
+            handles: Mutex::new(Vec::new()),
+            spawn: SpawnOptions::default(),
             stats: WorkerStats::new(),
         }
     }
 
-    fn get_channels(capacity: Option<usize>) -> (Sender<Option<String>>, Receiver<Option<String>>) {
-        if let Some(v) = capacity {
-            crossbeam_channel::bounded(v)
-        } else {
-            crossbeam_channel::unbounded()
-        }
+    fn with_flush<F>(mut self, flush: F) -> Self
+    where
+        F: Fn() + Sync + Send + RefUnwindSafe + 'static,
+    {
+        self.flush = Box::new(flush);
+        self
+    }
+
+    fn with_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self.running = AtomicUsize::new(self.workers);
+        self
+    }
+
+    fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    fn with_spawn_options(mut self, spawn: SpawnOptions) -> Self {
+        self.spawn = spawn;
+        self
     }
 
-    fn submit(&self, v: String) -> Result<(), TrySendError<Option<String>>> {
-        let res = self.sender.try_send(Some(v));
-        if res.is_ok() {
-            self.stats.incr_submitted();
+    // Use a lane with the given capacity for metrics of the given priority,
+    // replacing any existing lane for it.
+    fn with_lane(mut self, priority: Priority, capacity: Option<usize>) -> Self {
+        self.lanes.retain(|l| l.priority != priority);
+        self.lanes.push(Lane::new(priority, capacity));
+        self.lanes.sort_by_key(|l| l.priority);
+        self
+    }
+
+    // Get the lane for metrics of the given priority, which is the normal
+    // priority lane if there isn't one specifically for it.
+    fn lane(&self, priority: Priority) -> &Lane {
+        let find = |p| self.lanes.iter().find(|l| l.priority == p);
+        // There's always a lane for normal priority metrics
+        find(priority).or_else(|| find(Priority::Normal)).unwrap()
+    }
+
+    fn submit(&self, v: String, priority: Priority) -> Result<(), TrySendError<Message>> {
+        let lane = self.lane(priority);
+        let msg = Message::Metric(v, Instant::now());
+        let res = match self.policy {
+            OverflowPolicy::DropNewest => self.send(lane, msg),
+            OverflowPolicy::DropOldest => self.send_dropping_oldest(lane, msg),
+            OverflowPolicy::Block(timeout) => self.send_blocking(lane, msg, timeout),
+            OverflowPolicy::Sampled(n) => {
+                if !self.sample(lane, n) {
+                    self.stats.incr_sampled_out();
+                    lane.incr_dropped();
+                    return Ok(());
+                }
+
+                self.send(lane, msg)
+            }
+        };
+
+        match res {
+            Ok(_) => {
+                self.stats.incr_submitted();
+                self.stats.update_queued_high_water();
+                lane.submitted.fetch_add(1, Ordering::Release);
+            }
+            Err(TrySendError::Full(_)) => {
+                self.stats.incr_dropped_newest();
+                lane.incr_dropped();
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                self.stats.incr_dropped_disconnected();
+                lane.incr_dropped();
+            }
         }
 
         res
     }
 
-    fn run(&self) {
-        for opt in self.receiver.iter() {
-            if let Some(v) = opt {
+    // Try to send a metric, discarding the oldest queued metric to make room
+    // for it if the lane is full. Flushes and stops removed from the front of
+    // the lane while looking for a metric to discard are never lost: they're
+    // sent again, only delayed, which is fine since they still come after the
+    // metrics they're meant to follow. If there are no metrics to discard, wait
+    // for the worker to make room instead.
+    fn send_dropping_oldest(&self, lane: &Lane, msg: Message) -> Result<(), TrySendError<Message>> {
+        let mut msg = msg;
+
+        loop {
+            match self.send(lane, msg) {
+                Err(TrySendError::Full(m)) => msg = m,
+                res => return res,
+            }
+
+            let mut markers = Vec::new();
+            let dropped = loop {
+                match lane.receiver.try_recv() {
+                    Ok(Message::Metric(..)) => {
+                        self.stats.incr_dropped_oldest();
+                        lane.incr_dropped();
+                        break true;
+                    }
+                    Ok(other) => markers.push(other),
+                    Err(_) => break false,
+                }
+            };
+
+            // There's room for these unless other producers took it, in which
+            // case wait for the worker to make more. The lane can only be
+            // disconnected if the worker is gone, and the markers with it.
+            for marker in markers {
+                let _ = lane.sender.send(marker);
+            }
+
+            if !dropped {
+                return self.send_waiting(lane, msg);
+            }
+        }
+    }
+
+    // Send a message waiting for room for as long as it takes, unless the
+    // worker is shut down in the meantime.
+    fn send_waiting(&self, lane: &Lane, msg: Message) -> Result<(), TrySendError<Message>> {
+        let mut msg = msg;
+
+        loop {
+            if self.closed.load(Ordering::Acquire) {
+                return Err(TrySendError::Disconnected(msg));
+            }
+
+            match lane.sender.send_timeout(msg, Duration::from_millis(10)) {
+                Err(SendTimeoutError::Timeout(m)) => msg = m,
+                Err(SendTimeoutError::Disconnected(m)) => return Err(TrySendError::Disconnected(m)),
+                Ok(_) => return Ok(()),
+            }
+        }
+    }
+
+    // Try to send a metric, waiting up to the timeout for room if the lane is full.
+    fn send_blocking(&self, lane: &Lane, msg: Message, timeout: Duration) -> Result<(), TrySendError<Message>> {
+        let msg = match self.send(lane, msg) {
+            Err(TrySendError::Full(m)) => m,
+            res => return res,
+        };
+
+        self.stats.incr_blocked();
+        if self.closed.load(Ordering::Acquire) {
+            return Err(TrySendError::Disconnected(msg));
+        }
+
+        match lane.sender.send_timeout(msg, timeout) {
+            Err(SendTimeoutError::Timeout(m)) => Err(TrySendError::Full(m)),
+            Err(SendTimeoutError::Disconnected(m)) => Err(TrySendError::Disconnected(m)),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    // Should a new metric be queued when sampling one in every `n` metrics? All
+    // metrics are accepted until the lane is half full.
+    fn sample(&self, lane: &Lane, n: u32) -> bool {
+        let half = lane.sender.capacity().map(|c| c / 2).unwrap_or(usize::MAX);
+        if lane.sender.len() < half || n <= 1 {
+            return true;
+        }
+
+        self.sampled.fetch_add(1, Ordering::Relaxed) % u64::from(n) == 0
+    }
+
+    // Send a message without blocking unless the worker has been shut down.
+    fn send(&self, lane: &Lane, msg: Message) -> Result<(), TrySendError<Message>> {
+        if self.closed.load(Ordering::Acquire) {
+            return Err(TrySendError::Disconnected(msg));
+        }
+
+        lane.sender.try_send(msg)
+    }
+
+    // Send a flush or stop request to every lane, waiting until the deadline for
+    // room in them if there is one. The request is cancelled if it can't be sent
+    // to every lane. Note that this doesn't check if the worker has been shut down.
+    fn send_marker(
+        &self,
+        request: fn(Arc<Marker>) -> Message,
+        ack: Option<Sender<()>>,
+        deadline: Option<Instant>,
+    ) -> Result<(), SendTimeoutError<()>> {
+        let marker = Marker::new(self.lanes.len(), ack);
+
+        for (i, lane) in self.lanes.iter().enumerate() {
+            let msg = request(Arc::clone(&marker));
+            let res = match deadline {
+                Some(d) => lane.sender.send_deadline(msg, d).map_err(|e| match e {
+                    SendTimeoutError::Timeout(_) => SendTimeoutError::Timeout(()),
+                    SendTimeoutError::Disconnected(_) => SendTimeoutError::Disconnected(()),
+                }),
+                None => lane.sender.try_send(msg).map_err(|e| match e {
+                    TrySendError::Full(_) => SendTimeoutError::Timeout(()),
+                    TrySendError::Disconnected(_) => SendTimeoutError::Disconnected(()),
+                }),
+            };
+
+            if let Err(e) = res {
+                marker.cancel(self.lanes.len() - i);
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Send a flush request, returning an error if the worker has been shut down.
+    fn send_flush(&self, ack: Option<Sender<()>>, deadline: Option<Instant>) -> Result<(), SendTimeoutError<()>> {
+        if self.closed.load(Ordering::Acquire) {
+            return Err(SendTimeoutError::Disconnected(()));
+        }
+
+        self.send_marker(Message::Flush, ack, deadline)
+    }
+
+    // Wait for an entry in any lane and take it from the highest priority lane
+    // that has one.
+    fn next_message(&self) -> (&Lane, Message) {
+        loop {
+            for lane in &self.lanes {
+                if let Ok(msg) = lane.receiver.try_recv() {
+                    return (lane, msg);
+                }
+            }
+
+            let mut sel = Select::new();
+            for lane in &self.lanes {
+                sel.recv(&lane.receiver);
+            }
+
+            // Lanes may have become ready in any order, check them by priority again
+            sel.ready();
+        }
+    }
+
+    // Wait for the next entry in any lane and take up to a batch of metrics
+    // from that lane without waiting for more, stopping at the first request
+    // to flush or stop. The request is returned separately if this thread is
+    // the one to carry it out. Also return the sequence number of the first
+    // metric in the batch and the total number of metrics taken by all threads,
+    // including this batch.
+    fn take(&self) -> (Vec<String>, u64, Option<Message>, u64) {
+        let mut taken = self.taken.lock().unwrap();
+        let (lane, first) = self.next_message();
+        let mut batch = Vec::new();
+        let mut request = None;
+
+        let mut next = Some(first);
+        while let Some(msg) = next {
+            if let Message::Metric(v, submitted) = msg {
+                self.stats.record_queue_time(submitted.elapsed());
                 self.stats.incr_drained();
-                (self.task)(v);
+                batch.push(v);
             } else {
+                if msg.marker().map(|m| m.take()).unwrap_or(false) {
+                    request = Some(msg);
+                }
+
                 break;
             }
+
+            if batch.len() >= self.batch_size {
+                break;
+            }
+
+            next = lane.receiver.try_recv().ok();
+        }
+
+        let start = *taken;
+        *taken += batch.len() as u64;
+        if !batch.is_empty() {
+            self.in_flight.0.lock().unwrap().push(start);
         }
 
-        // Set the "stopped" flag so that callers using the `stop_and_wait`
-        // method will see that we've stopped processing entries in the channel.
-        // This is only for the benefit of unit testing.
-        self.stopped.store(true, Ordering::Release);
+        (batch, start, request, *taken)
+    }
+
+    // Block until every batch starting before the given sequence number has
+    // been processed by all threads.
+    fn wait_for_in_flight(&self, end: u64) {
+        let (lock, cvar) = &self.in_flight;
+        let mut in_flight = lock.lock().unwrap();
+        while in_flight.iter().any(|start| *start < end) {
+            in_flight = cvar.wait(in_flight).unwrap();
+        }
+    }
+
+    fn run(&self) {
+        loop {
+            // Carry out requests left by a thread that panicked before taking
+            // anything else, they came before anything still queued.
+            let orphaned = self.orphaned.lock().unwrap().pop();
+            let (batch, start, request, taken) = match orphaned {
+                Some((request, taken)) => (Vec::new(), taken, Some(request), taken),
+                None => self.take(),
+            };
+
+            let mut handoff = Handoff {
+                worker: self,
+                request: request.map(|r| (r, taken)),
+            };
+
+            if !batch.is_empty() {
+                let _completion = Completion { worker: self, start };
+                (self.task)(batch);
+            }
+
+            match handoff.request.take().map(|(r, _)| r) {
+                Some(Message::Flush(marker)) => {
+                    // Other threads may still be processing metrics taken
+                    // before this flush, make sure they're done first.
+                    self.wait_for_in_flight(taken);
+                    (self.flush)();
+                    if let Some(tx) = &marker.ack {
+                        // The caller may have given up waiting already
+                        let _ = tx.try_send(());
+                    }
+                }
+                Some(Message::Stop(_)) => break,
+                _ => {}
+            }
+        }
+
+        // Set the "stopped" flag once every thread has stopped so that callers
+        // using the `stop_and_wait` method will see that we've stopped processing
+        // entries in the channel. This is only for the benefit of unit testing.
+        if self.running.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.stopped.store(true, Ordering::Release);
+        }
+
+        // Wake up a call to `.shutdown()` waiting for the worker to stop.
+        let _ = self.done.0.try_send(());
     }
 
     fn stop(&self) {
-        // Send a `None` poison pill value to stop the run loop.
-        let _ = self.sender.try_send(None);
+        // Send a `Stop` poison pill value to stop the run loop of each thread.
+        for _ in 0..self.workers {
+            let _ = self.send_marker(Message::Stop, None, None);
+        }
+    }
+
+    // Stop accepting new entries and wait until the deadline for the entries
+    // already queued to be processed, followed by a flush. Return the number
+    // of entries that were still queued as an error if the deadline passed.
+    fn shutdown(&self, timeout: Duration) -> Result<(), u64> {
+        let deadline = Instant::now() + timeout;
+
+        // Only the first call to shutdown waits for the worker, there's nothing
+        // left to stop for other calls.
+        if self.closed.swap(true, Ordering::AcqRel) {
+            return if self.joined.load(Ordering::Acquire) {
+                Ok(())
+            } else {
+                Err(self.stats.queued())
+            };
+        }
+
+        let stopped = self.send_marker(Message::Flush, None, Some(deadline)).is_ok()
+            && (0..self.workers).all(|_| self.send_marker(Message::Stop, None, Some(deadline)).is_ok())
+            && (0..self.workers).all(|_| self.done.1.recv_deadline(deadline).is_ok());
+
+        if !stopped {
+            return Err(self.stats.queued());
+        }
+
+        // Every thread has returned from its run loop so they're about to exit
+        // and we won't block long joining them. Threads are only replaced when
+        // they panic so the current handles are for the threads that stopped.
+        let handles: Vec<_> = self.handles.lock().unwrap().drain(..).collect();
+        for h in handles.into_iter().flatten() {
+            let _ = h.join();
+        }
+
+        self.joined.store(true, Ordering::Release);
+        Ok(())
     }
 
     // Stop reading events from the channel and wait for the "stopped" flag
@@ -403,10 +1617,10 @@ impl Worker {
         }
     }
 
-    // Is the channel used between threads empty, i.e. are all values processed?
+    // Are the channels used between threads empty, i.e. are all values processed?
     #[cfg(test)]
     fn is_empty(&self) -> bool {
-        self.receiver.is_empty()
+        self.lanes.iter().all(|l| l.receiver.is_empty())
     }
 
     // Has this worker stopped running?
@@ -424,13 +1638,16 @@ impl fmt::Debug for Worker {
 
 #[cfg(test)]
 mod tests {
-    use super::{QueuingMetricSink, Worker};
-    use crate::sinks::core::MetricSink;
+    use super::{spawn_worker, Message, OverflowPolicy, Priority, QueuingMetricSink, Worker};
+    use crate::sinks::core::{MetricSink, NopMetricSink};
+    use crate::sinks::filter::FilterRule;
+    use crossbeam_channel::TrySendError;
     use std::io;
     use std::panic;
     use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
     use std::sync::{Arc, Mutex};
     use std::thread;
+    use std::time::{Duration, Instant};
 
     const QUEUE_SIZE: Option<usize> = Some(128);
 
@@ -445,19 +1662,333 @@ mod tests {
             }
         };
 
-        let worker = Arc::new(Worker::new(QUEUE_SIZE, task));
-        let worker_ref = worker.clone();
+        let worker = Arc::new(Worker::new(QUEUE_SIZE, task));
+        let worker_ref = worker.clone();
+
+        let t = thread::spawn(move || {
+            worker_ref.run();
+        });
+
+        worker.submit("bar".to_string(), Priority::Normal).unwrap();
+        worker.submit("foo".to_string(), Priority::Normal).unwrap();
+        worker.stop();
+        t.join().unwrap();
+
+        assert!(flag.load(Ordering::Acquire));
+    }
+
+    #[test]
+    fn test_worker_flush_after_submitted_events() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let task_events = events.clone();
+        let flush_events = events.clone();
+
+        let worker = Arc::new(
+            Worker::new(QUEUE_SIZE, move |v: String| task_events.lock().unwrap().push(v))
+                .with_flush(move || flush_events.lock().unwrap().push("flush".to_string())),
+        );
+        let worker_ref = worker.clone();
+
+        let t = thread::spawn(move || {
+            worker_ref.run();
+        });
+
+        worker.submit("foo".to_string(), Priority::Normal).unwrap();
+        worker.send_marker(Message::Flush, None, None).unwrap();
+        worker.submit("bar".to_string(), Priority::Normal).unwrap();
+        worker.stop();
+        t.join().unwrap();
+
+        assert_eq!(vec!["foo", "flush", "bar"], *events.lock().unwrap());
+        assert_eq!(2, worker.stats.drained());
+    }
+
+    // Return the metrics in the lanes of a worker that isn't running, in order
+    // of priority, with other messages represented by their type.
+    fn queued_messages(worker: &Worker) -> Vec<String> {
+        worker
+            .lanes
+            .iter()
+            .flat_map(|l| l.receiver.try_iter())
+            .map(|m| match m {
+                Message::Metric(v, _) => v,
+                Message::Flush(_) => "flush".to_string(),
+                Message::Stop(_) => "stop".to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_worker_overflow_drop_newest() {
+        let worker = Worker::new(Some(2), move |_: String| {});
+
+        worker.submit("foo".to_string(), Priority::Normal).unwrap();
+        worker.submit("bar".to_string(), Priority::Normal).unwrap();
+        assert!(matches!(
+            worker.submit("baz".to_string(), Priority::Normal),
+            Err(TrySendError::Full(_))
+        ));
+
+        assert_eq!(2, worker.stats.submitted());
+        assert_eq!(1, worker.stats.dropped_newest());
+        assert_eq!(vec!["foo", "bar"], queued_messages(&worker));
+    }
+
+    #[test]
+    fn test_worker_overflow_drop_oldest() {
+        let worker = Worker::new(Some(2), move |_: String| {}).with_policy(OverflowPolicy::DropOldest);
+
+        worker.submit("foo".to_string(), Priority::Normal).unwrap();
+        worker.submit("bar".to_string(), Priority::Normal).unwrap();
+        worker.submit("baz".to_string(), Priority::Normal).unwrap();
+
+        assert_eq!(3, worker.stats.submitted());
+        assert_eq!(1, worker.stats.dropped_oldest());
+        assert_eq!(2, worker.stats.queued());
+        assert_eq!(vec!["bar", "baz"], queued_messages(&worker));
+    }
+
+    #[test]
+    fn test_worker_overflow_drop_oldest_keeps_flushes() {
+        let worker = Worker::new(Some(2), move |_: String| {}).with_policy(OverflowPolicy::DropOldest);
+
+        worker.submit("foo".to_string(), Priority::Normal).unwrap();
+        worker.send_marker(Message::Flush, None, None).unwrap();
+        worker.submit("bar".to_string(), Priority::Normal).unwrap();
+        worker.submit("baz".to_string(), Priority::Normal).unwrap();
+
+        assert_eq!(2, worker.stats.dropped_oldest());
+        assert_eq!(vec!["flush", "baz"], queued_messages(&worker));
+    }
+
+    // With nothing but a flush in the lane there's no metric to discard, the
+    // flush must be kept and the new metric queued once the worker makes room.
+    #[test]
+    fn test_worker_overflow_drop_oldest_only_flushes() {
+        let worker = Arc::new(Worker::new(Some(1), move |_: String| {}).with_policy(OverflowPolicy::DropOldest));
+        worker.send_marker(Message::Flush, None, None).unwrap();
+
+        let worker_ref = Arc::clone(&worker);
+        let taker = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            match worker_ref.lanes[0].receiver.recv().unwrap() {
+                Message::Flush(_) => {}
+                _ => panic!("expected the flush to be kept"),
+            }
+        });
+
+        worker.submit("foo".to_string(), Priority::Normal).unwrap();
+        taker.join().unwrap();
+
+        assert_eq!(0, worker.stats.dropped_oldest());
+        assert_eq!(vec!["foo"], queued_messages(&worker));
+    }
+
+    #[test]
+    fn test_worker_overflow_block_timeout() {
+        let worker =
+            Worker::new(Some(1), move |_: String| {}).with_policy(OverflowPolicy::Block(Duration::from_millis(10)));
+
+        worker.submit("foo".to_string(), Priority::Normal).unwrap();
+        assert!(matches!(
+            worker.submit("bar".to_string(), Priority::Normal),
+            Err(TrySendError::Full(_))
+        ));
+
+        assert_eq!(1, worker.stats.blocked());
+        assert_eq!(1, worker.stats.dropped_newest());
+        assert_eq!(vec!["foo"], queued_messages(&worker));
+    }
+
+    #[test]
+    fn test_worker_overflow_block_success() {
+        let worker = Arc::new(
+            Worker::new(Some(1), move |_: String| {}).with_policy(OverflowPolicy::Block(Duration::from_secs(5))),
+        );
+        let worker_ref = worker.clone();
+
+        worker.submit("foo".to_string(), Priority::Normal).unwrap();
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            worker_ref.lanes[0].receiver.recv().unwrap();
+        });
+
+        worker.submit("bar".to_string(), Priority::Normal).unwrap();
+        t.join().unwrap();
+
+        assert_eq!(1, worker.stats.blocked());
+        assert_eq!(0, worker.stats.dropped_newest());
+        assert_eq!(vec!["bar"], queued_messages(&worker));
+    }
+
+    #[test]
+    fn test_worker_overflow_sampled() {
+        let worker = Worker::new(Some(4), move |_: String| {}).with_policy(OverflowPolicy::Sampled(2));
+
+        // Everything is accepted until the queue is half full, then every other
+        // metric until the queue is full.
+        for i in 0..6 {
+            worker.submit(format!("foo.{}", i), Priority::Normal).unwrap();
+        }
+        assert!(matches!(
+            worker.submit("foo.6".to_string(), Priority::Normal),
+            Err(TrySendError::Full(_))
+        ));
+
+        assert_eq!(4, worker.stats.submitted());
+        assert_eq!(2, worker.stats.sampled_out());
+        assert_eq!(1, worker.stats.dropped_newest());
+        assert_eq!(vec!["foo.0", "foo.1", "foo.2", "foo.4"], queued_messages(&worker));
+    }
+
+    #[test]
+    fn test_worker_stats_queued_high_water() {
+        let worker = Worker::new(QUEUE_SIZE, move |_: String| {});
+
+        worker.submit("foo".to_string(), Priority::Normal).unwrap();
+        worker.submit("bar".to_string(), Priority::Normal).unwrap();
+        worker.submit("baz".to_string(), Priority::Normal).unwrap();
+        worker.stop();
+        worker.run();
+
+        assert_eq!(0, worker.stats.queued());
+        assert_eq!(3, worker.stats.queued_high_water());
+    }
+
+    #[test]
+    fn test_worker_stats_queue_time() {
+        let worker = Worker::new(QUEUE_SIZE, move |_: String| {});
+        assert_eq!(Duration::from_nanos(0), worker.stats.queue_time_avg());
+
+        worker.submit("foo".to_string(), Priority::Normal).unwrap();
+        thread::sleep(Duration::from_millis(20));
+        worker.submit("bar".to_string(), Priority::Normal).unwrap();
+        worker.stop();
+        worker.run();
+
+        // The first metric waited at least 20ms and the second much less
+        assert!(worker.stats.queue_time_max() >= Duration::from_millis(20));
+        assert!(worker.stats.queue_time_avg() >= Duration::from_millis(10));
+        assert!(worker.stats.queue_time_avg() < worker.stats.queue_time_max());
+    }
+
+    #[test]
+    fn test_worker_stats_dropped_disconnected() {
+        let worker = Worker::new(Some(1), move |_: String| {});
+
+        worker.submit("foo".to_string(), Priority::Normal).unwrap();
+        assert!(matches!(
+            worker.submit("bar".to_string(), Priority::Normal),
+            Err(TrySendError::Full(_))
+        ));
+        worker.closed.store(true, Ordering::Release);
+        assert!(matches!(
+            worker.submit("baz".to_string(), Priority::Normal),
+            Err(TrySendError::Disconnected(_))
+        ));
+
+        assert_eq!(1, worker.stats.dropped_newest());
+        assert_eq!(1, worker.stats.dropped_disconnected());
+        assert_eq!(2, worker.stats.dropped());
+    }
+
+    #[test]
+    fn test_worker_batches() {
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let batches_ref = batches.clone();
+
+        let worker = Worker::batched(QUEUE_SIZE, move |batch: Vec<String>| {
+            batches_ref.lock().unwrap().push(batch)
+        })
+        .with_batch_size(2);
+
+        worker.submit("foo".to_string(), Priority::Normal).unwrap();
+        worker.submit("bar".to_string(), Priority::Normal).unwrap();
+        worker.submit("baz".to_string(), Priority::Normal).unwrap();
+        worker.send_marker(Message::Flush, None, None).unwrap();
+        worker.submit("qux".to_string(), Priority::Normal).unwrap();
+        worker.stop();
+        worker.run();
+
+        // Batches stop early at a flush or when there's nothing left to take
+        assert_eq!(
+            vec![vec!["foo", "bar"], vec!["baz"], vec!["qux"]],
+            *batches.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_worker_multiple_threads_stop() {
+        let worker = Arc::new(Worker::new(QUEUE_SIZE, move |_: String| {}).with_workers(3));
+        let threads: Vec<_> = (0..3)
+            .map(|_| {
+                let worker_ref = worker.clone();
+                thread::spawn(move || worker_ref.run())
+            })
+            .collect();
+
+        worker.stop();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        assert!(worker.is_stopped());
+    }
+
+    fn new_lane_worker(events: &Arc<Mutex<Vec<String>>>) -> Worker {
+        let task_events = events.clone();
+        let flush_events = events.clone();
+
+        Worker::new(Some(8), move |v: String| task_events.lock().unwrap().push(v))
+            .with_flush(move || flush_events.lock().unwrap().push("flush".to_string()))
+            .with_lane(Priority::High, Some(8))
+            .with_lane(Priority::Low, Some(8))
+    }
+
+    #[test]
+    fn test_worker_lanes_drain_high_priority_first() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let worker = new_lane_worker(&events);
+
+        worker.submit("foo".to_string(), Priority::Low).unwrap();
+        worker.submit("bar".to_string(), Priority::Normal).unwrap();
+        worker.submit("baz".to_string(), Priority::High).unwrap();
+        worker.submit("qux".to_string(), Priority::High).unwrap();
+        worker.stop();
+        worker.run();
 
-        let t = thread::spawn(move || {
-            worker_ref.run();
-        });
+        assert_eq!(vec!["baz", "qux", "bar", "foo"], *events.lock().unwrap());
+    }
+
+    // Make sure a flush comes after every metric submitted before it, even metrics
+    // in lower priority lanes that are drained after metrics submitted later.
+    #[test]
+    fn test_worker_lanes_flush_after_all_lanes() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let worker = new_lane_worker(&events);
 
-        worker.submit("bar".to_string()).unwrap();
-        worker.submit("foo".to_string()).unwrap();
+        worker.submit("foo".to_string(), Priority::Low).unwrap();
+        worker.send_marker(Message::Flush, None, None).unwrap();
+        worker.submit("bar".to_string(), Priority::High).unwrap();
         worker.stop();
-        t.join().unwrap();
+        worker.run();
 
-        assert!(flag.load(Ordering::Acquire));
+        assert_eq!(vec!["bar", "foo", "flush"], *events.lock().unwrap());
+    }
+
+    #[test]
+    fn test_worker_lanes_dropped() {
+        let worker = Worker::new(Some(1), move |_: String| {}).with_lane(Priority::Low, Some(1));
+
+        worker.submit("foo".to_string(), Priority::Low).unwrap();
+        assert!(worker.submit("bar".to_string(), Priority::Low).is_err());
+        worker.submit("baz".to_string(), Priority::Normal).unwrap();
+        // No lane for high priority metrics so they use the (full) normal lane
+        assert!(worker.submit("qux".to_string(), Priority::High).is_err());
+
+        assert_eq!(1, worker.lane(Priority::Low).dropped.load(Ordering::Acquire));
+        assert_eq!(1, worker.lane(Priority::Normal).dropped.load(Ordering::Acquire));
+        assert_eq!(2, worker.stats.dropped_newest());
     }
 
     #[test]
@@ -496,9 +2027,9 @@ mod tests {
         let worker_ref1 = worker.clone();
         let worker_ref2 = worker.clone();
 
-        #[allow(unreachable_code)]
         let t1 = thread::spawn(move || {
-            worker_ref1.submit(panic!("This thread is supposed to panic")).unwrap();
+            let _worker = worker_ref1;
+            panic!("This thread is supposed to panic");
         });
 
         let t2 = thread::spawn(move || {
@@ -514,6 +2045,34 @@ mod tests {
         assert!(worker.is_empty());
     }
 
+    // Make sure a stop request taken off the queue along with a batch of
+    // metrics still stops the thread that replaces the one that panicked.
+    #[test]
+    fn test_worker_batch_panic_keeps_stop() {
+        let worker = Arc::new(
+            Worker::batched(QUEUE_SIZE, move |batch: Vec<String>| {
+                if batch.iter().any(|m| m == "panic") {
+                    panic!("This thread is supposed to panic");
+                }
+            })
+            .with_batch_size(4),
+        );
+
+        worker.submit("foo".to_string(), Priority::Normal).unwrap();
+        worker.submit("panic".to_string(), Priority::Normal).unwrap();
+        worker.stop();
+        spawn_worker(Arc::clone(&worker), 0).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !worker.is_stopped() {
+            assert!(Instant::now() < deadline, "worker never stopped");
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(1, worker.stats.panics());
+        assert!(worker.is_empty());
+    }
+
     // Make sure the worker and its channel are in the expected state
     // when the consumer side of the channel panics.
     #[test]
@@ -525,7 +2084,7 @@ mod tests {
         let worker_ref2 = worker.clone();
 
         let t1 = thread::spawn(move || {
-            worker_ref1.submit("foo".to_owned()).unwrap();
+            worker_ref1.submit("foo".to_owned(), Priority::Normal).unwrap();
         });
 
         let t2 = thread::spawn(move || {
@@ -573,6 +2132,448 @@ mod tests {
         assert_eq!("baz.counter:3|c".to_string(), store.lock().unwrap()[2]);
     }
 
+    struct FlushRecordingMetricSink {
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl MetricSink for FlushRecordingMetricSink {
+        fn emit(&self, m: &str) -> io::Result<usize> {
+            self.events.lock().unwrap().push(m.to_string());
+            Ok(m.len())
+        }
+
+        fn flush(&self) -> io::Result<()> {
+            self.events.lock().unwrap().push("flush".to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_queuing_sink_flush() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let queuing = QueuingMetricSink::from(FlushRecordingMetricSink { events: events.clone() });
+
+        queuing.emit("foo.counter:1|c").unwrap();
+        queuing.flush().unwrap();
+        queuing.emit("bar.counter:2|c").unwrap();
+        queuing.worker.stop_and_wait();
+
+        assert_eq!(
+            vec!["foo.counter:1|c", "flush", "bar.counter:2|c"],
+            *events.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_queuing_sink_flush_and_wait() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let queuing = QueuingMetricSink::from(FlushRecordingMetricSink { events: events.clone() });
+
+        queuing.emit("foo.counter:1|c").unwrap();
+        queuing.emit("bar.counter:2|c").unwrap();
+        queuing.flush_and_wait(Duration::from_secs(5)).unwrap();
+
+        // Everything is guaranteed to have been processed, no need to stop the worker
+        assert_eq!(
+            vec!["foo.counter:1|c", "bar.counter:2|c", "flush"],
+            *events.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_queuing_sink_flush_and_wait_timeout() {
+        struct BlockingMetricSink;
+
+        impl MetricSink for BlockingMetricSink {
+            fn emit(&self, _m: &str) -> io::Result<usize> {
+                loop {
+                    thread::park();
+                }
+            }
+        }
+
+        let queuing = QueuingMetricSink::from(BlockingMetricSink);
+        queuing.emit("foo.counter:1|c").unwrap();
+
+        let err = queuing.flush_and_wait(Duration::from_millis(20)).unwrap_err();
+        assert_eq!(io::ErrorKind::TimedOut, err.kind());
+    }
+
+    #[test]
+    fn test_queuing_sink_flush_full_queue() {
+        struct BlockingMetricSink;
+
+        impl MetricSink for BlockingMetricSink {
+            fn emit(&self, _m: &str) -> io::Result<usize> {
+                loop {
+                    thread::park();
+                }
+            }
+        }
+
+        let queuing = QueuingMetricSink::with_capacity(BlockingMetricSink, 2);
+        let metric_err = loop {
+            if let Err(e) = queuing.emit("foo.counter:1|c") {
+                break e;
+            }
+        };
+
+        let flush_err = queuing.flush().unwrap_err();
+        assert_eq!(io::ErrorKind::Other, metric_err.kind());
+        assert_eq!(io::ErrorKind::WouldBlock, flush_err.kind());
+    }
+
+    #[test]
+    fn test_queuing_sink_shutdown() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let queuing = QueuingMetricSink::from(FlushRecordingMetricSink { events: events.clone() });
+
+        queuing.emit("foo.counter:1|c").unwrap();
+        queuing.emit("bar.counter:2|c").unwrap();
+
+        assert_eq!(Ok(()), queuing.shutdown(Duration::from_secs(5)));
+        assert!(queuing.worker.is_stopped());
+        assert!(queuing.emit("baz.counter:3|c").is_err());
+        assert!(queuing.flush().is_err());
+        assert_eq!(1, queuing.dropped_disconnected());
+        // Shutting down again has nothing left to do
+        assert_eq!(Ok(()), queuing.shutdown(Duration::from_secs(5)));
+
+        assert_eq!(
+            vec!["foo.counter:1|c", "bar.counter:2|c", "flush"],
+            *events.lock().unwrap()
+        );
+    }
+
+    // Make sure the worker is stopped even when the bounded queue is full at the
+    // time of shutdown, which would cause the stop message to be dropped if it
+    // were sent without waiting.
+    #[test]
+    fn test_queuing_sink_shutdown_full_queue() {
+        struct SlowMetricSink {
+            emitted: Arc<AtomicUsize>,
+        }
+
+        impl MetricSink for SlowMetricSink {
+            fn emit(&self, m: &str) -> io::Result<usize> {
+                thread::sleep(Duration::from_millis(5));
+                self.emitted.fetch_add(1, Ordering::Release);
+                Ok(m.len())
+            }
+        }
+
+        let emitted = Arc::new(AtomicUsize::new(0));
+        let queuing = QueuingMetricSink::with_capacity(
+            SlowMetricSink {
+                emitted: emitted.clone(),
+            },
+            4,
+        );
+
+        let mut accepted = 0;
+        while queuing.emit("foo.counter:1|c").is_ok() {
+            accepted += 1;
+        }
+
+        assert_eq!(Ok(()), queuing.shutdown(Duration::from_secs(5)));
+        assert!(queuing.worker.is_stopped());
+        assert_eq!(accepted, emitted.load(Ordering::Acquire));
+    }
+
+    #[test]
+    fn test_queuing_sink_shutdown_timeout() {
+        struct BlockingMetricSink;
+
+        impl MetricSink for BlockingMetricSink {
+            fn emit(&self, _m: &str) -> io::Result<usize> {
+                loop {
+                    thread::park();
+                }
+            }
+        }
+
+        let queuing = QueuingMetricSink::from(BlockingMetricSink);
+        queuing.emit("foo.counter:1|c").unwrap();
+        queuing.emit("foo.counter:2|c").unwrap();
+        queuing.emit("foo.counter:3|c").unwrap();
+
+        // The first metric may or may not have been taken off the queue by the
+        // worker (where it's stuck) but the others definitely haven't.
+        let abandoned = queuing.shutdown(Duration::from_millis(20)).unwrap_err();
+        assert!(
+            abandoned >= 2,
+            "expected at least two abandoned metrics, got {}",
+            abandoned
+        );
+        assert!(!queuing.worker.is_stopped());
+    }
+
+    // A timeout is reported even if nothing is left in the queue, since the
+    // worker is still busy with the last metric and the sink isn't flushed.
+    #[test]
+    fn test_queuing_sink_shutdown_timeout_empty_queue() {
+        struct BlockingMetricSink;
+
+        impl MetricSink for BlockingMetricSink {
+            fn emit(&self, _m: &str) -> io::Result<usize> {
+                loop {
+                    thread::park();
+                }
+            }
+        }
+
+        let queuing = QueuingMetricSink::from(BlockingMetricSink);
+        queuing.emit("foo.counter:1|c").unwrap();
+        while queuing.queued() != 0 {
+            thread::yield_now();
+        }
+
+        assert_eq!(Err(0), queuing.shutdown(Duration::from_millis(20)));
+        assert_eq!(Err(0), queuing.shutdown(Duration::from_millis(20)));
+        assert!(!queuing.worker.is_stopped());
+    }
+
+    #[test]
+    fn test_queuing_sink_overflow_drop_oldest() {
+        struct BlockingMetricSink;
+
+        impl MetricSink for BlockingMetricSink {
+            fn emit(&self, _m: &str) -> io::Result<usize> {
+                loop {
+                    thread::park();
+                }
+            }
+        }
+
+        let queuing = QueuingMetricSink::with_overflow_policy(BlockingMetricSink, 1, OverflowPolicy::DropOldest);
+        for i in 0..5 {
+            queuing.emit(&format!("foo.counter:{}|c", i)).unwrap();
+        }
+
+        // The worker may have taken the first metric off the queue before getting
+        // stuck, any others but the last were discarded to make room for the next.
+        assert_eq!(5, queuing.submitted());
+        assert!(queuing.dropped_oldest() >= 3);
+        assert_eq!(0, queuing.dropped_newest());
+        assert_eq!(1, queuing.queued());
+    }
+
+    // Sink that counts emitted metrics, slowly, and records how many had been
+    // emitted each time it's flushed.
+    struct CountingMetricSink {
+        emitted: AtomicUsize,
+        flushes: Mutex<Vec<usize>>,
+    }
+
+    impl MetricSink for CountingMetricSink {
+        fn emit(&self, m: &str) -> io::Result<usize> {
+            thread::sleep(Duration::from_micros(50));
+            self.emitted.fetch_add(1, Ordering::AcqRel);
+            Ok(m.len())
+        }
+
+        fn flush(&self) -> io::Result<()> {
+            self.flushes.lock().unwrap().push(self.emitted.load(Ordering::Acquire));
+            Ok(())
+        }
+    }
+
+    impl MetricSink for Arc<CountingMetricSink> {
+        fn emit(&self, m: &str) -> io::Result<usize> {
+            (**self).emit(m)
+        }
+
+        fn flush(&self) -> io::Result<()> {
+            (**self).flush()
+        }
+    }
+
+    #[test]
+    fn test_queuing_sink_with_workers_flush_and_wait() {
+        let counting = Arc::new(CountingMetricSink {
+            emitted: AtomicUsize::new(0),
+            flushes: Mutex::new(Vec::new()),
+        });
+        let queuing = QueuingMetricSink::with_workers(counting.clone(), 1024, 4);
+
+        for i in 0..500 {
+            queuing.emit(&format!("foo.counter:{}|c", i)).unwrap();
+        }
+        queuing.flush_and_wait(Duration::from_secs(10)).unwrap();
+
+        // Every metric emitted before the flush was passed to the wrapped sink
+        // by one of the threads before the wrapped sink was flushed.
+        assert_eq!(vec![500], *counting.flushes.lock().unwrap());
+        assert_eq!(500, queuing.drained());
+    }
+
+    #[test]
+    fn test_queuing_sink_with_workers_shutdown() {
+        let counting = Arc::new(CountingMetricSink {
+            emitted: AtomicUsize::new(0),
+            flushes: Mutex::new(Vec::new()),
+        });
+        let queuing = QueuingMetricSink::with_workers(counting.clone(), 1024, 4);
+
+        for i in 0..500 {
+            queuing.emit(&format!("foo.counter:{}|c", i)).unwrap();
+        }
+
+        assert_eq!(Ok(()), queuing.shutdown(Duration::from_secs(10)));
+        assert!(queuing.worker.is_stopped());
+        assert_eq!(500, counting.emitted.load(Ordering::Acquire));
+        assert_eq!(vec![500], *counting.flushes.lock().unwrap());
+    }
+
+    #[test]
+    fn test_queuing_sink_builder_threads() {
+        let started = Arc::new(Mutex::new(Vec::new()));
+        let affinity_started = started.clone();
+        let on_start_started = started.clone();
+
+        let queuing = QueuingMetricSink::builder()
+            .with_workers(2)
+            .with_thread_name("test-metrics")
+            .with_stack_size(256 * 1024)
+            .with_affinity(move |index| {
+                affinity_started.lock().unwrap().push(format!("affinity {}", index));
+            })
+            .with_on_start(move |index| {
+                let name = thread::current().name().unwrap().to_string();
+                on_start_started
+                    .lock()
+                    .unwrap()
+                    .push(format!("start {} {}", index, name));
+            })
+            .build(NopMetricSink)
+            .unwrap();
+
+        assert_eq!(Ok(()), queuing.shutdown(Duration::from_secs(5)));
+
+        let mut started = started.lock().unwrap().clone();
+        started.sort();
+        assert_eq!(
+            vec![
+                "affinity 0",
+                "affinity 1",
+                "start 0 test-metrics-0",
+                "start 1 test-metrics-1"
+            ],
+            started
+        );
+    }
+
+    #[test]
+    fn test_queuing_sink_builder_executor() {
+        let executed = Arc::new(AtomicUsize::new(0));
+        let executed_ref = executed.clone();
+        let events = Arc::new(Mutex::new(Vec::new()));
+
+        let queuing = QueuingMetricSink::builder()
+            .with_executor(move |task| {
+                executed_ref.fetch_add(1, Ordering::Release);
+                thread::spawn(task);
+            })
+            .build(FlushRecordingMetricSink { events: events.clone() })
+            .unwrap();
+
+        queuing.emit("foo.counter:1|c").unwrap();
+        assert_eq!(Ok(()), queuing.shutdown(Duration::from_secs(5)));
+
+        assert_eq!(1, executed.load(Ordering::Acquire));
+        assert_eq!(vec!["foo.counter:1|c", "flush"], *events.lock().unwrap());
+    }
+
+    #[test]
+    fn test_queuing_sink_builder_executor_restarts_panics() {
+        struct SometimesPanickingMetricSink(AtomicUsize);
+
+        impl MetricSink for SometimesPanickingMetricSink {
+            fn emit(&self, m: &str) -> io::Result<usize> {
+                if self.0.fetch_add(1, Ordering::AcqRel) == 0 {
+                    panic!("This thread is supposed to panic");
+                }
+                Ok(m.len())
+            }
+        }
+
+        let executed = Arc::new(AtomicUsize::new(0));
+        let executed_ref = executed.clone();
+
+        let queuing = QueuingMetricSink::builder()
+            .with_executor(move |task| {
+                executed_ref.fetch_add(1, Ordering::Release);
+                thread::spawn(task);
+            })
+            .build(SometimesPanickingMetricSink(AtomicUsize::new(0)))
+            .unwrap();
+
+        queuing.emit("foo.counter:1|c").unwrap();
+        queuing.emit("foo.counter:2|c").unwrap();
+        assert_eq!(Ok(()), queuing.shutdown(Duration::from_secs(5)));
+
+        assert_eq!(1, queuing.panics());
+        assert_eq!(2, executed.load(Ordering::Acquire));
+    }
+
+    #[test]
+    fn test_queuing_sink_priority_rules() {
+        struct BlockingMetricSink;
+
+        impl MetricSink for BlockingMetricSink {
+            fn emit(&self, _m: &str) -> io::Result<usize> {
+                loop {
+                    thread::park();
+                }
+            }
+        }
+
+        let queuing = QueuingMetricSink::builder()
+            .with_capacity(4)
+            .with_lane(Priority::High, 4)
+            .with_lane(Priority::Low, 2)
+            .with_priority_rule(FilterRule::prefix("slo."), Priority::High)
+            .with_priority_rule(FilterRule::prefix("debug."), Priority::Low)
+            .build(BlockingMetricSink)
+            .unwrap();
+
+        // Get the worker stuck on a metric so that the lanes fill up
+        queuing.emit("blocker:1|c").unwrap();
+        while queuing.drained() == 0 {
+            thread::yield_now();
+        }
+
+        for i in 0..10 {
+            let _ = queuing.emit(&format!("debug.latency:{}|h", i));
+        }
+        for i in 0..3 {
+            queuing.emit(&format!("slo.requests:{}|c", i)).unwrap();
+        }
+
+        assert_eq!(3, queuing.submitted_for(Priority::High));
+        assert_eq!(0, queuing.dropped_for(Priority::High));
+        assert_eq!(2, queuing.submitted_for(Priority::Low));
+        assert_eq!(8, queuing.dropped_for(Priority::Low));
+        assert_eq!(1, queuing.submitted_for(Priority::Normal));
+    }
+
+    #[test]
+    fn test_queuing_sink_emit_with_priority_flush_and_wait() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let queuing = QueuingMetricSink::builder()
+            .with_lane(Priority::Low, 16)
+            .build(FlushRecordingMetricSink { events: events.clone() })
+            .unwrap();
+
+        queuing.emit_with_priority("foo.counter:1|c", Priority::Low).unwrap();
+        queuing.emit("bar.counter:2|c").unwrap();
+        queuing.flush_and_wait(Duration::from_secs(5)).unwrap();
+
+        let events = events.lock().unwrap();
+        assert_eq!(3, events.len());
+        assert_eq!("flush", events[2]);
+    }
+
     #[test]
     fn test_queuing_sink_emit_panics() {
         struct PanickingMetricSink;
@@ -637,6 +2638,64 @@ mod tests {
         assert_eq!("foo.timer:34|ms".to_string(), store.lock().unwrap()[1]);
     }
 
+    // Make sure a flush taken off the queue along with a batch of metrics is
+    // still carried out by the replacement thread when the batch panics.
+    #[test]
+    fn test_queuing_sink_batch_panic_keeps_flush() {
+        struct PanickingMetricSink {
+            released: Arc<AtomicBool>,
+            events: Arc<Mutex<Vec<String>>>,
+        }
+
+        impl MetricSink for PanickingMetricSink {
+            fn emit(&self, m: &str) -> io::Result<usize> {
+                if m.starts_with("block") {
+                    while !self.released.load(Ordering::Acquire) {
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                } else if m.starts_with("panic") {
+                    panic!("This thread is supposed to panic");
+                }
+
+                Ok(m.len())
+            }
+
+            fn flush(&self) -> io::Result<()> {
+                self.events.lock().unwrap().push("flush".to_string());
+                Ok(())
+            }
+        }
+
+        let released = Arc::new(AtomicBool::new(false));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let queuing = QueuingMetricSink::builder()
+            .with_batch_size(4)
+            .build(PanickingMetricSink {
+                released: released.clone(),
+                events: events.clone(),
+            })
+            .unwrap();
+
+        // Keep the worker busy so that the panicking metric and the flush and
+        // stop requests from the shutdown end up queued together.
+        queuing.emit("block.counter:1|c").unwrap();
+        while queuing.queued() != 0 {
+            thread::yield_now();
+        }
+        queuing.emit("panic.counter:1|c").unwrap();
+
+        let release = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            released.store(true, Ordering::Release);
+        });
+
+        assert_eq!(Ok(()), queuing.shutdown(Duration::from_secs(5)));
+        release.join().unwrap();
+
+        assert_eq!(1, queuing.panics());
+        assert_eq!(vec!["flush"], *events.lock().unwrap());
+    }
+
     // Make sure that our queuing sink is unwind safe (it has the auto trait) and
     // that it handles any expected panics on its own, resulting in calling code not
     // seeing any panics.
@@ -0,0 +1,214 @@
+// Cadence - An extensible Statsd client for Rust!
+//
+// Copyright 2021 Nick Pillitteri
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::cmp;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Options for sinks that send metrics over a connection, such as a TCP
+/// connection, that needs to be re-established when it's lost.
+///
+/// Connections are established the first time a metric is sent. If that
+/// fails, or the connection is lost later, the sink will try to connect
+/// again the next time a metric is sent. After each failed attempt to
+/// connect, the sink waits before trying again, doubling the time it waits
+/// after each consecutive failure up to a maximum. While waiting, sending
+/// metrics fails immediately with a `NotConnected` error.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use cadence::ConnectionOptions;
+///
+/// let options = ConnectionOptions::default()
+///     .with_connect_timeout(Duration::from_millis(500))
+///     .with_write_timeout(Duration::from_millis(250))
+///     .with_backoff(Duration::from_millis(50), Duration::from_secs(30));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionOptions {
+    pub(crate) connect_timeout: Duration,
+    pub(crate) write_timeout: Duration,
+    pub(crate) min_backoff: Duration,
+    pub(crate) max_backoff: Duration,
+}
+
+impl ConnectionOptions {
+    /// Set the maximum amount of time to wait when establishing a
+    /// connection, one second by default.
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Set the maximum amount of time to wait when writing metrics to
+    /// a connection, one second by default.
+    pub fn with_write_timeout(mut self, timeout: Duration) -> Self {
+        self.write_timeout = timeout;
+        self
+    }
+
+    /// Set the time to wait before reconnecting after the first failed
+    /// attempt to connect and the maximum time to wait after repeated
+    /// failures, 100 milliseconds and 10 seconds by default.
+    pub fn with_backoff(mut self, min: Duration, max: Duration) -> Self {
+        self.min_backoff = min;
+        self.max_backoff = cmp::max(min, max);
+        self
+    }
+}
+
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        ConnectionOptions {
+            connect_timeout: Duration::from_secs(1),
+            write_timeout: Duration::from_secs(1),
+            min_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Way to establish a new connection for a `Reconnecting` writer.
+pub(crate) trait Connector {
+    type Stream: Write;
+
+    fn connect(&self, options: &ConnectionOptions) -> io::Result<Self::Stream>;
+}
+
+/// Writer that sends complete frames over a connection, establishing the
+/// connection as needed and backing off between failed attempts.
+#[derive(Debug)]
+pub(crate) struct Reconnecting<C>
+where
+    C: Connector,
+{
+    connector: C,
+    options: ConnectionOptions,
+    stream: Option<C::Stream>,
+    backoff: Duration,
+    retry_at: Option<Instant>,
+}
+
+impl<C> Reconnecting<C>
+where
+    C: Connector,
+{
+    pub(crate) fn new(connector: C, options: ConnectionOptions) -> Self {
+        Reconnecting {
+            connector,
+            options,
+            stream: None,
+            backoff: options.min_backoff,
+            retry_at: None,
+        }
+    }
+
+    /// Write an entire frame to the connection, connecting first if needed.
+    ///
+    /// If the write fails, the connection is discarded so that a partially
+    /// written frame is never followed by other frames on the same connection.
+    pub(crate) fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        if self.stream.is_none() {
+            self.stream = Some(self.connect()?);
+        }
+
+        let res = match self.stream {
+            Some(ref mut s) => s.write_all(frame).and_then(|_| s.flush()),
+            None => unreachable!(),
+        };
+
+        if res.is_err() {
+            self.stream = None;
+        }
+
+        res
+    }
+
+    fn connect(&mut self) -> io::Result<C::Stream> {
+        let now = Instant::now();
+        if let Some(retry_at) = self.retry_at {
+            if now < retry_at {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    "Not connected, waiting to reconnect",
+                ));
+            }
+        }
+
+        match self.connector.connect(&self.options) {
+            Ok(stream) => {
+                self.backoff = self.options.min_backoff;
+                self.retry_at = None;
+                Ok(stream)
+            }
+            Err(e) => {
+                self.retry_at = Some(now + self.backoff);
+                self.backoff = cmp::min(self.backoff * 2, self.options.max_backoff);
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConnectionOptions, Connector, Reconnecting};
+    use std::cell::Cell;
+    use std::io;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Connector that fails a given number of times before succeeding
+    struct FlakyConnector {
+        failures: Cell<usize>,
+        attempts: Cell<usize>,
+    }
+
+    impl Connector for FlakyConnector {
+        type Stream = Vec<u8>;
+
+        fn connect(&self, _options: &ConnectionOptions) -> io::Result<Vec<u8>> {
+            self.attempts.set(self.attempts.get() + 1);
+            if self.failures.get() > 0 {
+                self.failures.set(self.failures.get() - 1);
+                Err(io::Error::from(io::ErrorKind::ConnectionRefused))
+            } else {
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    #[test]
+    fn test_reconnecting_backoff() {
+        let connector = FlakyConnector {
+            failures: Cell::new(2),
+            attempts: Cell::new(0),
+        };
+        let options = ConnectionOptions::default().with_backoff(Duration::from_millis(20), Duration::from_millis(40));
+        let mut writer = Reconnecting::new(connector, options);
+
+        assert_eq!(io::ErrorKind::ConnectionRefused, writer.send(b"a").unwrap_err().kind());
+        // Waiting to reconnect so no attempt is made
+        assert_eq!(io::ErrorKind::NotConnected, writer.send(b"b").unwrap_err().kind());
+        assert_eq!(1, writer.connector.attempts.get());
+
+        thread::sleep(Duration::from_millis(25));
+        assert_eq!(io::ErrorKind::ConnectionRefused, writer.send(b"c").unwrap_err().kind());
+        assert_eq!(Duration::from_millis(40), writer.backoff);
+
+        thread::sleep(Duration::from_millis(45));
+        writer.send(b"d").unwrap();
+        assert_eq!(3, writer.connector.attempts.get());
+        assert_eq!(Duration::from_millis(20), writer.backoff);
+        assert_eq!(b"d".to_vec(), writer.stream.unwrap());
+    }
+}
@@ -0,0 +1,388 @@
+// Cadence - An extensible Statsd client for Rust!
+//
+// Copyright 2021 Nick Pillitteri
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::panic::RefUnwindSafe;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Way to resolve the address of a metric server from a host and port.
+///
+/// The default implementation uses the resolver of the operating system
+/// via the `ToSocketAddrs` trait. Custom implementations can be used to
+/// look addresses up some other way (or in tests). Closures that accept
+/// a string in the form `host:port` and return a `SocketAddr` implement
+/// this trait.
+pub trait AddressResolver {
+    /// Resolve a host given as `host:port` to a single address, returning an
+    /// error if it can't be resolved. On error, sinks keep using the address
+    /// they already have.
+    fn resolve(&self, host: &str) -> io::Result<SocketAddr>;
+}
+
+impl<F> AddressResolver for F
+where
+    F: Fn(&str) -> io::Result<SocketAddr>,
+{
+    fn resolve(&self, host: &str) -> io::Result<SocketAddr> {
+        (self)(host)
+    }
+}
+
+/// `AddressResolver` that uses the resolver of the operating system, the
+/// same way the address of a server is resolved when creating a sink.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemResolver;
+
+impl AddressResolver for SystemResolver {
+    fn resolve(&self, host: &str) -> io::Result<SocketAddr> {
+        host.to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No socket addresses yielded"))
+    }
+}
+
+type SharedResolver = Arc<dyn AddressResolver + Send + Sync + RefUnwindSafe>;
+
+/// Options for sinks that periodically resolve the address of the metric
+/// server again, for when the address of a server can change while an
+/// application is running (such as the IP of a service in Kubernetes).
+///
+/// The address is resolved when the sink is created and again when a
+/// metric is sent after the interval has elapsed, 60 seconds by default.
+/// Optionally, the address can also be resolved again after a number of
+/// consecutive errors sending metrics. Resolving again happens in a
+/// background thread so sending metrics never waits for a lookup: metrics
+/// are sent to the previous address until the new one is available. If
+/// resolving fails, the previous address is kept until the next attempt.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use cadence::ResolveOptions;
+///
+/// let options = ResolveOptions::default()
+///     .with_interval(Duration::from_secs(30))
+///     .with_error_threshold(5);
+/// ```
+#[derive(Clone)]
+pub struct ResolveOptions {
+    interval: Duration,
+    error_threshold: Option<u32>,
+    resolver: SharedResolver,
+}
+
+impl ResolveOptions {
+    /// Set how often to resolve the address of the server again, 60 seconds
+    /// by default.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Resolve the address of the server again after this many consecutive
+    /// errors sending metrics, disabled by default. `WouldBlock` errors from
+    /// non-blocking sockets are not counted since they don't indicate any
+    /// problem with the address.
+    pub fn with_error_threshold(mut self, errors: u32) -> Self {
+        self.error_threshold = Some(errors);
+        self
+    }
+
+    /// Use a custom `AddressResolver` instead of the resolver of the
+    /// operating system.
+    pub fn with_resolver<R>(mut self, resolver: R) -> Self
+    where
+        R: AddressResolver + Send + Sync + RefUnwindSafe + 'static,
+    {
+        self.resolver = Arc::new(resolver);
+        self
+    }
+}
+
+impl Default for ResolveOptions {
+    fn default() -> Self {
+        ResolveOptions {
+            interval: Duration::from_secs(60),
+            error_threshold: None,
+            resolver: Arc::new(SystemResolver),
+        }
+    }
+}
+
+impl fmt::Debug for ResolveOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResolveOptions")
+            .field("interval", &self.interval)
+            .field("error_threshold", &self.error_threshold)
+            .finish()
+    }
+}
+
+/// Address of a metric server that is resolved again periodically or after
+/// repeated errors.
+///
+/// Resolving happens in a background thread started by whichever caller
+/// notices that it's due, so callers never wait for a lookup. The current
+/// address keeps being used until the new one has been resolved and swapped
+/// in. Clones share the same address.
+#[derive(Clone)]
+pub(crate) struct ResolvingAddr {
+    inner: Arc<ResolvingAddrInner>,
+}
+
+struct ResolvingAddrInner {
+    host: String,
+    options: ResolveOptions,
+    addr: RwLock<SocketAddr>,
+    resolved_at: Mutex<Instant>,
+    resolving: AtomicBool,
+    errors: AtomicU32,
+}
+
+impl ResolvingAddr {
+    pub(crate) fn new(host: &str, options: ResolveOptions) -> io::Result<ResolvingAddr> {
+        let addr = options.resolver.resolve(host)?;
+
+        Ok(ResolvingAddr {
+            inner: Arc::new(ResolvingAddrInner {
+                host: host.to_string(),
+                options,
+                addr: RwLock::new(addr),
+                resolved_at: Mutex::new(Instant::now()),
+                resolving: AtomicBool::new(false),
+                errors: AtomicU32::new(0),
+            }),
+        })
+    }
+
+    /// Get the current address of the server, starting to resolve it again in
+    /// the background if due.
+    pub(crate) fn get(&self) -> SocketAddr {
+        if self.inner.is_due(Instant::now()) {
+            self.spawn_resolve();
+        }
+
+        *self.inner.addr.read().unwrap()
+    }
+
+    /// Keep track of consecutive errors sending to the current address.
+    pub(crate) fn record<T>(&self, res: &io::Result<T>) {
+        let errors = &self.inner.errors;
+        match res {
+            Ok(_) => {
+                // Avoid writing to the shared counter on every successful send
+                if errors.load(Ordering::Relaxed) != 0 {
+                    errors.store(0, Ordering::Relaxed);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(_) => {
+                errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn spawn_resolve(&self) {
+        // Only one lookup runs at a time, any other callers that notice it's
+        // due in the meantime use the current address.
+        if self
+            .inner
+            .resolving
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return;
+        }
+
+        let inner = Arc::clone(&self.inner);
+        let spawned = thread::Builder::new()
+            .name("cadence-resolver".to_string())
+            .spawn(move || inner.resolve());
+
+        // Try again the next time the address is due to be resolved
+        if spawned.is_err() {
+            self.inner.resolving.store(false, Ordering::Release);
+        }
+    }
+}
+
+impl ResolvingAddrInner {
+    fn is_due(&self, now: Instant) -> bool {
+        if self.resolving.load(Ordering::Relaxed) {
+            return false;
+        }
+
+        let too_many_errors = match self.options.error_threshold {
+            Some(threshold) => self.errors.load(Ordering::Relaxed) >= threshold,
+            None => false,
+        };
+
+        too_many_errors || now.duration_since(*self.resolved_at.lock().unwrap()) >= self.options.interval
+    }
+
+    fn resolve(&self) {
+        // The attempt counts even if it fails so that a broken resolver isn't
+        // called on every send, the current address is kept until next time.
+        if let Ok(addr) = self.options.resolver.resolve(&self.host) {
+            *self.addr.write().unwrap() = addr;
+        }
+
+        *self.resolved_at.lock().unwrap() = Instant::now();
+        self.errors.store(0, Ordering::Relaxed);
+        self.resolving.store(false, Ordering::Release);
+    }
+}
+
+impl fmt::Debug for ResolvingAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResolvingAddr")
+            .field("host", &self.inner.host)
+            .field("options", &self.inner.options)
+            .field("addr", &*self.inner.addr.read().unwrap())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ResolveOptions, ResolvingAddr};
+    use std::io;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Call `get` until it returns the expected address, since the address is
+    /// resolved again in the background.
+    fn wait_for(resolving: &ResolvingAddr, expected: &str) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while resolving.get().to_string() != expected {
+            assert!(Instant::now() < deadline, "address never changed to {}", expected);
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Options using a resolver that returns whatever address is currently in
+    /// `addr` and counts how many times it's called.
+    fn options(addr: &Arc<Mutex<io::Result<SocketAddr>>>, calls: &Arc<AtomicUsize>) -> ResolveOptions {
+        let addr = Arc::clone(addr);
+        let calls = Arc::clone(calls);
+
+        ResolveOptions::default().with_resolver(move |host: &str| {
+            assert_eq!("metrics.example.com:8125", host);
+            calls.fetch_add(1, Ordering::SeqCst);
+            match *addr.lock().unwrap() {
+                Ok(a) => Ok(a),
+                Err(ref e) => Err(io::Error::new(e.kind(), "lookup failed")),
+            }
+        })
+    }
+
+    #[test]
+    fn test_resolving_addr_interval() {
+        let addr = Arc::new(Mutex::new(Ok("127.0.0.1:8125".parse().unwrap())));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let resolving = ResolvingAddr::new(
+            "metrics.example.com:8125",
+            options(&addr, &calls).with_interval(Duration::from_millis(0)),
+        )
+        .unwrap();
+
+        assert_eq!("127.0.0.1:8125", resolving.get().to_string());
+        *addr.lock().unwrap() = Ok("127.0.0.2:8125".parse().unwrap());
+        wait_for(&resolving, "127.0.0.2:8125");
+        assert!(calls.load(Ordering::SeqCst) >= 2);
+    }
+
+    #[test]
+    fn test_resolving_addr_not_due() {
+        let addr = Arc::new(Mutex::new(Ok("127.0.0.1:8125".parse().unwrap())));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let resolving = ResolvingAddr::new("metrics.example.com:8125", options(&addr, &calls)).unwrap();
+
+        *addr.lock().unwrap() = Ok("127.0.0.2:8125".parse().unwrap());
+        assert_eq!("127.0.0.1:8125", resolving.get().to_string());
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_resolving_addr_error_threshold() {
+        let addr = Arc::new(Mutex::new(Ok("127.0.0.1:8125".parse().unwrap())));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let resolving = ResolvingAddr::new(
+            "metrics.example.com:8125",
+            options(&addr, &calls).with_error_threshold(2),
+        )
+        .unwrap();
+
+        *addr.lock().unwrap() = Ok("127.0.0.2:8125".parse().unwrap());
+        resolving.record::<()>(&Err(io::Error::from(io::ErrorKind::ConnectionRefused)));
+        resolving.record::<()>(&Err(io::Error::from(io::ErrorKind::WouldBlock)));
+        assert_eq!("127.0.0.1:8125", resolving.get().to_string());
+
+        resolving.record::<()>(&Err(io::Error::from(io::ErrorKind::ConnectionRefused)));
+        wait_for(&resolving, "127.0.0.2:8125");
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_resolving_addr_keeps_address_on_failure() {
+        let addr = Arc::new(Mutex::new(Ok("127.0.0.1:8125".parse().unwrap())));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let resolving = ResolvingAddr::new(
+            "metrics.example.com:8125",
+            options(&addr, &calls).with_interval(Duration::from_millis(0)),
+        )
+        .unwrap();
+
+        *addr.lock().unwrap() = Err(io::Error::from(io::ErrorKind::NotFound));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while calls.load(Ordering::SeqCst) < 3 {
+            assert_eq!("127.0.0.1:8125", resolving.get().to_string());
+            assert!(Instant::now() < deadline, "address never resolved again");
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!("127.0.0.1:8125", resolving.get().to_string());
+    }
+
+    #[test]
+    fn test_resolving_addr_does_not_block_callers() {
+        let addr = Arc::new(Mutex::new(Ok("127.0.0.1:8125".parse().unwrap())));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let resolving = ResolvingAddr::new(
+            "metrics.example.com:8125",
+            options(&addr, &calls).with_interval(Duration::from_millis(0)),
+        )
+        .unwrap();
+
+        // Hold the lock used by the resolver so that any lookup hangs, the
+        // current address must still be returned right away.
+        let guard = addr.lock().unwrap();
+        for _ in 0..10 {
+            assert_eq!("127.0.0.1:8125", resolving.get().to_string());
+        }
+        drop(guard);
+    }
+
+    #[test]
+    fn test_resolving_addr_initial_failure() {
+        let addr = Arc::new(Mutex::new(Err(io::Error::from(io::ErrorKind::NotFound))));
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        assert!(ResolvingAddr::new("metrics.example.com:8125", options(&addr, &calls)).is_err());
+    }
+}
@@ -0,0 +1,476 @@
+// Cadence - An extensible Statsd client for Rust!
+//
+// Copyright 2021 Nick Pillitteri
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::sinks::core::{emit_each, MetricSink};
+use crate::sinks::filter::parse_metric;
+use std::fmt;
+use std::io;
+use std::panic::RefUnwindSafe;
+use std::sync::{Arc, RwLock};
+
+type SharedSink = Arc<dyn MetricSink + Sync + Send + RefUnwindSafe>;
+
+// Default number of points on the hash ring for each node. More points
+// spread keys more evenly between nodes at the cost of a larger ring.
+const DEFAULT_REPLICAS: usize = 160;
+
+/// Hash used for placing nodes and keys on the ring.
+///
+/// This is 64-bit FNV-1a followed by the finalizer of MurmurHash3 to spread
+/// similar inputs (such as the names of points for the same node) across the
+/// ring. Unlike the hashers in the standard library, the output is stable
+/// across Rust versions and processes so that every instance of an
+/// application sends a given key to the same server.
+fn hash(bytes: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in bytes {
+        h ^= u64::from(*b);
+        h = h.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^ (h >> 33)
+}
+
+/// Named child sinks and the points on the ring each of them owns.
+struct Ring {
+    nodes: Vec<(String, SharedSink)>,
+    points: Vec<(u64, usize)>,
+}
+
+impl Ring {
+    fn new(nodes: Vec<(String, SharedSink)>, replicas: usize) -> Self {
+        let mut points = Vec::with_capacity(nodes.len() * replicas);
+        for (i, (name, _)) in nodes.iter().enumerate() {
+            for r in 0..replicas {
+                points.push((hash(format!("{}-{}", name, r).as_bytes()), i));
+            }
+        }
+
+        // Ties are broken by node name rather than the order nodes were added
+        // so that assignment only depends on which nodes are in the ring.
+        points.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| nodes[a.1].0.cmp(&nodes[b.1].0)));
+        Ring { nodes, points }
+    }
+
+    fn node_for(&self, key: &str) -> Option<usize> {
+        if self.points.is_empty() {
+            return None;
+        }
+
+        let h = hash(key.as_bytes());
+        let i = match self.points.binary_search_by(|p| p.0.cmp(&h)) {
+            Ok(i) => i,
+            Err(i) => i % self.points.len(),
+        };
+
+        Some(self.points[i].1)
+    }
+}
+
+/// Builder for creating a `ShardedMetricSink` from named child sinks.
+///
+/// Created via `ShardedMetricSink::builder()`.
+pub struct ShardedMetricSinkBuilder {
+    nodes: Vec<(String, SharedSink)>,
+    replicas: usize,
+}
+
+impl ShardedMetricSinkBuilder {
+    /// Add a child sink with a name that identifies it on the hash ring.
+    ///
+    /// The name, not the order nodes are added, decides which keys are sent
+    /// to the sink. Thus, it should stay the same across restarts and between
+    /// every instance of an application, the address of the server is a good
+    /// choice. Adding a node with the same name as an existing node replaces it.
+    pub fn with_node<T>(mut self, name: &str, sink: T) -> Self
+    where
+        T: MetricSink + Sync + Send + RefUnwindSafe + 'static,
+    {
+        self.nodes.retain(|(n, _)| n != name);
+        self.nodes.push((name.to_string(), Arc::new(sink)));
+        self
+    }
+
+    /// Set the number of points on the hash ring for each node, 160 by
+    /// default. Every instance of an application must use the same value.
+    pub fn with_replicas(mut self, replicas: usize) -> Self {
+        self.replicas = replicas.max(1);
+        self
+    }
+
+    /// Construct a new `ShardedMetricSink` from the nodes added so far.
+    pub fn build(self) -> ShardedMetricSink {
+        ShardedMetricSink {
+            replicas: self.replicas,
+            ring: Arc::new(RwLock::new(Arc::new(Ring::new(self.nodes, self.replicas)))),
+        }
+    }
+}
+
+impl fmt::Debug for ShardedMetricSinkBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = self.nodes.iter().map(|(n, _)| n.as_str()).collect();
+        f.debug_struct("ShardedMetricSinkBuilder")
+            .field("nodes", &names)
+            .field("replicas", &self.replicas)
+            .finish()
+    }
+}
+
+/// Implementation of a `MetricSink` that sends each metric to one of several
+/// child sinks based on its key, so that every metric with a given key lands
+/// on the same Statsd server and is aggregated correctly.
+///
+/// Metrics are assigned to child sinks (nodes) using consistent hashing of
+/// the key of the metric, including any prefix but not tags. When a node is
+/// added or removed, only the keys assigned to that node move: about `1/N`
+/// of keys for `N` nodes. Assignment is deterministic, so separate instances
+/// of an application configured with the same node names agree on where each
+/// key is sent.
+///
+/// Child sinks may be buffered. Calling `.flush()` flushes every node and
+/// returns the first error, if any. Nodes removed at runtime are flushed
+/// when they are removed.
+///
+/// Clones of a `ShardedMetricSink` share the same nodes. Thus, callers can
+/// keep a clone of the sink before using it to build a client and use that
+/// clone to add or remove nodes at runtime.
+///
+/// # Example
+///
+/// ```
+/// use cadence::prelude::*;
+/// use cadence::{NopMetricSink, ShardedMetricSink, StatsdClient};
+///
+/// let sink = ShardedMetricSink::builder()
+///     .with_node("statsd-1:8125", NopMetricSink)
+///     .with_node("statsd-2:8125", NopMetricSink)
+///     .build();
+///
+/// let client = StatsdClient::from_sink("my.prefix", sink.clone());
+/// client.count("some.counter", 1).unwrap();
+///
+/// // Every metric with this key is sent to the same node
+/// let node = sink.node_for("my.prefix.some.counter").unwrap();
+/// ```
+#[derive(Clone)]
+pub struct ShardedMetricSink {
+    replicas: usize,
+    ring: Arc<RwLock<Arc<Ring>>>,
+}
+
+impl ShardedMetricSink {
+    /// Create a builder for adding nodes to a new `ShardedMetricSink`.
+    pub fn builder() -> ShardedMetricSinkBuilder {
+        ShardedMetricSinkBuilder {
+            nodes: Vec::new(),
+            replicas: DEFAULT_REPLICAS,
+        }
+    }
+
+    /// Add a node to this sink and all clones of it, replacing any existing
+    /// node with the same name.
+    ///
+    /// Any error returned is the result of flushing the replaced node, the
+    /// new node is in use regardless.
+    pub fn add_node<T>(&self, name: &str, sink: T) -> io::Result<()>
+    where
+        T: MetricSink + Sync + Send + RefUnwindSafe + 'static,
+    {
+        let new: SharedSink = Arc::new(sink);
+        let previous = self.update(|nodes| {
+            let previous = nodes.iter().position(|(n, _)| n == name).map(|i| nodes.remove(i).1);
+            nodes.push((name.to_string(), new));
+            previous
+        });
+
+        match previous {
+            Some(s) => s.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Remove the node with the given name from this sink and all clones of
+    /// it, returning false if there was no such node.
+    ///
+    /// The node is flushed after it is removed. Errors flushing it are
+    /// ignored since it is no longer being used.
+    pub fn remove_node(&self, name: &str) -> bool {
+        let previous = self.update(|nodes| nodes.iter().position(|(n, _)| n == name).map(|i| nodes.remove(i).1));
+
+        match previous {
+            Some(s) => {
+                let _ = s.flush();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Return the names of the nodes currently in use.
+    pub fn nodes(&self) -> Vec<String> {
+        self.current().nodes.iter().map(|(n, _)| n.clone()).collect()
+    }
+
+    /// Return the name of the node that metrics with the given key are sent
+    /// to, or `None` if there are no nodes.
+    pub fn node_for(&self, key: &str) -> Option<String> {
+        let ring = self.current();
+        ring.node_for(key).map(|i| ring.nodes[i].0.clone())
+    }
+
+    /// Build a new ring from the current nodes after modifying them, returning
+    /// the result of the modification. The previous ring stays in use by any
+    /// in progress calls until they are done with it.
+    fn update<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut Vec<(String, SharedSink)>) -> R,
+    {
+        let mut ring = self.ring.write().unwrap();
+        let mut nodes = ring.nodes.clone();
+        let out = f(&mut nodes);
+        *ring = Arc::new(Ring::new(nodes, self.replicas));
+        out
+    }
+
+    fn current(&self) -> Arc<Ring> {
+        self.ring.read().unwrap().clone()
+    }
+}
+
+impl MetricSink for ShardedMetricSink {
+    fn emit(&self, metric: &str) -> io::Result<usize> {
+        let ring = self.current();
+        let (key, _) = parse_metric(metric);
+
+        match ring.node_for(key) {
+            Some(i) => ring.nodes[i].1.emit(metric),
+            None => Err(io::Error::new(io::ErrorKind::Other, "No nodes to send metrics to")),
+        }
+    }
+
+    fn emit_batch(&self, metrics: &[&str]) -> io::Result<usize> {
+        let ring = self.current();
+        if ring.nodes.is_empty() && !metrics.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::Other, "No nodes to send metrics to"));
+        }
+
+        // Group metrics by node so that each node gets a single batch with
+        // the metrics in the same order they were given.
+        let mut shards: Vec<Vec<&str>> = vec![Vec::new(); ring.nodes.len()];
+        for metric in metrics {
+            let (key, _) = parse_metric(metric);
+            if let Some(i) = ring.node_for(key) {
+                shards[i].push(metric);
+            }
+        }
+
+        let batches = shards.iter().enumerate().filter(|(_, s)| !s.is_empty());
+        emit_each(batches, |(i, shard)| ring.nodes[i].1.emit_batch(shard))
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let ring = self.current();
+        let mut res = Ok(());
+
+        for (_, sink) in ring.nodes.iter() {
+            let flushed = sink.flush();
+            if res.is_ok() {
+                res = flushed;
+            }
+        }
+
+        res
+    }
+}
+
+impl fmt::Debug for ShardedMetricSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShardedMetricSink")
+            .field("nodes", &self.nodes())
+            .field("replicas", &self.replicas)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hash, ShardedMetricSink};
+    use crate::sinks::{BufferedSpyMetricSink, MetricSink, NopMetricSink, SpyMetricSink};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    fn keys() -> Vec<String> {
+        (0..1000).map(|i| format!("app.metric.{}", i)).collect()
+    }
+
+    fn assignments(sink: &ShardedMetricSink) -> HashMap<String, String> {
+        keys()
+            .into_iter()
+            .map(|k| {
+                let node = sink.node_for(&k).unwrap();
+                (k, node)
+            })
+            .collect()
+    }
+
+    fn new_sink(names: &[&str]) -> ShardedMetricSink {
+        names
+            .iter()
+            .fold(ShardedMetricSink::builder(), |b, n| b.with_node(n, NopMetricSink))
+            .build()
+    }
+
+    #[test]
+    fn test_hash_stable() {
+        // These values must never change, since they determine which server
+        // gets each key across versions of an application.
+        assert_eq!(0xefd0_1f60_ba99_2926, hash(b""));
+        assert_eq!(0xb26c_e246_c44f_b255, hash(b"some.key"));
+    }
+
+    #[test]
+    fn test_sharded_metric_sink_same_key_same_node() {
+        let writer1 = Arc::new(Mutex::new(Vec::new()));
+        let writer2 = Arc::new(Mutex::new(Vec::new()));
+        let sink = ShardedMetricSink::builder()
+            .with_node("a", SpyMetricSink::from(writer1.clone()))
+            .with_node("b", SpyMetricSink::from(writer2.clone()))
+            .build();
+
+        for _ in 0..10 {
+            sink.emit("some.key:1|c|#host:web1").unwrap();
+            sink.emit("some.key:2|c|#host:web2").unwrap();
+        }
+
+        // Tags don't affect which node is used
+        let written1 = writer1.lock().unwrap().len();
+        let written2 = writer2.lock().unwrap().len();
+        assert!(written1 == 0 || written2 == 0);
+        assert!(written1 + written2 > 0);
+    }
+
+    #[test]
+    fn test_sharded_metric_sink_distribution() {
+        let sink = new_sink(&["a", "b", "c"]);
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for node in assignments(&sink).values() {
+            *counts.entry(node.clone()).or_insert(0) += 1;
+        }
+
+        for name in &["a", "b", "c"] {
+            let count = counts[*name];
+            assert!(count > 200 && count < 500, "{} got {} keys", name, count);
+        }
+    }
+
+    #[test]
+    fn test_sharded_metric_sink_order_independent() {
+        let forward = new_sink(&["a", "b", "c"]);
+        let reverse = new_sink(&["c", "b", "a"]);
+
+        assert_eq!(assignments(&forward), assignments(&reverse));
+    }
+
+    #[test]
+    fn test_sharded_metric_sink_remove_node_stable() {
+        let sink = new_sink(&["a", "b", "c"]);
+        let before = assignments(&sink);
+
+        assert!(sink.remove_node("b"));
+        assert!(!sink.remove_node("b"));
+        let after = assignments(&sink);
+
+        for (key, node) in before.iter() {
+            if node != "b" {
+                assert_eq!(node, &after[key]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sharded_metric_sink_add_node_stable() {
+        let sink = new_sink(&["a", "b", "c"]);
+        let clone = sink.clone();
+        let before = assignments(&sink);
+
+        clone.add_node("d", NopMetricSink).unwrap();
+        let after = assignments(&sink);
+
+        let mut moved = 0;
+        for (key, node) in after.iter() {
+            if node != "d" {
+                assert_eq!(&before[key], node);
+            } else {
+                moved += 1;
+            }
+        }
+
+        assert!(moved > 0);
+        assert_eq!(vec!["a", "b", "c", "d"], sink.nodes());
+    }
+
+    #[test]
+    fn test_sharded_metric_sink_buffered_nodes() {
+        let writer = Arc::new(Mutex::new(Vec::new()));
+        let sink = ShardedMetricSink::builder()
+            .with_node("a", BufferedSpyMetricSink::with_capacity(writer.clone(), 64))
+            .build();
+
+        sink.emit("foo:1|c").unwrap();
+        assert!(writer.lock().unwrap().is_empty());
+
+        sink.flush().unwrap();
+        assert_eq!(b"foo:1|c\n".to_vec(), *writer.lock().unwrap());
+    }
+
+    #[test]
+    fn test_sharded_metric_sink_emit_batch() {
+        let new_spies = || {
+            let writers = vec![Arc::new(Mutex::new(Vec::new())), Arc::new(Mutex::new(Vec::new()))];
+            let sink = ShardedMetricSink::builder()
+                .with_node("a", BufferedSpyMetricSink::with_capacity(writers[0].clone(), 1024))
+                .with_node("b", BufferedSpyMetricSink::with_capacity(writers[1].clone(), 1024))
+                .build();
+            (sink, writers)
+        };
+
+        let metrics: Vec<String> = keys().iter().take(20).map(|k| format!("{}:1|c", k)).collect();
+        let metrics: Vec<&str> = metrics.iter().map(|m| m.as_str()).collect();
+
+        let (single, single_writers) = new_spies();
+        for metric in metrics.iter() {
+            single.emit(metric).unwrap();
+        }
+        single.flush().unwrap();
+
+        let (batched, batched_writers) = new_spies();
+        batched.emit_batch(&metrics).unwrap();
+        batched.flush().unwrap();
+
+        // Each node gets the same metrics in the same order either way
+        for (a, b) in single_writers.iter().zip(batched_writers.iter()) {
+            assert!(!a.lock().unwrap().is_empty());
+            assert_eq!(*a.lock().unwrap(), *b.lock().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_sharded_metric_sink_no_nodes() {
+        let sink = ShardedMetricSink::builder().build();
+        assert!(sink.emit("foo:1|c").is_err());
+        assert!(sink.emit_batch(&["foo:1|c"]).is_err());
+        assert_eq!(None, sink.node_for("foo"));
+    }
+}
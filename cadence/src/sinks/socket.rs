@@ -0,0 +1,102 @@
+// Cadence - An extensible Statsd client for Rust!
+//
+// Copyright 2021 Nick Pillitteri
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use socket2::SockRef;
+use std::io;
+use std::net::{TcpStream, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::{UnixDatagram, UnixStream};
+
+/// Query or change the size of the kernel send buffer (`SO_SNDBUF`) of a
+/// socket used by a sink.
+///
+/// For datagram sockets, this limits how much can be sent before sends start
+/// to block (or fail for non-blocking sockets). For Unix datagram sockets on
+/// Linux it also limits the size of a single datagram, so the buffer of a
+/// `BufferedUnixMetricSink` must not be larger than it.
+///
+/// The MTU of the network path used by a UDP socket isn't available through
+/// this trait since it can only be read with platform specific options. The
+/// default buffer size of `BufferedUdpMetricSink` fits within the MTU of an
+/// Ethernet network.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::net::UdpSocket;
+/// use cadence::{BufferedUdpMetricSink, SocketSendBuffer, DEFAULT_PORT};
+///
+/// let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+/// socket.set_nonblocking(true).unwrap();
+/// socket.set_send_buffer_size(1024 * 1024).unwrap();
+///
+/// println!("Send buffer is {} bytes", socket.send_buffer_size().unwrap());
+///
+/// let host = ("metrics.example.com", DEFAULT_PORT);
+/// let sink = BufferedUdpMetricSink::from(host, socket);
+/// ```
+pub trait SocketSendBuffer {
+    /// Return the size of the send buffer of this socket in bytes.
+    fn send_buffer_size(&self) -> io::Result<usize>;
+
+    /// Set the size of the send buffer of this socket in bytes.
+    ///
+    /// The operating system may adjust the requested size. Linux doubles it
+    /// to account for bookkeeping overhead and caps it to `net.core.wmem_max`
+    /// so `.send_buffer_size()` should be used to check the size actually in
+    /// use.
+    fn set_send_buffer_size(&self, size: usize) -> io::Result<()>;
+}
+
+macro_rules! impl_socket_send_buffer {
+    ($t:ty) => {
+        impl SocketSendBuffer for $t {
+            fn send_buffer_size(&self) -> io::Result<usize> {
+                SockRef::from(self).send_buffer_size()
+            }
+
+            fn set_send_buffer_size(&self, size: usize) -> io::Result<()> {
+                SockRef::from(self).set_send_buffer_size(size)
+            }
+        }
+    };
+}
+
+impl_socket_send_buffer!(UdpSocket);
+impl_socket_send_buffer!(TcpStream);
+#[cfg(unix)]
+impl_socket_send_buffer!(UnixDatagram);
+#[cfg(unix)]
+impl_socket_send_buffer!(UnixStream);
+
+#[cfg(test)]
+mod tests {
+    use super::SocketSendBuffer;
+    use std::net::UdpSocket;
+
+    #[test]
+    fn test_udp_socket_send_buffer_size() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert!(socket.send_buffer_size().unwrap() > 0);
+
+        socket.set_send_buffer_size(64 * 1024).unwrap();
+        assert!(socket.send_buffer_size().unwrap() >= 64 * 1024);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_datagram_send_buffer_size() {
+        use std::os::unix::net::UnixDatagram;
+
+        let socket = UnixDatagram::unbound().unwrap();
+        socket.set_send_buffer_size(32 * 1024).unwrap();
+        assert!(socket.send_buffer_size().unwrap() >= 32 * 1024);
+    }
+}
@@ -9,11 +9,13 @@
 // except according to those terms.
 
 use crate::io::MultiLineWriter;
-use crate::sinks::core::MetricSink;
+use crate::sinks::core::{emit_each, MetricSink};
+use crate::sinks::flush::PeriodicFlush;
 use std::fmt::{self, Debug, Formatter};
 use std::io::{self, Write};
 use std::panic::RefUnwindSafe;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 // Default size of the buffer for buffered metric sinks, picked for
 // consistency with the UDP implementation.
@@ -70,6 +72,11 @@ impl MetricSink for SpyMetricSink {
         let mut writer = self.writer.lock().unwrap();
         writer.write(metric.as_bytes())
     }
+
+    fn emit_batch(&self, metrics: &[&str]) -> io::Result<usize> {
+        let mut writer = self.writer.lock().unwrap();
+        emit_each(metrics, |metric| writer.write(metric.as_bytes()))
+    }
 }
 
 impl Debug for SpyMetricSink {
@@ -93,9 +100,8 @@ impl Debug for SpyMetricSink {
 /// the underlying writer and then the metric is written to the buffer. The
 /// buffer is also flushed when this sink is destroyed.
 ///
-/// The default size of the buffer is 512 bytes. This is to be consistent with
-/// the default for the `BufferedUdpMetricSink`. The buffer size can be customized
-/// using the `with_capacity` method to create the sink if desired.
+/// The default size of the buffer is 512 bytes. The buffer size can be
+/// customized using the `with_capacity` method to create the sink if desired.
 ///
 /// If a metric larger than the buffer is emitted, it will be written
 /// directly to the underlying writer, bypassing the buffer.
@@ -106,7 +112,8 @@ impl Debug for SpyMetricSink {
 /// throughput use cases, it may make more sense to use the `SpyMetricSink`
 /// since it sends metrics immediately with no buffering.
 pub struct BufferedSpyMetricSink {
-    writer: Mutex<MultiLineWriter<SpyWriter>>,
+    writer: Arc<Mutex<MultiLineWriter<SpyWriter>>>,
+    flush: Option<PeriodicFlush>,
 }
 
 impl BufferedSpyMetricSink {
@@ -116,9 +123,18 @@ impl BufferedSpyMetricSink {
 
     pub fn with_capacity(writer: Arc<Mutex<dyn Write + Send + RefUnwindSafe + 'static>>, cap: usize) -> Self {
         BufferedSpyMetricSink {
-            writer: Mutex::new(MultiLineWriter::new(SpyWriter::from(writer), cap)),
+            writer: Arc::new(Mutex::new(MultiLineWriter::new(SpyWriter::from(writer), cap))),
+            flush: None,
         }
     }
+
+    /// Flush metrics in the buffer to the underlying writer at least once
+    /// every `interval`, even if the buffer isn't full. See
+    /// `BufferedUdpMetricSink::with_max_latency` for details.
+    pub fn with_max_latency(mut self, interval: Duration) -> Self {
+        self.flush = Some(PeriodicFlush::spawn(&self.writer, interval));
+        self
+    }
 }
 
 impl MetricSink for BufferedSpyMetricSink {
@@ -127,6 +143,11 @@ impl MetricSink for BufferedSpyMetricSink {
         writer.write(metric.as_bytes())
     }
 
+    fn emit_batch(&self, metrics: &[&str]) -> io::Result<usize> {
+        let mut writer = self.writer.lock().unwrap();
+        emit_each(metrics, |metric| writer.write(metric.as_bytes()))
+    }
+
     fn flush(&self) -> io::Result<()> {
         let mut writer = self.writer.lock().unwrap();
         writer.flush()
@@ -145,7 +166,10 @@ impl Debug for BufferedSpyMetricSink {
 #[cfg(test)]
 mod test {
     use super::{BufferedSpyMetricSink, MetricSink, SpyMetricSink};
+    use crate::client::{Counted, StatsdClient};
     use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
 
     // Get a copy of the contents of the shared writer and make sure to
     // drop the lock before any assertions, otherwise the mutex becomes
@@ -181,6 +205,17 @@ mod test {
         assert_eq!("foo:54|c\nfoo:67|c\n".as_bytes(), contents.as_slice());
     }
 
+    #[test]
+    fn test_buffered_spy_metric_sink_emit_batch() {
+        let writer = Arc::new(Mutex::new(Vec::new()));
+        let sink = BufferedSpyMetricSink::with_capacity(writer.clone(), 64);
+        sink.emit_batch(&["foo:54|c", "foo:67|c", "bar:1|g"]).unwrap();
+        sink.flush().unwrap();
+
+        let contents = copy_buffer(writer);
+        assert_eq!("foo:54|c\nfoo:67|c\nbar:1|g\n".as_bytes(), contents.as_slice());
+    }
+
     #[test]
     fn test_buffered_spy_metric_sink_flush() {
         let writer = Arc::new(Mutex::new(Vec::new()));
@@ -196,4 +231,22 @@ mod test {
         assert_eq!("foo:54|c\nfoo:67|c\n".as_bytes(), contents.as_slice());
         assert!(flush.is_ok());
     }
+
+    #[test]
+    fn test_buffered_spy_metric_sink_max_latency() {
+        let writer = Arc::new(Mutex::new(Vec::new()));
+        let sink = BufferedSpyMetricSink::with_capacity(writer.clone(), 64).with_max_latency(Duration::from_millis(10));
+        let client = StatsdClient::from_sink("prefix", sink);
+        client.count("foo", 54).unwrap();
+
+        // Nothing calls flush, the buffer is flushed from the background thread
+        let start = Instant::now();
+        while copy_buffer(writer.clone()).is_empty() {
+            assert!(start.elapsed() < Duration::from_secs(5), "buffer was not flushed");
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        let contents = copy_buffer(writer);
+        assert_eq!("prefix.foo:54|c\n".as_bytes(), contents.as_slice());
+    }
 }
@@ -0,0 +1,173 @@
+// Cadence - An extensible Statsd client for Rust!
+//
+// Copyright 2021 Nick Pillitteri
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::sinks::core::MetricSink;
+use std::fmt;
+use std::io;
+use std::panic::RefUnwindSafe;
+use std::sync::{Arc, RwLock};
+
+type SharedSink = Arc<dyn MetricSink + Sync + Send + RefUnwindSafe>;
+
+/// Implementation of a `MetricSink` that delegates to another sink which
+/// can be replaced at runtime.
+///
+/// This sink is meant for applications that reload their configuration
+/// while running: if the address of the Statsd server or the transport used
+/// to reach it changes, the sink behind a `StatsdClient` (and all clones of
+/// it) can be replaced without having to rebuild the client.
+///
+/// Clones of a `SwappableMetricSink` share the same underlying sink. Thus,
+/// callers keep a clone of the sink before using it to build a client and
+/// use that clone to replace the sink later.
+///
+/// Replacing the sink does not block callers emitting metrics for longer
+/// than it takes to change a pointer: each call to `.emit()` uses whichever
+/// sink was current when it started. The previous sink is flushed after it
+/// has been replaced and dropped once any in progress calls are done with it.
+///
+/// # Example
+///
+/// ```
+/// use cadence::prelude::*;
+/// use cadence::{NopMetricSink, StatsdClient, SwappableMetricSink, UdpMetricSink, DEFAULT_PORT};
+/// use std::net::UdpSocket;
+///
+/// let sink = SwappableMetricSink::new(NopMetricSink);
+/// let client = StatsdClient::from_sink("my.prefix", sink.clone());
+/// client.count("my.counter.thing", 1);
+///
+/// // Later, once the address of the server is known
+/// let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+/// let host = ("localhost", DEFAULT_PORT);
+/// sink.swap(UdpMetricSink::from(host, socket).unwrap()).unwrap();
+///
+/// client.count("my.counter.thing", 1);
+/// ```
+#[derive(Clone)]
+pub struct SwappableMetricSink {
+    current: Arc<RwLock<SharedSink>>,
+}
+
+impl SwappableMetricSink {
+    /// Construct a new `SwappableMetricSink` that initially delegates
+    /// to the provided sink.
+    pub fn new<T>(sink: T) -> Self
+    where
+        T: MetricSink + Sync + Send + RefUnwindSafe + 'static,
+    {
+        SwappableMetricSink {
+            current: Arc::new(RwLock::new(Arc::new(sink))),
+        }
+    }
+
+    /// Replace the sink used by this sink and all clones of it, flushing
+    /// the previous sink.
+    ///
+    /// Metrics emitted after this method is called are sent to the new sink.
+    /// Any error returned is the result of flushing the previous sink; the
+    /// new sink is in use regardless.
+    pub fn swap<T>(&self, sink: T) -> io::Result<()>
+    where
+        T: MetricSink + Sync + Send + RefUnwindSafe + 'static,
+    {
+        let previous = {
+            let mut current = self.current.write().unwrap();
+            std::mem::replace(&mut *current, Arc::new(sink))
+        };
+
+        // Flush outside of the lock so that callers emitting metrics
+        // to the new sink don't have to wait for the previous one.
+        previous.flush()
+    }
+
+    fn current(&self) -> SharedSink {
+        self.current.read().unwrap().clone()
+    }
+}
+
+impl MetricSink for SwappableMetricSink {
+    fn emit(&self, metric: &str) -> io::Result<usize> {
+        self.current().emit(metric)
+    }
+
+    fn emit_batch(&self, metrics: &[&str]) -> io::Result<usize> {
+        self.current().emit_batch(metrics)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.current().flush()
+    }
+}
+
+impl fmt::Debug for SwappableMetricSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SwappableMetricSink {{ Arc {{ RwLock {{ ... }} }} }}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SwappableMetricSink;
+    use crate::sinks::{BufferedSpyMetricSink, MetricSink, NopMetricSink, SpyMetricSink};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    #[test]
+    fn test_swappable_metric_sink_swap() {
+        let first = Arc::new(Mutex::new(Vec::new()));
+        let second = Arc::new(Mutex::new(Vec::new()));
+
+        let sink = SwappableMetricSink::new(SpyMetricSink::from(first.clone()));
+        let clone = sink.clone();
+        sink.emit("foo:1|c").unwrap();
+
+        clone.swap(SpyMetricSink::from(second.clone())).unwrap();
+        sink.emit("bar:2|c").unwrap();
+
+        assert_eq!("foo:1|c".as_bytes(), first.lock().unwrap().as_slice());
+        assert_eq!("bar:2|c".as_bytes(), second.lock().unwrap().as_slice());
+    }
+
+    #[test]
+    fn test_swappable_metric_sink_flushes_previous() {
+        let writer = Arc::new(Mutex::new(Vec::new()));
+
+        let sink = SwappableMetricSink::new(BufferedSpyMetricSink::with_capacity(writer.clone(), 64));
+        sink.emit("foo:1|c").unwrap();
+        assert!(writer.lock().unwrap().is_empty());
+
+        sink.swap(NopMetricSink).unwrap();
+        assert_eq!("foo:1|c\n".as_bytes(), writer.lock().unwrap().as_slice());
+    }
+
+    #[test]
+    fn test_swappable_metric_sink_concurrent_emit() {
+        let sink = SwappableMetricSink::new(NopMetricSink);
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let sink = sink.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        sink.emit("foo:1|c").unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for _ in 0..100 {
+            sink.swap(NopMetricSink).unwrap();
+        }
+
+        for t in threads {
+            t.join().unwrap();
+        }
+    }
+}
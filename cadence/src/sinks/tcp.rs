@@ -0,0 +1,402 @@
+// Cadence - An extensible Statsd client for Rust!
+//
+// Copyright 2021 Nick Pillitteri
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::io::MultiLineWriter;
+use crate::sinks::core::{emit_each, MetricSink};
+use crate::sinks::flush::PeriodicFlush;
+use crate::sinks::reconnect::{ConnectionOptions, Connector, Reconnecting};
+use crate::sinks::udp::get_addr;
+use crate::types::MetricResult;
+
+// Default size of the buffer for buffered metric sinks. There's no
+// packet size to stay under with TCP so this is picked to allow a
+// reasonable number of metrics to be sent with each write.
+pub(crate) const DEFAULT_BUFFER_SIZE: usize = 8192;
+
+/// Establishes TCP connections to a particular address
+#[derive(Debug)]
+struct TcpConnector {
+    addr: SocketAddr,
+}
+
+impl Connector for TcpConnector {
+    type Stream = TcpStream;
+
+    fn connect(&self, options: &ConnectionOptions) -> io::Result<TcpStream> {
+        let stream = TcpStream::connect_timeout(&self.addr, options.connect_timeout)?;
+        stream.set_write_timeout(Some(options.write_timeout))?;
+        stream.set_nodelay(true)?;
+        Ok(stream)
+    }
+}
+
+/// Adapter for writing newline terminated metrics to a TCP connection
+/// via the `Write` trait.
+///
+/// Each call to `.write()` is sent as a single frame, followed by a newline
+/// if it doesn't already end with one. This ensures that metrics written
+/// directly, bypassing the buffer of a `MultiLineWriter`, are still newline
+/// terminated.
+#[derive(Debug)]
+pub(crate) struct TcpWriteAdapter {
+    conn: Reconnecting<TcpConnector>,
+    frame: Vec<u8>,
+}
+
+impl TcpWriteAdapter {
+    fn new(addr: SocketAddr, options: ConnectionOptions) -> TcpWriteAdapter {
+        TcpWriteAdapter {
+            conn: Reconnecting::new(TcpConnector { addr }, options),
+            frame: Vec::new(),
+        }
+    }
+}
+
+impl Write for TcpWriteAdapter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.ends_with(b"\n") {
+            self.conn.send(buf)?;
+        } else {
+            self.frame.clear();
+            self.frame.extend_from_slice(buf);
+            self.frame.push(b'\n');
+            self.conn.send(&self.frame)?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Implementation of a `MetricSink` that emits metrics over a TCP connection.
+///
+/// Each metric is sent, followed by a newline, to the Statsd server when the
+/// `.emit()` method is called, in the thread of the caller.
+///
+/// The connection to the server is established the first time a metric is
+/// emitted and re-established if it is lost, waiting between failed attempts
+/// to connect. Metrics emitted while the sink is not connected are discarded
+/// and an error is returned. See `ConnectionOptions` for details.
+///
+/// Since connecting and writing block the caller (up to the configured
+/// timeouts), this sink is typically wrapped by a `QueuingMetricSink`.
+#[derive(Debug)]
+pub struct TcpMetricSink {
+    writer: Mutex<TcpWriteAdapter>,
+}
+
+impl TcpMetricSink {
+    /// Construct a new `TcpMetricSink` instance with the default
+    /// connection options.
+    ///
+    /// The address should be the address of the remote metric server to
+    /// emit metrics to over TCP.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use cadence::{TcpMetricSink, DEFAULT_PORT};
+    ///
+    /// let host = ("metrics.example.com", DEFAULT_PORT);
+    /// let sink = TcpMetricSink::from(host);
+    /// ```
+    ///
+    /// # Failures
+    ///
+    /// This method may fail if:
+    ///
+    /// * It is unable to resolve the hostname of the metric server.
+    /// * The host address is otherwise unable to be parsed
+    pub fn from<A>(to_addr: A) -> MetricResult<TcpMetricSink>
+    where
+        A: ToSocketAddrs,
+    {
+        Self::with_options(to_addr, ConnectionOptions::default())
+    }
+
+    /// Construct a new `TcpMetricSink` instance with the given connection
+    /// options.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use cadence::{ConnectionOptions, TcpMetricSink, DEFAULT_PORT};
+    ///
+    /// let host = ("metrics.example.com", DEFAULT_PORT);
+    /// let options = ConnectionOptions::default().with_write_timeout(Duration::from_millis(100));
+    /// let sink = TcpMetricSink::with_options(host, options);
+    /// ```
+    ///
+    /// # Failures
+    ///
+    /// This method may fail if:
+    ///
+    /// * It is unable to resolve the hostname of the metric server.
+    /// * The host address is otherwise unable to be parsed
+    pub fn with_options<A>(to_addr: A, options: ConnectionOptions) -> MetricResult<TcpMetricSink>
+    where
+        A: ToSocketAddrs,
+    {
+        let addr = get_addr(to_addr)?;
+        Ok(TcpMetricSink {
+            writer: Mutex::new(TcpWriteAdapter::new(addr, options)),
+        })
+    }
+}
+
+impl MetricSink for TcpMetricSink {
+    fn emit(&self, metric: &str) -> io::Result<usize> {
+        let mut writer = self.writer.lock().unwrap();
+        writer.write(metric.as_bytes())
+    }
+
+    fn emit_batch(&self, metrics: &[&str]) -> io::Result<usize> {
+        let mut writer = self.writer.lock().unwrap();
+        emit_each(metrics, |metric| writer.write(metric.as_bytes()))
+    }
+}
+
+/// Implementation of a `MetricSink` that buffers metrics before sending
+/// them over a TCP connection.
+///
+/// Metrics are line buffered, meaning that a trailing "\n" is added after
+/// each metric written to this sink. When the buffer is sufficiently full
+/// and a write is attempted, the contents of the buffer are sent over the
+/// connection and then the metric is written to the buffer. The buffer is
+/// also flushed when this sink is destroyed.
+///
+/// The default size of the buffer is 8192 bytes. The buffer size can be
+/// customized using the `with_capacity` method to create the sink if desired.
+///
+/// If a metric larger than the buffer is emitted, any metrics already in the
+/// buffer are sent first and then it's sent directly over the connection
+/// (with a trailing "\n"), bypassing the buffer. Metrics are always sent in
+/// the order they were emitted.
+///
+/// The connection is managed the same way as the `TcpMetricSink`. If the
+/// buffer cannot be sent because the sink is not connected, the metrics in
+/// it are kept and sent once the connection is re-established while new
+/// metrics are discarded until then.
+#[derive(Debug)]
+pub struct BufferedTcpMetricSink {
+    buffer: Arc<Mutex<MultiLineWriter<TcpWriteAdapter>>>,
+    flush: Option<PeriodicFlush>,
+}
+
+impl BufferedTcpMetricSink {
+    /// Construct a new `BufferedTcpMetricSink` instance with a default
+    /// buffer size of 8192 bytes and the default connection options.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use cadence::{BufferedTcpMetricSink, DEFAULT_PORT};
+    ///
+    /// let host = ("metrics.example.com", DEFAULT_PORT);
+    /// let sink = BufferedTcpMetricSink::from(host);
+    /// ```
+    ///
+    /// # Failures
+    ///
+    /// This method may fail if:
+    ///
+    /// * It is unable to resolve the hostname of the metric server.
+    /// * The host address is otherwise unable to be parsed
+    pub fn from<A>(sink_addr: A) -> MetricResult<BufferedTcpMetricSink>
+    where
+        A: ToSocketAddrs,
+    {
+        Self::with_capacity(sink_addr, DEFAULT_BUFFER_SIZE)
+    }
+
+    /// Construct a new `BufferedTcpMetricSink` instance with a custom
+    /// buffer size and the default connection options.
+    ///
+    /// # Failures
+    ///
+    /// This method may fail if:
+    ///
+    /// * It is unable to resolve the hostname of the metric server.
+    /// * The host address is otherwise unable to be parsed
+    pub fn with_capacity<A>(sink_addr: A, cap: usize) -> MetricResult<BufferedTcpMetricSink>
+    where
+        A: ToSocketAddrs,
+    {
+        Self::with_options(sink_addr, cap, ConnectionOptions::default())
+    }
+
+    /// Construct a new `BufferedTcpMetricSink` instance with a custom
+    /// buffer size and the given connection options.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use cadence::{BufferedTcpMetricSink, ConnectionOptions, DEFAULT_PORT};
+    ///
+    /// let host = ("metrics.example.com", DEFAULT_PORT);
+    /// let options = ConnectionOptions::default().with_connect_timeout(Duration::from_millis(250));
+    /// let sink = BufferedTcpMetricSink::with_options(host, 16384, options);
+    /// ```
+    ///
+    /// # Failures
+    ///
+    /// This method may fail if:
+    ///
+    /// * It is unable to resolve the hostname of the metric server.
+    /// * The host address is otherwise unable to be parsed
+    pub fn with_options<A>(sink_addr: A, cap: usize, options: ConnectionOptions) -> MetricResult<BufferedTcpMetricSink>
+    where
+        A: ToSocketAddrs,
+    {
+        let addr = get_addr(sink_addr)?;
+        Ok(BufferedTcpMetricSink {
+            buffer: Arc::new(Mutex::new(
+                MultiLineWriter::new(TcpWriteAdapter::new(addr, options), cap).keeping_order(),
+            )),
+            flush: None,
+        })
+    }
+
+    /// Flush metrics in the buffer at least once every `interval`, even if
+    /// the buffer isn't full, using a background thread that stops when this
+    /// sink is dropped. See `BufferedUdpMetricSink::with_max_latency`.
+    pub fn with_max_latency(mut self, interval: Duration) -> Self {
+        self.flush = Some(PeriodicFlush::spawn(&self.buffer, interval));
+        self
+    }
+}
+
+impl MetricSink for BufferedTcpMetricSink {
+    fn emit(&self, metric: &str) -> io::Result<usize> {
+        let mut writer = self.buffer.lock().unwrap();
+        writer.write(metric.as_bytes())
+    }
+
+    fn emit_batch(&self, metrics: &[&str]) -> io::Result<usize> {
+        let mut writer = self.buffer.lock().unwrap();
+        emit_each(metrics, |metric| writer.write(metric.as_bytes()))
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let mut writer = self.buffer.lock().unwrap();
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BufferedTcpMetricSink, TcpMetricSink};
+    use crate::sinks::core::MetricSink;
+    use crate::sinks::reconnect::ConnectionOptions;
+    use std::io::{BufRead, BufReader};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::Duration;
+
+    fn read_line(reader: &mut BufReader<TcpStream>) -> String {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        line
+    }
+
+    fn accept(listener: &TcpListener) -> BufReader<TcpStream> {
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        BufReader::new(stream)
+    }
+
+    #[test]
+    fn test_tcp_metric_sink() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let sink = TcpMetricSink::from(listener.local_addr().unwrap()).unwrap();
+
+        assert_eq!(7, sink.emit("buz:1|c").unwrap());
+        let mut reader = accept(&listener);
+        assert_eq!("buz:1|c\n", read_line(&mut reader));
+    }
+
+    #[test]
+    fn test_tcp_metric_sink_not_listening() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let sink = TcpMetricSink::from(addr).unwrap();
+        assert!(sink.emit("buz:1|c").is_err());
+    }
+
+    #[test]
+    fn test_tcp_metric_sink_reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let options = ConnectionOptions::default().with_backoff(Duration::from_millis(1), Duration::from_millis(1));
+        let sink = TcpMetricSink::with_options(listener.local_addr().unwrap(), options).unwrap();
+
+        sink.emit("foo:1|c").unwrap();
+        let reader = accept(&listener);
+        drop(reader);
+
+        // Writes may keep succeeding for a bit after the server has closed
+        // the connection, until the TCP stack notices that it's gone.
+        let mut failed = false;
+        for _ in 0..100 {
+            if sink.emit("foo:2|c").is_err() {
+                failed = true;
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert!(failed, "expected an error writing to a closed connection");
+        sink.emit("bar:1|c").unwrap();
+
+        let mut reader = accept(&listener);
+        assert_eq!("bar:1|c\n", read_line(&mut reader));
+    }
+
+    #[test]
+    fn test_buffered_tcp_metric_sink() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let sink = BufferedTcpMetricSink::with_capacity(listener.local_addr().unwrap(), 64).unwrap();
+
+        sink.emit("foo:54|c").unwrap();
+        sink.emit("foo:67|c").unwrap();
+        sink.flush().unwrap();
+
+        let mut reader = accept(&listener);
+        assert_eq!("foo:54|c\n", read_line(&mut reader));
+        assert_eq!("foo:67|c\n", read_line(&mut reader));
+    }
+
+    #[test]
+    fn test_buffered_tcp_metric_sink_bigger_than_buffer() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let sink = BufferedTcpMetricSink::with_capacity(listener.local_addr().unwrap(), 16).unwrap();
+
+        sink.emit("foo:5|g").unwrap();
+        sink.emit("some.long.metric.name:1|c").unwrap();
+        sink.emit("foo:+1|g").unwrap();
+        drop(sink);
+
+        let mut reader = accept(&listener);
+        assert_eq!("foo:5|g\n", read_line(&mut reader));
+        assert_eq!("some.long.metric.name:1|c\n", read_line(&mut reader));
+        assert_eq!("foo:+1|g\n", read_line(&mut reader));
+    }
+}
@@ -11,18 +11,35 @@
 use std::io;
 use std::io::Write;
 use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use crate::io::MultiLineWriter;
-use crate::sinks::core::MetricSink;
+use crate::sinks::core::{emit_each, MetricSink};
+use crate::sinks::flush::PeriodicFlush;
+use crate::sinks::resolve::{ResolveOptions, ResolvingAddr};
 use crate::types::{ErrorKind, MetricError, MetricResult};
 
-// Default size of the buffer for buffered metric sinks. This
-// is a rather conservative value, picked to make sure the entire
-// buffer fits in a small UDP packet. Users may want to use a
-// different value based on the configuration of the network
-// their application runs in.
-const DEFAULT_BUFFER_SIZE: usize = 512;
+// Default size of the buffer for buffered metric sinks. This is the
+// largest UDP payload that fits in a single packet on an Ethernet
+// network (1500 byte MTU) after IP and UDP headers, with some room
+// left for tunnels. Users may want to use a different value based on
+// the configuration of the network their application runs in.
+pub(crate) const DEFAULT_BUFFER_SIZE: usize = 1432;
+
+// Default number of buffers for striped buffered sinks. Threads beyond
+// this share buffers, which is still much less contention than a single
+// buffer for every thread.
+const DEFAULT_STRIPES: usize = 8;
+
+// Stripe used by each thread, assigned round-robin the first time a thread
+// emits a metric so that threads are spread evenly over the buffers of a sink.
+static NEXT_STRIPE: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static THREAD_STRIPE: usize = NEXT_STRIPE.fetch_add(1, Ordering::Relaxed);
+}
 
 /// Attempt to convert anything implementing the `ToSocketAddrs` trait
 /// into a concrete `SocketAddr` instance, returning an `InvalidInput`
@@ -30,7 +47,7 @@ const DEFAULT_BUFFER_SIZE: usize = 512;
 // Public portion of the API (the sink constructors) is pass by value so
 // there's no point in changing this to be pass by reference yet.
 #[allow(clippy::needless_pass_by_value)]
-fn get_addr<A: ToSocketAddrs>(addr: A) -> MetricResult<SocketAddr> {
+pub(crate) fn get_addr<A: ToSocketAddrs>(addr: A) -> MetricResult<SocketAddr> {
     match addr.to_socket_addrs()?.next() {
         Some(addr) => Ok(addr),
         None => Err(MetricError::from((
@@ -40,6 +57,31 @@ fn get_addr<A: ToSocketAddrs>(addr: A) -> MetricResult<SocketAddr> {
     }
 }
 
+/// Address of the server that metrics are sent to, either resolved once
+/// when a sink is created or resolved again periodically.
+#[derive(Debug, Clone)]
+enum Destination {
+    Fixed(SocketAddr),
+    Resolving(ResolvingAddr),
+}
+
+impl Destination {
+    fn send(&self, socket: &UdpSocket, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Destination::Fixed(addr) => socket.send_to(buf, addr),
+            Destination::Resolving(addr) => {
+                let res = socket.send_to(buf, addr.get());
+                addr.record(&res);
+                res
+            }
+        }
+    }
+}
+
+fn new_resolving(host: &str, options: ResolveOptions) -> MetricResult<Destination> {
+    Ok(Destination::Resolving(ResolvingAddr::new(host, options)?))
+}
+
 /// Implementation of a `MetricSink` that emits metrics over UDP.
 ///
 /// This is the most basic version of `MetricSink` that sends metrics over
@@ -48,9 +90,13 @@ fn get_addr<A: ToSocketAddrs>(addr: A) -> MetricResult<SocketAddr> {
 ///
 /// Each metric is sent to the Statsd server when the `.emit()` method is
 /// called, in the thread of the caller.
+///
+/// By default, the address of the server is resolved once when the sink is
+/// created. Sinks created with `with_options` resolve it again periodically
+/// instead, see `ResolveOptions`.
 #[derive(Debug)]
 pub struct UdpMetricSink {
-    addr: SocketAddr,
+    addr: Destination,
     socket: UdpSocket,
 }
 
@@ -102,33 +148,68 @@ impl UdpMetricSink {
     where
         A: ToSocketAddrs,
     {
-        let addr = get_addr(to_addr)?;
+        let addr = Destination::Fixed(get_addr(to_addr)?);
+        Ok(UdpMetricSink { addr, socket })
+    }
+
+    /// Construct a new `UdpMetricSink` instance that resolves the address
+    /// of the metric server again according to the given options.
+    ///
+    /// The host should be the hostname and port of the remote metric server,
+    /// separated by a colon. The socket should already be bound to a local
+    /// address with any desired configuration applied.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::net::UdpSocket;
+    /// use std::time::Duration;
+    /// use cadence::{ResolveOptions, UdpMetricSink};
+    ///
+    /// let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+    /// let options = ResolveOptions::default().with_interval(Duration::from_secs(30));
+    /// let sink = UdpMetricSink::with_options("metrics.example.com:8125", socket, options);
+    /// ```
+    ///
+    /// # Failures
+    ///
+    /// This method may fail if it is unable to resolve the hostname of the
+    /// metric server initially.
+    pub fn with_options(host: &str, socket: UdpSocket, options: ResolveOptions) -> MetricResult<UdpMetricSink> {
+        let addr = new_resolving(host, options)?;
         Ok(UdpMetricSink { addr, socket })
     }
 }
 
 impl MetricSink for UdpMetricSink {
+    // Batches use the default `.emit_batch()` and send one datagram per metric.
+    // Sending several datagrams in a single call with `sendmmsg` isn't possible
+    // without unsafe code, which this crate forbids. Use `BufferedUdpMetricSink`
+    // to pack multiple metrics into each datagram instead.
     fn emit(&self, metric: &str) -> io::Result<usize> {
-        self.socket.send_to(metric.as_bytes(), &self.addr)
+        self.addr.send(&self.socket, metric.as_bytes())
     }
 }
 
 /// Adapter for writing to a `UdpSocket` via the `Write` trait
 #[derive(Debug)]
 pub(crate) struct UdpWriteAdapter {
-    addr: SocketAddr,
+    addr: Destination,
     socket: UdpSocket,
 }
 
 impl UdpWriteAdapter {
     pub(crate) fn new(addr: SocketAddr, socket: UdpSocket) -> UdpWriteAdapter {
-        UdpWriteAdapter { addr, socket }
+        UdpWriteAdapter {
+            addr: Destination::Fixed(addr),
+            socket,
+        }
     }
 }
 
 impl Write for UdpWriteAdapter {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.socket.send_to(buf, &self.addr)
+        self.addr.send(&self.socket, buf)
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -145,27 +226,33 @@ impl Write for UdpWriteAdapter {
 /// a UDP socket and then the metric is written to the buffer. The buffer is
 /// also flushed when this sink is destroyed.
 ///
-/// The default size of the buffer is 512 bytes. This is the "safest"
-/// size for a UDP packet according to the Etsy Statsd docs. The
-/// buffer size can be customized using the `with_capacity` method
-/// to create the sink if desired.
+/// The default size of the buffer is 1432 bytes, the largest payload that
+/// fits in a single packet on an Ethernet network. Networks with a smaller
+/// MTU, such as some VPNs, may need a smaller buffer. The buffer size can be
+/// customized using the `with_capacity` method to create the sink if desired.
 ///
-/// If a metric larger than the buffer is emitted, it will be written
-/// directly to the underlying UDP socket, bypassing the buffer.
+/// Metrics larger than the buffer aren't sent since they would need a larger
+/// packet than the buffer was sized for. Emitting one returns an error that
+/// wraps a `MetricTooLargeError`.
 ///
 /// Note that since metrics are buffered until a certain size is reached, it's
 /// possible that they may sit in the buffer for a while for applications
 /// that do not emit metrics frequently or at a high volume. For these low-
 /// throughput use cases, it may make more sense to use the `UdpMetricSink`
-/// since it sends metrics immediately with no buffering.
+/// since it sends metrics immediately with no buffering, or to set a maximum
+/// latency for the buffer with `with_max_latency`.
+///
+/// Like the `UdpMetricSink`, sinks created with `with_options` periodically
+/// resolve the address of the server again, see `ResolveOptions`.
 #[derive(Debug)]
 pub struct BufferedUdpMetricSink {
-    buffer: Mutex<MultiLineWriter<UdpWriteAdapter>>,
+    buffer: Arc<Mutex<MultiLineWriter<UdpWriteAdapter>>>,
+    flush: Option<PeriodicFlush>,
 }
 
 impl BufferedUdpMetricSink {
     /// Construct a new `BufferedUdpMetricSink` instance with a default
-    /// buffer size of 512 bytes.
+    /// buffer size of 1432 bytes.
     ///
     /// The address should be the address of the remote metric server to
     /// emit metrics to over UDP. The socket should already be bound to a
@@ -173,7 +260,7 @@ impl BufferedUdpMetricSink {
     /// non-blocking, timeouts, etc.).
     ///
     /// Writes to this sink are automatically suffixed with a Unix newline
-    /// ('\n') by the sink and stored in a 512 byte buffer until the buffer
+    /// ('\n') by the sink and stored in a 1432 byte buffer until the buffer
     /// is full or this sink is destroyed, at which point the buffer will be
     /// flushed.
     ///
@@ -239,9 +326,79 @@ impl BufferedUdpMetricSink {
     {
         let addr = get_addr(sink_addr)?;
         Ok(BufferedUdpMetricSink {
-            buffer: Mutex::new(MultiLineWriter::new(UdpWriteAdapter::new(addr, socket), cap)),
+            buffer: Arc::new(Mutex::new(
+                MultiLineWriter::new(UdpWriteAdapter::new(addr, socket), cap).rejecting_oversized(),
+            )),
+            flush: None,
+        })
+    }
+
+    /// Construct a new `BufferedUdpMetricSink` instance with a custom buffer
+    /// size that resolves the address of the metric server again according
+    /// to the given options.
+    ///
+    /// The host should be the hostname and port of the remote metric server,
+    /// separated by a colon. The socket should already be bound to a local
+    /// address with any desired configuration applied.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::net::UdpSocket;
+    /// use cadence::{BufferedUdpMetricSink, ResolveOptions};
+    ///
+    /// let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+    /// let options = ResolveOptions::default().with_error_threshold(5);
+    /// let sink = BufferedUdpMetricSink::with_options("metrics.example.com:8125", socket, 1432, options);
+    /// ```
+    ///
+    /// # Failures
+    ///
+    /// This method may fail if it is unable to resolve the hostname of the
+    /// metric server initially.
+    pub fn with_options(
+        host: &str,
+        socket: UdpSocket,
+        cap: usize,
+        options: ResolveOptions,
+    ) -> MetricResult<BufferedUdpMetricSink> {
+        let adapter = UdpWriteAdapter {
+            addr: new_resolving(host, options)?,
+            socket,
+        };
+
+        Ok(BufferedUdpMetricSink {
+            buffer: Arc::new(Mutex::new(MultiLineWriter::new(adapter, cap).rejecting_oversized())),
+            flush: None,
         })
     }
+
+    /// Flush metrics in the buffer at least once every `interval`, even if
+    /// the buffer isn't full.
+    ///
+    /// This bounds how long metrics can sit in the buffer for applications
+    /// that don't emit metrics often, without the application needing to run
+    /// its own thread to call `.flush()`. A background thread is started to
+    /// flush the buffer and stops when this sink is dropped. Errors flushing
+    /// the buffer from the background thread are discarded.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::net::UdpSocket;
+    /// use std::time::Duration;
+    /// use cadence::{BufferedUdpMetricSink, DEFAULT_PORT};
+    ///
+    /// let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+    /// let host = ("metrics.example.com", DEFAULT_PORT);
+    /// let sink = BufferedUdpMetricSink::from(host, socket)
+    ///     .unwrap()
+    ///     .with_max_latency(Duration::from_secs(1));
+    /// ```
+    pub fn with_max_latency(mut self, interval: Duration) -> Self {
+        self.flush = Some(PeriodicFlush::spawn(&self.buffer, interval));
+        self
+    }
 }
 
 impl MetricSink for BufferedUdpMetricSink {
@@ -250,16 +407,236 @@ impl MetricSink for BufferedUdpMetricSink {
         writer.write(metric.as_bytes())
     }
 
+    fn emit_batch(&self, metrics: &[&str]) -> io::Result<usize> {
+        let mut writer = self.buffer.lock().unwrap();
+        emit_each(metrics, |metric| writer.write(metric.as_bytes()))
+    }
+
     fn flush(&self) -> io::Result<()> {
         let mut writer = self.buffer.lock().unwrap();
         writer.flush()
     }
 }
 
+/// Implementation of a `MetricSink` that buffers metrics in several
+/// independent buffers before sending them to a UDP socket.
+///
+/// This works like the `BufferedUdpMetricSink` except that metrics emitted
+/// from different threads go to different buffers (stripes), each with its
+/// own lock. When many threads emit metrics at once they don't all wait on
+/// the same lock. Each buffer is packed and sent as its own datagram when it
+/// is full, so metrics from different threads may arrive in a different order
+/// than they were emitted.
+///
+/// Each thread always uses the same buffer. When there are more threads than
+/// buffers, some threads share a buffer. Calling `.flush()` flushes every
+/// buffer, as does dropping the sink.
+///
+/// Like the `BufferedUdpMetricSink`, sinks created with `with_options`
+/// periodically resolve the address of the server again, see `ResolveOptions`.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::net::UdpSocket;
+/// use cadence::{StatsdClient, StripedBufferedUdpMetricSink, DEFAULT_PORT};
+///
+/// let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+/// let host = ("metrics.example.com", DEFAULT_PORT);
+/// let sink = StripedBufferedUdpMetricSink::with_stripes(host, socket, 1432, 16).unwrap();
+/// let client = StatsdClient::from_sink("my.prefix", sink);
+/// ```
+#[derive(Debug)]
+pub struct StripedBufferedUdpMetricSink {
+    stripes: Vec<Arc<Mutex<MultiLineWriter<UdpWriteAdapter>>>>,
+    flush: Option<PeriodicFlush>,
+}
+
+impl StripedBufferedUdpMetricSink {
+    /// Construct a new `StripedBufferedUdpMetricSink` instance with eight
+    /// buffers of 1432 bytes each.
+    ///
+    /// The address should be the address of the remote metric server to
+    /// emit metrics to over UDP. The socket should already be bound to a
+    /// local address with any desired configuration applied. Each buffer
+    /// sends using its own handle to the same socket.
+    ///
+    /// # Failures
+    ///
+    /// This method may fail if:
+    ///
+    /// * It is unable to resolve the hostname of the metric server.
+    /// * The host address is otherwise unable to be parsed
+    /// * The socket can't be cloned for each buffer
+    pub fn from<A>(sink_addr: A, socket: UdpSocket) -> MetricResult<StripedBufferedUdpMetricSink>
+    where
+        A: ToSocketAddrs,
+    {
+        Self::with_stripes(sink_addr, socket, DEFAULT_BUFFER_SIZE, DEFAULT_STRIPES)
+    }
+
+    /// Construct a new `StripedBufferedUdpMetricSink` instance with eight
+    /// buffers of a custom size.
+    ///
+    /// # Failures
+    ///
+    /// See `StripedBufferedUdpMetricSink::from`.
+    pub fn with_capacity<A>(sink_addr: A, socket: UdpSocket, cap: usize) -> MetricResult<StripedBufferedUdpMetricSink>
+    where
+        A: ToSocketAddrs,
+    {
+        Self::with_stripes(sink_addr, socket, cap, DEFAULT_STRIPES)
+    }
+
+    /// Construct a new `StripedBufferedUdpMetricSink` instance with a custom
+    /// number of buffers, each of a custom size.
+    ///
+    /// Using at least as many buffers as there are threads emitting metrics
+    /// gives each thread a buffer of its own.
+    ///
+    /// # Failures
+    ///
+    /// This method may fail if the number of buffers is zero or for any of
+    /// the reasons `StripedBufferedUdpMetricSink::from` may fail.
+    pub fn with_stripes<A>(
+        sink_addr: A,
+        socket: UdpSocket,
+        cap: usize,
+        stripes: usize,
+    ) -> MetricResult<StripedBufferedUdpMetricSink>
+    where
+        A: ToSocketAddrs,
+    {
+        if stripes == 0 {
+            return Err(MetricError::from((
+                ErrorKind::InvalidInput,
+                "At least one buffer is required",
+            )));
+        }
+
+        Self::with_destination(Destination::Fixed(get_addr(sink_addr)?), socket, cap, stripes)
+    }
+
+    /// Construct a new `StripedBufferedUdpMetricSink` instance with a custom
+    /// number of buffers, each of a custom size, that resolves the address of
+    /// the metric server again according to the given options.
+    ///
+    /// The host should be the hostname and port of the remote metric server,
+    /// separated by a colon. All buffers share the same resolved address, so
+    /// the address is looked up the same number of times no matter how many
+    /// buffers there are.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::net::UdpSocket;
+    /// use cadence::{ResolveOptions, StripedBufferedUdpMetricSink};
+    ///
+    /// let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+    /// let options = ResolveOptions::default().with_error_threshold(5);
+    /// let sink = StripedBufferedUdpMetricSink::with_options("metrics.example.com:8125", socket, 1432, 16, options);
+    /// ```
+    ///
+    /// # Failures
+    ///
+    /// This method may fail if the number of buffers is zero, if it is unable
+    /// to resolve the hostname of the metric server initially, or if the
+    /// socket can't be cloned for each buffer.
+    pub fn with_options(
+        host: &str,
+        socket: UdpSocket,
+        cap: usize,
+        stripes: usize,
+        options: ResolveOptions,
+    ) -> MetricResult<StripedBufferedUdpMetricSink> {
+        if stripes == 0 {
+            return Err(MetricError::from((
+                ErrorKind::InvalidInput,
+                "At least one buffer is required",
+            )));
+        }
+
+        Self::with_destination(new_resolving(host, options)?, socket, cap, stripes)
+    }
+
+    fn with_destination(
+        addr: Destination,
+        socket: UdpSocket,
+        cap: usize,
+        stripes: usize,
+    ) -> MetricResult<StripedBufferedUdpMetricSink> {
+        let new_buffer = |addr: Destination, socket: UdpSocket| {
+            let adapter = UdpWriteAdapter { addr, socket };
+            Arc::new(Mutex::new(MultiLineWriter::new(adapter, cap).rejecting_oversized()))
+        };
+
+        let mut buffers = Vec::with_capacity(stripes);
+        for _ in 1..stripes {
+            buffers.push(new_buffer(addr.clone(), socket.try_clone()?));
+        }
+        buffers.push(new_buffer(addr, socket));
+
+        Ok(StripedBufferedUdpMetricSink {
+            stripes: buffers,
+            flush: None,
+        })
+    }
+
+    /// Flush metrics in every buffer at least once every `interval`, even if
+    /// the buffers aren't full. A single background thread flushes all the
+    /// buffers. See `BufferedUdpMetricSink::with_max_latency` for details.
+    pub fn with_max_latency(mut self, interval: Duration) -> Self {
+        self.flush = Some(PeriodicFlush::spawn_all(&self.stripes, interval));
+        self
+    }
+
+    /// Return the number of buffers metrics are spread over.
+    pub fn stripes(&self) -> usize {
+        self.stripes.len()
+    }
+
+    fn current(&self) -> &Mutex<MultiLineWriter<UdpWriteAdapter>> {
+        let stripe = THREAD_STRIPE.with(|s| *s);
+        &self.stripes[stripe % self.stripes.len()]
+    }
+}
+
+impl MetricSink for StripedBufferedUdpMetricSink {
+    fn emit(&self, metric: &str) -> io::Result<usize> {
+        let mut writer = self.current().lock().unwrap();
+        writer.write(metric.as_bytes())
+    }
+
+    fn emit_batch(&self, metrics: &[&str]) -> io::Result<usize> {
+        let mut writer = self.current().lock().unwrap();
+        emit_each(metrics, |metric| writer.write(metric.as_bytes()))
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let mut res = Ok(());
+
+        for stripe in self.stripes.iter() {
+            let flushed = stripe.lock().unwrap().flush();
+            if res.is_ok() {
+                res = flushed;
+            }
+        }
+
+        res
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{get_addr, BufferedUdpMetricSink, MetricSink, UdpMetricSink};
-    use std::net::UdpSocket;
+    use super::{get_addr, BufferedUdpMetricSink, MetricSink, StripedBufferedUdpMetricSink, UdpMetricSink};
+    use crate::sinks::resolve::ResolveOptions;
+    use crate::test::UdpServerHarness;
+    use std::io;
+    use std::net::{SocketAddr, UdpSocket};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
 
     #[test]
     fn test_get_addr_bad_address() {
@@ -309,4 +686,198 @@ mod tests {
         assert_eq!(8, sink.emit("foo:54|c").unwrap());
         assert!(sink.flush().is_ok());
     }
+
+    #[test]
+    fn test_buffered_udp_metric_sink_flush_received() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received_ref = Arc::clone(&received);
+
+        let harness = UdpServerHarness::new();
+        harness.run(
+            move |s: String| received_ref.lock().unwrap().push(s),
+            |addr| {
+                let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+                let sink = BufferedUdpMetricSink::with_capacity(addr, socket, 64).unwrap();
+
+                sink.emit("foo:54|c").unwrap();
+                sink.emit("foo:67|c").unwrap();
+                sink.flush().unwrap();
+            },
+        );
+
+        assert_eq!(vec!["foo:54|c\nfoo:67|c\n"], *received.lock().unwrap());
+    }
+
+    #[test]
+    fn test_striped_buffered_udp_metric_sink_no_stripes() {
+        let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+        assert!(StripedBufferedUdpMetricSink::with_stripes("127.0.0.1:8125", socket, 64, 0).is_err());
+    }
+
+    #[test]
+    fn test_striped_buffered_udp_metric_sink_flush_received() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received_ref = Arc::clone(&received);
+
+        let harness = UdpServerHarness::new();
+        harness.run(
+            move |s: String| received_ref.lock().unwrap().push(s),
+            |addr| {
+                let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+                let sink = Arc::new(StripedBufferedUdpMetricSink::with_stripes(addr, socket, 64, 4).unwrap());
+                assert_eq!(4, sink.stripes());
+
+                let threads: Vec<_> = (0..4)
+                    .map(|i| {
+                        let sink = Arc::clone(&sink);
+                        thread::spawn(move || sink.emit(&format!("foo:{}|c", i)).unwrap())
+                    })
+                    .collect();
+
+                for t in threads {
+                    t.join().unwrap();
+                }
+
+                // Nothing is sent until every buffer is flushed together
+                sink.flush().unwrap();
+            },
+        );
+
+        let mut lines: Vec<String> = received
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|d| d.lines().map(String::from).collect::<Vec<_>>())
+            .collect();
+        lines.sort();
+
+        assert_eq!(vec!["foo:0|c", "foo:1|c", "foo:2|c", "foo:3|c"], lines);
+    }
+
+    fn bind_receiver() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        socket
+    }
+
+    fn recv(socket: &UdpSocket) -> String {
+        let mut buf = [0u8; 512];
+        let n = socket.recv(&mut buf).unwrap();
+        String::from_utf8(buf[0..n].to_vec()).unwrap()
+    }
+
+    /// Keep sending until a metric arrives at the socket, since the address is
+    /// resolved again in the background and the first sends may still go to
+    /// the previous address.
+    fn emit_until_received<F: Fn()>(socket: &UdpSocket, emit: F) -> String {
+        socket.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+        let mut buf = [0u8; 512];
+
+        for _ in 0..500 {
+            emit();
+            if let Ok(n) = socket.recv(&mut buf) {
+                return String::from_utf8(buf[0..n].to_vec()).unwrap();
+            }
+        }
+
+        panic!("metric never sent to the new address");
+    }
+
+    /// Options that resolve to whatever address is currently in `addr` every time
+    fn resolve_options(addr: &Arc<Mutex<SocketAddr>>) -> ResolveOptions {
+        let addr = Arc::clone(addr);
+        ResolveOptions::default()
+            .with_interval(Duration::from_millis(0))
+            .with_resolver(move |_: &str| -> io::Result<SocketAddr> { Ok(*addr.lock().unwrap()) })
+    }
+
+    #[test]
+    fn test_udp_metric_sink_resolving() {
+        let first = bind_receiver();
+        let second = bind_receiver();
+        let addr = Arc::new(Mutex::new(first.local_addr().unwrap()));
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sink = UdpMetricSink::with_options("statsd.example.com:8125", socket, resolve_options(&addr)).unwrap();
+
+        sink.emit("foo:1|c").unwrap();
+        assert_eq!("foo:1|c", recv(&first));
+
+        *addr.lock().unwrap() = second.local_addr().unwrap();
+        assert_eq!(
+            "foo:2|c",
+            emit_until_received(&second, || {
+                sink.emit("foo:2|c").unwrap();
+            })
+        );
+    }
+
+    #[test]
+    fn test_buffered_udp_metric_sink_resolving() {
+        let first = bind_receiver();
+        let second = bind_receiver();
+        let addr = Arc::new(Mutex::new(first.local_addr().unwrap()));
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sink =
+            BufferedUdpMetricSink::with_options("statsd.example.com:8125", socket, 64, resolve_options(&addr)).unwrap();
+
+        sink.emit("foo:1|c").unwrap();
+        sink.flush().unwrap();
+        assert_eq!("foo:1|c\n", recv(&first));
+
+        *addr.lock().unwrap() = second.local_addr().unwrap();
+        assert_eq!(
+            "foo:2|c\n",
+            emit_until_received(&second, || {
+                sink.emit("foo:2|c").unwrap();
+                sink.flush().unwrap();
+            })
+        );
+    }
+
+    #[test]
+    fn test_striped_buffered_udp_metric_sink_resolving() {
+        let first = bind_receiver();
+        let second = bind_receiver();
+        let addr = Arc::new(Mutex::new(first.local_addr().unwrap()));
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let calls_ref = calls.clone();
+        let options = resolve_options(&addr).with_resolver({
+            let addr = addr.clone();
+            move |_: &str| -> io::Result<SocketAddr> {
+                calls_ref.fetch_add(1, Ordering::SeqCst);
+                Ok(*addr.lock().unwrap())
+            }
+        });
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sink =
+            StripedBufferedUdpMetricSink::with_options("statsd.example.com:8125", socket, 64, 4, options).unwrap();
+
+        // Every buffer shares the address resolved when the sink was created
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+        sink.emit("foo:1|c").unwrap();
+        sink.flush().unwrap();
+        assert_eq!("foo:1|c\n", recv(&first));
+
+        *addr.lock().unwrap() = second.local_addr().unwrap();
+        assert_eq!(
+            "foo:2|c\n",
+            emit_until_received(&second, || {
+                sink.emit("foo:2|c").unwrap();
+                sink.flush().unwrap();
+            })
+        );
+    }
+
+    #[test]
+    fn test_udp_metric_sink_resolving_initial_failure() {
+        let options = ResolveOptions::default()
+            .with_resolver(|_: &str| -> io::Result<SocketAddr> { Err(io::Error::from(io::ErrorKind::NotFound)) });
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert!(UdpMetricSink::with_options("statsd.example.com:8125", socket, options).is_err());
+    }
 }
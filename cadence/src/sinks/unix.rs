@@ -13,17 +13,25 @@ use std::io;
 use std::io::Write;
 use std::os::unix::net::UnixDatagram;
 use std::path::{Path, PathBuf};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use crate::io::MultiLineWriter;
-use crate::sinks::core::MetricSink;
+use crate::sinks::core::{emit_each, MetricSink};
+use crate::sinks::flush::PeriodicFlush;
 
-// Default size of the buffer for buffered metric sinks. This
-// is a rather conservative value, picked for consistency with
-// the UDP implementation.  Users may want to use a different
-// value based on the configuration of the server their
-// application is running on.
-const DEFAULT_BUFFER_SIZE: usize = 512;
+// Default size of the buffer for buffered metric sinks. Datagrams
+// over a Unix socket aren't limited by a network MTU so they can be
+// much larger than UDP packets, this is the size recommended for the
+// Datadog agent. Users may want to use a different value based on the
+// configuration of the server their application is running on.
+#[cfg(not(target_os = "macos"))]
+pub(crate) const DEFAULT_BUFFER_SIZE: usize = 8192;
+
+// macOS doesn't accept Unix datagrams larger than 2048 bytes by default
+// (`net.local.dgram.maxdgram`) so use that instead.
+#[cfg(target_os = "macos")]
+pub(crate) const DEFAULT_BUFFER_SIZE: usize = 2048;
 
 /// Implementation of a `MetricSink` that emits metrics over a Unix socket.
 ///
@@ -128,12 +136,17 @@ impl Write for UnixWriteAdapter {
 /// a Unix socket and then the metric is written to the buffer. The buffer is
 /// also flushed when this sink is destroyed.
 ///
-/// The default size of the buffer is 512 bytes. This is to be consistent with
-/// the default for the `BufferedUdpMetricSink`. The buffer size can be customized
-/// using the `with_capacity` method to create the sink if desired.
+/// The default size of the buffer is 8192 bytes, or 2048 bytes on macOS. The
+/// largest datagram a Unix socket accepts depends on the platform and its
+/// configuration: it's limited by the send buffer size (`SO_SNDBUF`) on Linux
+/// and to 2048 bytes by default on macOS (`net.local.dgram.maxdgram`), hence
+/// the smaller default there. The buffer size can be customized
+/// using the `with_capacity` method to create the sink if desired, see
+/// `SocketSendBuffer` for checking or raising the send buffer size.
 ///
-/// If a metric larger than the buffer is emitted, it will be written
-/// directly to the underlying Unix socket, bypassing the buffer.
+/// Metrics larger than the buffer aren't sent since they would need a larger
+/// datagram than the buffer was sized for. Emitting one returns an error that
+/// wraps a `MetricTooLargeError`.
 ///
 /// Note that since metrics are buffered until a certain size is reached, it's
 /// possible that they may sit in the buffer for a while for applications
@@ -146,19 +159,20 @@ impl Write for UnixWriteAdapter {
 /// metrics are emitted (though this may not happen on every write due to buffering).
 #[derive(Debug)]
 pub struct BufferedUnixMetricSink {
-    buffer: Mutex<MultiLineWriter<UnixWriteAdapter>>,
+    buffer: Arc<Mutex<MultiLineWriter<UnixWriteAdapter>>>,
+    flush: Option<PeriodicFlush>,
 }
 
 impl BufferedUnixMetricSink {
     /// Construct a new `BufferedUnixMetricSink` instance with a default
-    /// buffer size of 512 bytes.
+    /// buffer size of 8192 bytes (2048 bytes on macOS).
     ///
     /// The socket does not need to be bound (i.e. `UnixDatagram::unbound()` is
     /// fine) but should have any desired configuration already applied
     /// (blocking vs non-blocking, timeouts, etc.).
     ///
     /// Writes to this sink are automatically suffixed with a Unix newline
-    /// ('\n') by the sink and stored in a 512 byte buffer until the buffer
+    /// ('\n') by the sink and stored in the buffer until the buffer
     /// is full or this sink is destroyed, at which point the buffer will be
     /// flushed.
     ///
@@ -203,9 +217,20 @@ impl BufferedUnixMetricSink {
         P: AsRef<Path>,
     {
         BufferedUnixMetricSink {
-            buffer: Mutex::new(MultiLineWriter::new(UnixWriteAdapter::new(socket, path), cap)),
+            buffer: Arc::new(Mutex::new(
+                MultiLineWriter::new(UnixWriteAdapter::new(socket, path), cap).rejecting_oversized(),
+            )),
+            flush: None,
         }
     }
+
+    /// Flush metrics in the buffer at least once every `interval`, even if
+    /// the buffer isn't full, using a background thread that stops when this
+    /// sink is dropped. See `BufferedUdpMetricSink::with_max_latency`.
+    pub fn with_max_latency(mut self, interval: Duration) -> Self {
+        self.flush = Some(PeriodicFlush::spawn(&self.buffer, interval));
+        self
+    }
 }
 
 impl MetricSink for BufferedUnixMetricSink {
@@ -214,6 +239,11 @@ impl MetricSink for BufferedUnixMetricSink {
         writer.write(metric.as_bytes())
     }
 
+    fn emit_batch(&self, metrics: &[&str]) -> io::Result<usize> {
+        let mut writer = self.buffer.lock().unwrap();
+        emit_each(metrics, |metric| writer.write(metric.as_bytes()))
+    }
+
     fn flush(&self) -> io::Result<()> {
         let mut writer = self.buffer.lock().unwrap();
         writer.flush()
@@ -224,6 +254,7 @@ impl MetricSink for BufferedUnixMetricSink {
 mod tests {
     use super::{BufferedUnixMetricSink, MetricSink, UnixMetricSink};
     use crate::test::UnixServerHarness;
+    use crate::types::MetricTooLargeError;
     use std::os::unix::net::UnixDatagram;
 
     #[test]
@@ -282,4 +313,20 @@ mod tests {
             assert!(sink.flush().is_ok());
         });
     }
+
+    #[test]
+    fn test_buffered_unix_metric_sink_too_large() {
+        let harness = UnixServerHarness::new("test_buffered_unix_metric_sink_too_large");
+
+        harness.run_quiet(|path| {
+            let socket = UnixDatagram::unbound().unwrap();
+            let sink = BufferedUnixMetricSink::with_capacity(path, socket, 16);
+
+            let err = sink.emit("some.long.metric.name:1|c").unwrap_err();
+            assert_eq!(Some(&MetricTooLargeError::new(26, 16)), MetricTooLargeError::find(&err));
+
+            // Smaller metrics are still buffered after an error
+            assert_eq!(8, sink.emit("foo:54|c").unwrap());
+        });
+    }
 }
@@ -0,0 +1,352 @@
+// Cadence - An extensible Statsd client for Rust!
+//
+// Copyright 2021 Nick Pillitteri
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::convert::TryFrom;
+use std::io::{self, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::io::MultiLineWriter;
+use crate::sinks::core::{emit_each, MetricSink};
+use crate::sinks::flush::PeriodicFlush;
+use crate::sinks::reconnect::{ConnectionOptions, Connector, Reconnecting};
+
+// Default size of the buffer for buffered metric sinks. This is the
+// size recommended by Datadog for Unix sockets, which aren't limited
+// by the size of packets on a network.
+const DEFAULT_BUFFER_SIZE: usize = 8192;
+
+/// Establishes connections to a Unix stream socket at a particular path
+#[derive(Debug)]
+struct UnixStreamConnector {
+    path: PathBuf,
+}
+
+impl Connector for UnixStreamConnector {
+    type Stream = UnixStream;
+
+    fn connect(&self, options: &ConnectionOptions) -> io::Result<UnixStream> {
+        let stream = UnixStream::connect(&self.path)?;
+        stream.set_write_timeout(Some(options.write_timeout))?;
+        Ok(stream)
+    }
+}
+
+/// Adapter for writing length-prefixed frames to a Unix stream socket via
+/// the `Write` trait.
+///
+/// Each call to `.write()` is sent as a single frame: the length of the
+/// input as a 4 byte little-endian integer followed by the input itself.
+/// This is the framing used by DogStatsD for `SOCK_STREAM` Unix sockets.
+#[derive(Debug)]
+pub(crate) struct UnixStreamWriteAdapter {
+    conn: Reconnecting<UnixStreamConnector>,
+    frame: Vec<u8>,
+}
+
+impl UnixStreamWriteAdapter {
+    fn new(path: PathBuf, options: ConnectionOptions) -> UnixStreamWriteAdapter {
+        UnixStreamWriteAdapter {
+            conn: Reconnecting::new(UnixStreamConnector { path }, options),
+            frame: Vec::new(),
+        }
+    }
+}
+
+impl Write for UnixStreamWriteAdapter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = u32::try_from(buf.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Frame too large for length prefix"))?;
+
+        self.frame.clear();
+        self.frame.extend_from_slice(&len.to_le_bytes());
+        self.frame.extend_from_slice(buf);
+        self.conn.send(&self.frame)?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Implementation of a `MetricSink` that emits metrics over a Unix stream
+/// socket.
+///
+/// Each metric is sent as a length-prefixed frame, as expected by DogStatsD
+/// for `SOCK_STREAM` Unix sockets, when the `.emit()` method is called, in the
+/// thread of the caller. Unlike Unix datagram sockets, metrics are not dropped
+/// when the receiving end is busy. Instead, writes block up to the configured
+/// write timeout.
+///
+/// The connection to the socket is established the first time a metric is
+/// emitted and re-established if it is lost (when the agent restarts, for
+/// example), waiting between failed attempts to connect. Metrics emitted while
+/// the sink is not connected are discarded and an error is returned. See
+/// `ConnectionOptions` for details, note that the connect timeout does not
+/// apply to Unix sockets.
+#[derive(Debug)]
+pub struct UnixStreamMetricSink {
+    writer: Mutex<UnixStreamWriteAdapter>,
+}
+
+impl UnixStreamMetricSink {
+    /// Construct a new `UnixStreamMetricSink` instance with the default
+    /// connection options.
+    ///
+    /// The path should be the path of the Unix stream socket of the remote
+    /// metric server to emit metrics to.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use cadence::UnixStreamMetricSink;
+    ///
+    /// let sink = UnixStreamMetricSink::from("/var/run/datadog/dsd.socket");
+    /// ```
+    pub fn from<P>(path: P) -> UnixStreamMetricSink
+    where
+        P: AsRef<Path>,
+    {
+        Self::with_options(path, ConnectionOptions::default())
+    }
+
+    /// Construct a new `UnixStreamMetricSink` instance with the given
+    /// connection options.
+    pub fn with_options<P>(path: P, options: ConnectionOptions) -> UnixStreamMetricSink
+    where
+        P: AsRef<Path>,
+    {
+        UnixStreamMetricSink {
+            writer: Mutex::new(UnixStreamWriteAdapter::new(path.as_ref().to_path_buf(), options)),
+        }
+    }
+}
+
+impl MetricSink for UnixStreamMetricSink {
+    fn emit(&self, metric: &str) -> io::Result<usize> {
+        let mut writer = self.writer.lock().unwrap();
+        writer.write(metric.as_bytes())
+    }
+
+    fn emit_batch(&self, metrics: &[&str]) -> io::Result<usize> {
+        let mut writer = self.writer.lock().unwrap();
+        emit_each(metrics, |metric| writer.write(metric.as_bytes()))
+    }
+}
+
+/// Implementation of a `MetricSink` that buffers metrics before sending
+/// them to a Unix stream socket.
+///
+/// Metrics are line buffered, meaning that a trailing "\n" is added after
+/// each metric written to this sink. When the buffer is sufficiently full
+/// and a write is attempted, the contents of the buffer are sent to the
+/// socket as a single length-prefixed frame and then the metric is written
+/// to the buffer. The buffer is also flushed when this sink is destroyed.
+///
+/// The default size of the buffer is 8192 bytes. The buffer size can be
+/// customized using the `with_capacity` method to create the sink if desired.
+///
+/// If a metric larger than the buffer is emitted, any metrics already in the
+/// buffer are sent first and then it's sent directly to the socket as its own
+/// frame, bypassing the buffer. Metrics are always sent in the order they
+/// were emitted.
+///
+/// The connection is managed the same way as the `UnixStreamMetricSink`.
+#[derive(Debug)]
+pub struct BufferedUnixStreamMetricSink {
+    buffer: Arc<Mutex<MultiLineWriter<UnixStreamWriteAdapter>>>,
+    flush: Option<PeriodicFlush>,
+}
+
+impl BufferedUnixStreamMetricSink {
+    /// Construct a new `BufferedUnixStreamMetricSink` instance with a default
+    /// buffer size of 8192 bytes and the default connection options.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use cadence::BufferedUnixStreamMetricSink;
+    ///
+    /// let sink = BufferedUnixStreamMetricSink::from("/var/run/datadog/dsd.socket");
+    /// ```
+    pub fn from<P>(path: P) -> BufferedUnixStreamMetricSink
+    where
+        P: AsRef<Path>,
+    {
+        Self::with_capacity(path, DEFAULT_BUFFER_SIZE)
+    }
+
+    /// Construct a new `BufferedUnixStreamMetricSink` instance with a custom
+    /// buffer size and the default connection options.
+    pub fn with_capacity<P>(path: P, cap: usize) -> BufferedUnixStreamMetricSink
+    where
+        P: AsRef<Path>,
+    {
+        Self::with_options(path, cap, ConnectionOptions::default())
+    }
+
+    /// Construct a new `BufferedUnixStreamMetricSink` instance with a custom
+    /// buffer size and the given connection options.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use cadence::{BufferedUnixStreamMetricSink, ConnectionOptions};
+    ///
+    /// let options = ConnectionOptions::default().with_write_timeout(Duration::from_millis(100));
+    /// let sink = BufferedUnixStreamMetricSink::with_options("/var/run/datadog/dsd.socket", 16384, options);
+    /// ```
+    pub fn with_options<P>(path: P, cap: usize, options: ConnectionOptions) -> BufferedUnixStreamMetricSink
+    where
+        P: AsRef<Path>,
+    {
+        let adapter = UnixStreamWriteAdapter::new(path.as_ref().to_path_buf(), options);
+        BufferedUnixStreamMetricSink {
+            buffer: Arc::new(Mutex::new(MultiLineWriter::new(adapter, cap).keeping_order())),
+            flush: None,
+        }
+    }
+
+    /// Flush metrics in the buffer at least once every `interval`, even if
+    /// the buffer isn't full, using a background thread that stops when this
+    /// sink is dropped. See `BufferedUdpMetricSink::with_max_latency`.
+    pub fn with_max_latency(mut self, interval: Duration) -> Self {
+        self.flush = Some(PeriodicFlush::spawn(&self.buffer, interval));
+        self
+    }
+}
+
+impl MetricSink for BufferedUnixStreamMetricSink {
+    fn emit(&self, metric: &str) -> io::Result<usize> {
+        let mut writer = self.buffer.lock().unwrap();
+        writer.write(metric.as_bytes())
+    }
+
+    fn emit_batch(&self, metrics: &[&str]) -> io::Result<usize> {
+        let mut writer = self.buffer.lock().unwrap();
+        emit_each(metrics, |metric| writer.write(metric.as_bytes()))
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let mut writer = self.buffer.lock().unwrap();
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BufferedUnixStreamMetricSink, UnixStreamMetricSink};
+    use crate::sinks::core::MetricSink;
+    use crate::sinks::reconnect::ConnectionOptions;
+    use crate::test::TempDir;
+    use std::io::Read;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::thread;
+    use std::time::Duration;
+
+    fn accept(listener: &UnixListener) -> UnixStream {
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream
+    }
+
+    fn read_frame(stream: &mut UnixStream) -> String {
+        let mut len = [0u8; 4];
+        stream.read_exact(&mut len).unwrap();
+
+        let mut payload = vec![0u8; u32::from_le_bytes(len) as usize];
+        stream.read_exact(&mut payload).unwrap();
+        String::from_utf8(payload).unwrap()
+    }
+
+    #[test]
+    fn test_unix_stream_metric_sink() {
+        let temp = TempDir::new("cadence-unix-stream-sink").unwrap();
+        let path = temp.new_path("cadence.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let sink = UnixStreamMetricSink::from(&path);
+        assert_eq!(7, sink.emit("buz:1|c").unwrap());
+        sink.emit("foo:12|g").unwrap();
+
+        let mut stream = accept(&listener);
+        assert_eq!("buz:1|c", read_frame(&mut stream));
+        assert_eq!("foo:12|g", read_frame(&mut stream));
+    }
+
+    #[test]
+    fn test_unix_stream_metric_sink_not_listening() {
+        let temp = TempDir::new("cadence-unix-stream-sink-not-listening").unwrap();
+        let sink = UnixStreamMetricSink::from(temp.new_path("cadence.sock"));
+
+        assert!(sink.emit("buz:1|c").is_err());
+    }
+
+    #[test]
+    fn test_unix_stream_metric_sink_reconnect() {
+        let temp = TempDir::new("cadence-unix-stream-sink-reconnect").unwrap();
+        let path = temp.new_path("cadence.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let options = ConnectionOptions::default().with_backoff(Duration::from_millis(1), Duration::from_millis(1));
+        let sink = UnixStreamMetricSink::with_options(&path, options);
+        sink.emit("foo:1|c").unwrap();
+
+        // Simulate the agent restarting by closing the connection and
+        // creating a new socket at the same path.
+        drop(accept(&listener));
+        drop(listener);
+        std::fs::remove_file(&path).unwrap();
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let mut failed = false;
+        for _ in 0..100 {
+            if sink.emit("foo:2|c").is_err() {
+                failed = true;
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert!(failed, "expected an error writing to a closed connection");
+        sink.emit("bar:1|c").unwrap();
+
+        let mut stream = accept(&listener);
+        assert_eq!("bar:1|c", read_frame(&mut stream));
+    }
+
+    #[test]
+    fn test_buffered_unix_stream_metric_sink() {
+        let temp = TempDir::new("cadence-buffered-unix-stream-sink").unwrap();
+        let path = temp.new_path("cadence.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+
+        // Set the capacity of the buffer such that only two metrics
+        // fit before it needs to be flushed
+        let sink = BufferedUnixStreamMetricSink::with_capacity(&path, 20);
+        sink.emit("foo:54|c").unwrap();
+        sink.emit("foo:67|c").unwrap();
+        sink.emit("foo:78|c").unwrap();
+        sink.emit("some.long.metric.name:1|c").unwrap();
+        drop(sink);
+
+        let mut stream = accept(&listener);
+        // Metrics already buffered are sent before a metric too large for
+        // the buffer so that frames arrive in the order metrics were emitted.
+        assert_eq!("foo:54|c\nfoo:67|c\n", read_frame(&mut stream));
+        assert_eq!("foo:78|c\n", read_frame(&mut stream));
+        assert_eq!("some.long.metric.name:1|c", read_frame(&mut stream));
+    }
+}
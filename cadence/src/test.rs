@@ -17,12 +17,14 @@
 
 use crate::MetricSink;
 use std::fs;
-use std::io::{self, ErrorKind};
-use std::os::unix::net::UnixDatagram;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::{UnixDatagram, UnixListener, UnixStream};
 use std::panic::RefUnwindSafe;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
 use std::time::Duration;
 use std::{env, thread};
@@ -73,6 +75,14 @@ where
     }
 }
 
+/// Is this error the result of hitting the read timeout of a socket?
+///
+/// Depending on the platform, a read timeout is reported as either
+/// `WouldBlock` or `TimedOut`.
+fn is_timeout(e: &io::Error) -> bool {
+    e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut
+}
+
 /// Basic server for listening on a given Unix socket path.
 ///
 /// This server reads messages from a Unix datagram socket in a loop, ensures
@@ -81,6 +91,7 @@ where
 ///
 /// This server is only meant for testing Unix socket related functionality in
 /// Cadence itself.
+#[cfg(unix)]
 pub struct UnixSocketServer {
     ready: AtomicBool,
     shutdown: AtomicBool,
@@ -89,6 +100,7 @@ pub struct UnixSocketServer {
     interval: Duration,
 }
 
+#[cfg(unix)]
 impl UnixSocketServer {
     /// Create a new server that will listen for datagrams on the given path, using
     /// the provided interval on the read timeout as part of its main loop.
@@ -134,7 +146,7 @@ impl UnixSocketServer {
                     // If the "shutdown" flag has been set by the client they've sent
                     // all the metrics they are going to send and we can shutdown the
                     // server. Otherwise, just ignore the WouldBlock error.
-                    if e.kind() == ErrorKind::WouldBlock {
+                    if is_timeout(&e) {
                         if self.shutdown.load(Ordering::Acquire) {
                             break;
                         }
@@ -154,17 +166,20 @@ impl UnixSocketServer {
         self.shutdown.store(true, Ordering::Release);
     }
 }
+
 /// Wrapper around a `UnixSocketServer` to start and stop it in the course
 /// of running a single test.
 ///
 /// The server is stopped and the thread it was running in is joined from
 /// the destructor of this struct.
+#[cfg(unix)]
 pub struct UnixServerHarness {
     base: PathBuf,
     server: Option<Arc<UnixSocketServer>>,
     thread: Option<JoinHandle<()>>,
 }
 
+#[cfg(unix)]
 impl UnixServerHarness {
     pub fn new<P>(prefix: P) -> Self
     where
@@ -210,6 +225,7 @@ impl UnixServerHarness {
     }
 }
 
+#[cfg(unix)]
 impl Drop for UnixServerHarness {
     fn drop(&mut self) {
         if let Some(s) = self.server.take() {
@@ -222,6 +238,523 @@ impl Drop for UnixServerHarness {
     }
 }
 
+/// Basic server for listening on a given Unix stream socket path.
+///
+/// This server accepts connections in a loop and reads frames prefixed with
+/// their length as a 4 byte little-endian integer from each of them in a
+/// separate thread, ensures they are valid UTF-8 strings, and then passes
+/// each frame (without the length prefix) to a `DatagramConsumer`. Any errors
+/// are printed to `stderr`.
+///
+/// This server is only meant for testing Unix stream socket related functionality
+/// in Cadence itself.
+#[cfg(unix)]
+pub struct UnixStreamServer {
+    shutdown: Arc<AtomicBool>,
+    interval: Duration,
+    listener: UnixListener,
+    consumer: Arc<dyn DatagramConsumer + Send + Sync + 'static>,
+    connections: Mutex<Vec<JoinHandle<()>>>,
+}
+
+#[cfg(unix)]
+impl UnixStreamServer {
+    /// Create a new server listening on the given path, using the provided interval
+    /// as the read timeout of connections and how often to check for new connections
+    /// as part of its main loop. Any existing socket at the path is removed first.
+    pub fn new<P, C>(path: P, interval: Duration, consumer: C) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+        C: DatagramConsumer + Send + Sync + 'static,
+    {
+        let _ = fs::remove_file(path.as_ref());
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+
+        Ok(UnixStreamServer {
+            shutdown: Arc::new(AtomicBool::new(false)),
+            interval,
+            listener,
+            consumer: Arc::new(consumer),
+            connections: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Run until the `.shutdown()` method is called, passing each frame read from
+    /// any connection to the consumer.
+    pub fn run(&self) -> io::Result<()> {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    let handle = self.handle(stream)?;
+                    self.connections.lock().unwrap().push(handle);
+                }
+                Err(e) => {
+                    // Once there are no more connections waiting to be accepted and
+                    // the "shutdown" flag has been set, stop.
+                    if e.kind() != ErrorKind::WouldBlock {
+                        eprintln!("Error: {} - {:?}", e, e.kind());
+                    } else if self.shutdown.load(Ordering::Acquire) {
+                        break;
+                    } else {
+                        thread::sleep(self.interval / 10);
+                    }
+                }
+            }
+        }
+
+        for handle in self.connections.lock().unwrap().drain(..) {
+            let _ = handle.join();
+        }
+
+        Ok(())
+    }
+
+    fn handle(&self, mut stream: UnixStream) -> io::Result<JoinHandle<()>> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(self.interval))?;
+
+        let shutdown = Arc::clone(&self.shutdown);
+        let consumer = Arc::clone(&self.consumer);
+
+        Ok(thread::spawn(move || {
+            // Bytes read before hitting the timeout are kept in the pending buffer
+            // until a complete frame is available.
+            let mut pending = Vec::new();
+            let mut buf = [0u8; 1024];
+
+            loop {
+                match stream.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        pending.extend_from_slice(&buf[0..n]);
+                        while let Some(frame) = next_frame(&mut pending) {
+                            match String::from_utf8(frame) {
+                                Ok(s) => consumer.accept(s),
+                                Err(e) => eprintln!("Error: Couldn't decode string to utf-8 {}", e),
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        if !is_timeout(&e) {
+                            eprintln!("Error: {} - {:?}", e, e.kind());
+                            break;
+                        } else if shutdown.load(Ordering::Acquire) {
+                            break;
+                        }
+                    }
+                }
+            }
+        }))
+    }
+
+    /// Indicate that the server should stop its main run loop.
+    pub fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::Release);
+    }
+}
+
+/// Remove and return the first complete length-prefixed frame in the buffer, if any.
+#[cfg(unix)]
+fn next_frame(pending: &mut Vec<u8>) -> Option<Vec<u8>> {
+    if pending.len() < 4 {
+        return None;
+    }
+
+    let len = u32::from_le_bytes([pending[0], pending[1], pending[2], pending[3]]) as usize;
+    if pending.len() < 4 + len {
+        return None;
+    }
+
+    let frame = pending[4..4 + len].to_vec();
+    pending.drain(0..4 + len);
+    Some(frame)
+}
+
+/// Wrapper around a `UnixStreamServer` to start and stop it in the course
+/// of running a single test.
+///
+/// The server is stopped and the threads it was using are joined from the
+/// destructor of this struct. Connections are read from until they are closed
+/// so everything sent by sinks that were dropped by the end of the body of the
+/// test has been passed to the consumer by the time `.run()` returns.
+#[cfg(unix)]
+pub struct UnixStreamServerHarness {
+    base: PathBuf,
+    server: Option<Arc<UnixStreamServer>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[cfg(unix)]
+impl UnixStreamServerHarness {
+    pub fn new<P>(prefix: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        UnixStreamServerHarness {
+            base: prefix.as_ref().to_path_buf(),
+            server: None,
+            thread: None,
+        }
+    }
+
+    pub fn run<C, F>(mut self, consumer: C, body: F)
+    where
+        C: DatagramConsumer + Send + Sync + 'static,
+        F: FnOnce(&Path),
+    {
+        let temp = TempDir::new(&self.base).unwrap();
+        let socket = temp.new_path("cadence.sock");
+
+        let server = Arc::new(UnixStreamServer::new(&socket, Duration::from_millis(100), consumer).unwrap());
+        let server_local = Arc::clone(&server);
+
+        let t = thread::spawn(move || {
+            server_local.run().unwrap();
+        });
+
+        self.server = Some(server);
+        self.thread = Some(t);
+
+        body(&socket);
+    }
+
+    pub fn run_quiet<F>(self, body: F)
+    where
+        F: FnOnce(&Path),
+    {
+        self.run(|_| (), body)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for UnixStreamServerHarness {
+    fn drop(&mut self) {
+        if let Some(s) = self.server.take() {
+            s.shutdown();
+        }
+
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
+
+/// Basic server for listening for UDP datagrams on an ephemeral localhost port.
+///
+/// This server reads datagrams from a UDP socket in a loop, ensures they are
+/// valid UTF-8 strings, and then passes them to a `DatagramConsumer`. Each call
+/// to the consumer corresponds to exactly one datagram so callers can make
+/// assertions about how metrics were packed by buffered sinks. Any errors are
+/// printed to `stderr`.
+///
+/// This server is only meant for testing UDP related functionality in Cadence
+/// itself.
+pub struct UdpSocketServer {
+    shutdown: AtomicBool,
+    socket: UdpSocket,
+    consumer: Arc<dyn DatagramConsumer + Send + Sync + 'static>,
+}
+
+impl UdpSocketServer {
+    /// Create a new server bound to an ephemeral port on localhost, using the
+    /// provided interval as the read timeout as part of its main loop.
+    pub fn new<C>(interval: Duration, consumer: C) -> io::Result<Self>
+    where
+        C: DatagramConsumer + Send + Sync + 'static,
+    {
+        let socket = UdpSocket::bind("127.0.0.1:0")?;
+        socket.set_read_timeout(Some(interval))?;
+
+        Ok(UdpSocketServer {
+            shutdown: AtomicBool::new(false),
+            socket,
+            consumer: Arc::new(consumer),
+        })
+    }
+
+    /// Get the local address the server is listening on.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Run until the `.shutdown()` method is called, passing each datagram to the consumer.
+    pub fn run(&self) -> io::Result<()> {
+        // Large enough for the biggest possible UDP payload so that datagrams
+        // are never truncated, no matter what size buffer a sink uses.
+        let mut buf = vec![0u8; 65536];
+
+        loop {
+            match self.socket.recv(&mut buf) {
+                Ok(v) => match std::str::from_utf8(&buf[0..v]) {
+                    Ok(s) => self.consumer.accept(s.to_owned()),
+                    Err(e) => eprintln!("Error: Couldn't decode string to utf-8 {}", e),
+                },
+                Err(e) => {
+                    // Hitting the receive timeout is expected. Once there are no more
+                    // datagrams to read and the "shutdown" flag has been set, stop.
+                    if is_timeout(&e) {
+                        if self.shutdown.load(Ordering::Acquire) {
+                            break;
+                        }
+                    } else {
+                        eprintln!("Error: {} - {:?}", e, e.kind());
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Indicate that the server should stop its main run loop.
+    pub fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::Release);
+    }
+}
+
+/// Wrapper around a `UdpSocketServer` to start and stop it in the course
+/// of running a single test.
+///
+/// The server is stopped and the thread it was running in is joined from
+/// the destructor of this struct. Since the server only stops once there
+/// are no more datagrams to read, everything sent by the body of the test
+/// has been passed to the consumer by the time `.run()` returns.
+pub struct UdpServerHarness {
+    server: Option<Arc<UdpSocketServer>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl UdpServerHarness {
+    pub fn new() -> Self {
+        UdpServerHarness {
+            server: None,
+            thread: None,
+        }
+    }
+
+    pub fn run<C, F>(mut self, consumer: C, body: F)
+    where
+        C: DatagramConsumer + Send + Sync + 'static,
+        F: FnOnce(SocketAddr),
+    {
+        let server = Arc::new(UdpSocketServer::new(Duration::from_millis(100), consumer).unwrap());
+        let addr = server.local_addr().unwrap();
+        let server_local = Arc::clone(&server);
+
+        let t = thread::spawn(move || {
+            server_local.run().unwrap();
+        });
+
+        self.server = Some(server);
+        self.thread = Some(t);
+
+        body(addr);
+    }
+
+    pub fn run_quiet<F>(self, body: F)
+    where
+        F: FnOnce(SocketAddr),
+    {
+        self.run(|_| (), body)
+    }
+}
+
+impl Default for UdpServerHarness {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for UdpServerHarness {
+    fn drop(&mut self) {
+        if let Some(s) = self.server.take() {
+            s.shutdown();
+        }
+
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
+
+/// Basic server for accepting TCP connections on an ephemeral localhost port.
+///
+/// This server accepts connections in a loop and reads newline separated
+/// lines from each of them in a separate thread, ensures they are valid UTF-8
+/// strings, and then passes each line (without the trailing newline) to a
+/// `DatagramConsumer`. Any errors are printed to `stderr`.
+///
+/// This server is only meant for testing TCP related functionality in Cadence
+/// itself.
+pub struct TcpSocketServer {
+    shutdown: Arc<AtomicBool>,
+    interval: Duration,
+    listener: TcpListener,
+    consumer: Arc<dyn DatagramConsumer + Send + Sync + 'static>,
+    connections: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl TcpSocketServer {
+    /// Create a new server bound to an ephemeral port on localhost, using the
+    /// provided interval as the read timeout of connections and how often to
+    /// check for new connections as part of its main loop.
+    pub fn new<C>(interval: Duration, consumer: C) -> io::Result<Self>
+    where
+        C: DatagramConsumer + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        listener.set_nonblocking(true)?;
+
+        Ok(TcpSocketServer {
+            shutdown: Arc::new(AtomicBool::new(false)),
+            interval,
+            listener,
+            consumer: Arc::new(consumer),
+            connections: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Get the local address the server is listening on.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Run until the `.shutdown()` method is called, passing each line read from
+    /// any connection to the consumer.
+    pub fn run(&self) -> io::Result<()> {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    let handle = self.handle(stream)?;
+                    self.connections.lock().unwrap().push(handle);
+                }
+                Err(e) => {
+                    // Once there are no more connections waiting to be accepted and
+                    // the "shutdown" flag has been set, stop.
+                    if e.kind() != ErrorKind::WouldBlock {
+                        eprintln!("Error: {} - {:?}", e, e.kind());
+                    } else if self.shutdown.load(Ordering::Acquire) {
+                        break;
+                    } else {
+                        thread::sleep(self.interval / 10);
+                    }
+                }
+            }
+        }
+
+        for handle in self.connections.lock().unwrap().drain(..) {
+            let _ = handle.join();
+        }
+
+        Ok(())
+    }
+
+    fn handle(&self, stream: TcpStream) -> io::Result<JoinHandle<()>> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(self.interval))?;
+
+        let shutdown = Arc::clone(&self.shutdown);
+        let consumer = Arc::clone(&self.consumer);
+
+        Ok(thread::spawn(move || {
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+
+            loop {
+                // Bytes read before hitting the timeout are kept in the line, so
+                // it's only cleared once a complete line has been consumed.
+                match reader.read_line(&mut line) {
+                    Ok(0) => break,
+                    Ok(_) if line.ends_with('\n') => {
+                        line.pop();
+                        consumer.accept(std::mem::take(&mut line));
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        if !is_timeout(&e) {
+                            eprintln!("Error: {} - {:?}", e, e.kind());
+                            break;
+                        } else if shutdown.load(Ordering::Acquire) {
+                            break;
+                        }
+                    }
+                }
+            }
+        }))
+    }
+
+    /// Indicate that the server should stop its main run loop.
+    pub fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::Release);
+    }
+}
+
+/// Wrapper around a `TcpSocketServer` to start and stop it in the course
+/// of running a single test.
+///
+/// The server is stopped and the threads it was using are joined from the
+/// destructor of this struct. Connections are read from until they are closed
+/// so everything sent by sinks that were dropped by the end of the body of the
+/// test has been passed to the consumer by the time `.run()` returns.
+pub struct TcpServerHarness {
+    server: Option<Arc<TcpSocketServer>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TcpServerHarness {
+    pub fn new() -> Self {
+        TcpServerHarness {
+            server: None,
+            thread: None,
+        }
+    }
+
+    pub fn run<C, F>(mut self, consumer: C, body: F)
+    where
+        C: DatagramConsumer + Send + Sync + 'static,
+        F: FnOnce(SocketAddr),
+    {
+        let server = Arc::new(TcpSocketServer::new(Duration::from_millis(100), consumer).unwrap());
+        let addr = server.local_addr().unwrap();
+        let server_local = Arc::clone(&server);
+
+        let t = thread::spawn(move || {
+            server_local.run().unwrap();
+        });
+
+        self.server = Some(server);
+        self.thread = Some(t);
+
+        body(addr);
+    }
+
+    pub fn run_quiet<F>(self, body: F)
+    where
+        F: FnOnce(SocketAddr),
+    {
+        self.run(|_| (), body)
+    }
+}
+
+impl Default for TcpServerHarness {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for TcpServerHarness {
+    fn drop(&mut self) {
+        if let Some(s) = self.server.take() {
+            s.shutdown();
+        }
+
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
+
 /// `MetricSink` implementation that wraps another reference counted
 /// `MetricSink` so that the caller can keep a reference to it (useful
 /// for testing the `QueuingMetricSink` so that we can inspect the
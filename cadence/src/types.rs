@@ -12,7 +12,7 @@ use std::error;
 use std::fmt;
 use std::io;
 
-use crate::builder::MetricFormatter;
+use crate::builder::{write_tags, MetricFormatter};
 
 /// Trait for metrics to expose Statsd metric string slice representation.
 ///
@@ -188,10 +188,113 @@ impl Metric for Set {
     }
 }
 
+/// Value of a metric, as an integer or floating point number.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MetricValue {
+    /// Value of counters and sets.
+    Signed(i64),
+    /// Value of timers, gauges, meters, and histograms.
+    Unsigned(u64),
+    /// Value of gauges created from a floating point number.
+    Float(f64),
+}
+
+impl fmt::Display for MetricValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            MetricValue::Signed(i) => i.fmt(f),
+            MetricValue::Unsigned(i) => i.fmt(f),
+            MetricValue::Float(i) => i.fmt(f),
+        }
+    }
+}
+
+/// Type of a metric, displayed as the suffix used by the Statsd protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MetricType {
+    /// Counter, written as `c`.
+    Counter,
+    /// Timer in milliseconds, written as `ms`.
+    Timer,
+    /// Gauge, written as `g`.
+    Gauge,
+    /// Meter, written as `m`.
+    Meter,
+    /// Histogram, written as `h`.
+    Histogram,
+    /// Set, written as `s`.
+    Set,
+}
+
+impl fmt::Display for MetricType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            MetricType::Counter => "c".fmt(f),
+            MetricType::Timer => "ms".fmt(f),
+            MetricType::Gauge => "g".fmt(f),
+            MetricType::Meter => "m".fmt(f),
+            MetricType::Histogram => "h".fmt(f),
+            MetricType::Set => "s".fmt(f),
+        }
+    }
+}
+
+/// Structured representation of a metric before it is turned into a
+/// string and sent to a `MetricSink`.
+///
+/// Records are passed to interceptors registered with a `StatsdClientBuilder`
+/// which may change any part of them. The key includes the prefix of the
+/// client that created it. Tags are key-value pairs or just values, in the
+/// order they will be written. The `Display` implementation writes a record
+/// in the canonical Statsd format.
+///
+/// # Example
+///
+/// ```
+/// use cadence::{MetricRecord, MetricType, MetricValue};
+///
+/// let mut record = MetricRecord::new("some.prefix.some.key", MetricValue::Signed(1), MetricType::Counter);
+/// record.tags.push((Some("region".to_string()), "us-east-2".to_string()));
+///
+/// assert_eq!("some.prefix.some.key:1|c|#region:us-east-2", record.to_string());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricRecord {
+    /// Full key of the metric, including the prefix of the client.
+    pub key: String,
+    /// Value of the metric.
+    pub value: MetricValue,
+    /// Type of the metric.
+    pub metric_type: MetricType,
+    /// Tags of the metric as optional keys and values.
+    pub tags: Vec<(Option<String>, String)>,
+}
+
+impl MetricRecord {
+    /// Create a new record without any tags.
+    pub fn new<K: ToString>(key: K, value: MetricValue, metric_type: MetricType) -> Self {
+        MetricRecord {
+            key: key.to_string(),
+            value,
+            metric_type,
+            tags: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for MetricRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}|{}", self.key, self.value, self.metric_type)?;
+        write_tags(f, self.tags.iter().map(|(k, v)| (k.as_deref(), v.as_str())))
+    }
+}
+
 /// Potential categories an error from this library falls into.
 #[derive(PartialEq, Eq, Debug, Hash, Clone, Copy)]
 pub enum ErrorKind {
+    /// A metric or some configuration was not valid.
     InvalidInput,
+    /// Sending a metric failed.
     IoError,
 }
 
@@ -270,11 +373,79 @@ impl From<(ErrorKind, &'static str)> for MetricError {
 
 pub type MetricResult<T> = Result<T, MetricError>;
 
+/// Error from a buffered datagram sink when a metric is too large to fit in
+/// its buffer, and so can't be sent without exceeding the packet size the
+/// buffer was sized for.
+///
+/// Sinks return this wrapped in an `io::Error` with the kind `InvalidInput`.
+/// Since the OS can return other `InvalidInput` errors, use
+/// `MetricTooLargeError::find` to tell them apart.
+///
+/// # Example
+///
+/// ```
+/// use std::net::UdpSocket;
+/// use cadence::{BufferedUdpMetricSink, MetricSink, MetricTooLargeError};
+///
+/// let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+/// let sink = BufferedUdpMetricSink::with_capacity("127.0.0.1:8125", socket, 16).unwrap();
+///
+/// let err = sink.emit("some.very.long.metric.name:1|c").unwrap_err();
+/// let too_large = MetricTooLargeError::find(&err).unwrap();
+/// assert_eq!(31, too_large.size());
+/// assert_eq!(16, too_large.capacity());
+/// ```
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct MetricTooLargeError {
+    size: usize,
+    capacity: usize,
+}
+
+impl MetricTooLargeError {
+    pub(crate) fn new(size: usize, capacity: usize) -> Self {
+        MetricTooLargeError { size, capacity }
+    }
+
+    /// Return the `MetricTooLargeError` wrapped by an I/O error returned by
+    /// a sink, if there is one.
+    pub fn find(err: &io::Error) -> Option<&MetricTooLargeError> {
+        err.get_ref().and_then(|e| e.downcast_ref::<MetricTooLargeError>())
+    }
+
+    /// Number of bytes needed to buffer the metric, including its line ending.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Size of the buffer in bytes.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl fmt::Display for MetricTooLargeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Metric of {} bytes is larger than the {} byte buffer",
+            self.size, self.capacity
+        )
+    }
+}
+
+impl error::Error for MetricTooLargeError {}
+
+impl From<MetricTooLargeError> for io::Error {
+    fn from(err: MetricTooLargeError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidInput, err)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #![allow(deprecated, deprecated_in_future)]
 
-    use super::{Counter, ErrorKind, Gauge, Histogram, Meter, Metric, MetricError, Set, Timer};
+    use super::{Counter, ErrorKind, Gauge, Histogram, Meter, Metric, MetricError, MetricTooLargeError, Set, Timer};
     use std::error::Error;
     use std::io;
 
@@ -388,4 +559,21 @@ mod tests {
         let our_err = MetricError::from((ErrorKind::InvalidInput, "Nope!"));
         assert!(our_err.source().is_none());
     }
+
+    #[test]
+    fn test_metric_too_large_error_find() {
+        let io_err = io::Error::from(MetricTooLargeError::new(600, 512));
+        assert_eq!(io::ErrorKind::InvalidInput, io_err.kind());
+        assert_eq!(
+            Some(&MetricTooLargeError::new(600, 512)),
+            MetricTooLargeError::find(&io_err)
+        );
+        assert_eq!(
+            "Metric of 600 bytes is larger than the 512 byte buffer",
+            io_err.to_string()
+        );
+
+        let other = io::Error::new(io::ErrorKind::InvalidInput, "Nope!");
+        assert_eq!(None, MetricTooLargeError::find(&other));
+    }
 }
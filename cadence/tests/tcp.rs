@@ -0,0 +1,118 @@
+use cadence::prelude::*;
+use cadence::test::TcpServerHarness;
+use cadence::{BufferedTcpMetricSink, QueuingMetricSink, StatsdClient, TcpMetricSink};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+mod utils;
+use utils::{run_arc_threaded_test, NUM_ITERATIONS, NUM_THREADS};
+
+fn new_tcp_client(prefix: &str, addr: SocketAddr) -> StatsdClient {
+    let sink = TcpMetricSink::from(addr).unwrap();
+    StatsdClient::from_sink(prefix, sink)
+}
+
+fn new_buffered_tcp_client(prefix: &str, addr: SocketAddr) -> StatsdClient {
+    let sink = BufferedTcpMetricSink::from(addr).unwrap();
+    StatsdClient::from_sink(prefix, sink)
+}
+
+fn new_queuing_buffered_tcp_client(prefix: &str, addr: SocketAddr) -> StatsdClient {
+    let buffered = BufferedTcpMetricSink::from(addr).unwrap();
+    let sink = QueuingMetricSink::from(buffered);
+    StatsdClient::from_sink(prefix, sink)
+}
+
+#[test]
+fn test_statsd_client_tcp_sink_single_threaded() {
+    let harness = TcpServerHarness::new();
+    harness.run_quiet(|addr| {
+        let client = new_tcp_client("cadence", addr);
+        run_arc_threaded_test(client, 1, 1);
+    });
+}
+
+#[test]
+fn test_statsd_client_buffered_tcp_sink_single_threaded() {
+    let harness = TcpServerHarness::new();
+    harness.run_quiet(|addr| {
+        let client = new_buffered_tcp_client("cadence", addr);
+        run_arc_threaded_test(client, 1, 1);
+    });
+}
+
+#[test]
+fn test_statsd_client_queuing_buffered_tcp_sink_single_threaded() {
+    let harness = TcpServerHarness::new();
+    harness.run_quiet(|addr| {
+        let client = new_queuing_buffered_tcp_client("cadence", addr);
+        run_arc_threaded_test(client, 1, 1);
+    });
+}
+
+#[test]
+fn test_statsd_client_buffered_tcp_sink_line_per_metric() {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let received_ref = Arc::clone(&received);
+
+    let harness = TcpServerHarness::new();
+    harness.run(
+        move |s: String| received_ref.lock().unwrap().push(s),
+        |addr| {
+            // Small enough buffer that it's flushed several times, with one
+            // metric that's too big to be buffered at all.
+            let sink = BufferedTcpMetricSink::with_capacity(addr, 50).unwrap();
+            let client = StatsdClient::from_sink("cadence", sink);
+
+            client.count("some.counter", 1).unwrap();
+            client.count("some.counter", 2).unwrap();
+            client.count("some.counter", 3).unwrap();
+            client
+                .count_with_tags("some.counter", 4)
+                .with_tag("host", "app11.example.com")
+                .try_send()
+                .unwrap();
+        },
+    );
+
+    let received = received.lock().unwrap();
+    assert_eq!(
+        vec![
+            "cadence.some.counter:1|c",
+            "cadence.some.counter:2|c",
+            "cadence.some.counter:3|c",
+            "cadence.some.counter:4|c|#host:app11.example.com",
+        ],
+        *received
+    );
+}
+
+#[ignore]
+#[test]
+fn test_statsd_client_tcp_sink_many_threaded() {
+    let harness = TcpServerHarness::new();
+    harness.run_quiet(|addr| {
+        let client = new_tcp_client("cadence", addr);
+        run_arc_threaded_test(client, NUM_THREADS, NUM_ITERATIONS);
+    });
+}
+
+#[ignore]
+#[test]
+fn test_statsd_client_buffered_tcp_sink_many_threaded() {
+    let harness = TcpServerHarness::new();
+    harness.run_quiet(|addr| {
+        let client = new_buffered_tcp_client("cadence", addr);
+        run_arc_threaded_test(client, NUM_THREADS, NUM_ITERATIONS);
+    });
+}
+
+#[ignore]
+#[test]
+fn test_statsd_client_queuing_buffered_tcp_sink_many_threaded() {
+    let harness = TcpServerHarness::new();
+    harness.run_quiet(|addr| {
+        let client = new_queuing_buffered_tcp_client("cadence", addr);
+        run_arc_threaded_test(client, NUM_THREADS, NUM_ITERATIONS);
+    });
+}
@@ -1,38 +1,41 @@
-use cadence::test::DelegatingMetricSink;
-use cadence::{BufferedUdpMetricSink, QueuingMetricSink, StatsdClient, UdpMetricSink, DEFAULT_PORT};
-use std::net::UdpSocket;
-use std::sync::Arc;
+use cadence::prelude::*;
+use cadence::test::{DelegatingMetricSink, UdpServerHarness};
+use cadence::{BufferedUdpMetricSink, QueuingMetricSink, StatsdClient, UdpMetricSink};
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
 use std::thread;
 
 mod utils;
 use utils::{run_arc_threaded_test, NUM_ITERATIONS, NUM_THREADS};
 
-const TARGET_HOST: (&str, u16) = ("127.0.0.1", DEFAULT_PORT);
 const BUFFER_SZ: usize = 512;
 const QUEUE_SZ: usize = 512 * 1024;
 
-fn new_udp_client(prefix: &str) -> StatsdClient {
+fn new_udp_client(prefix: &str, addr: SocketAddr) -> StatsdClient {
     let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
-    let sink = UdpMetricSink::from(TARGET_HOST, socket).unwrap();
+    let sink = UdpMetricSink::from(addr, socket).unwrap();
     StatsdClient::from_sink(prefix, sink)
 }
 
-fn new_buffered_udp_client(prefix: &str) -> StatsdClient {
+fn new_buffered_udp_client(prefix: &str, addr: SocketAddr) -> StatsdClient {
     let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
-    let sink = BufferedUdpMetricSink::from(TARGET_HOST, socket).unwrap();
+    let sink = BufferedUdpMetricSink::from(addr, socket).unwrap();
     StatsdClient::from_sink(prefix, sink)
 }
 
-fn new_queuing_buffered_udp_client(prefix: &str) -> StatsdClient {
+fn new_queuing_buffered_udp_client(prefix: &str, addr: SocketAddr) -> StatsdClient {
     let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
-    let buffered = BufferedUdpMetricSink::from(TARGET_HOST, socket).unwrap();
+    let buffered = BufferedUdpMetricSink::from(addr, socket).unwrap();
     let sink = QueuingMetricSink::from(buffered);
     StatsdClient::from_sink(prefix, sink)
 }
 
-fn new_delegating_queuing_buffered_udp_client(prefix: &str) -> (StatsdClient, Arc<QueuingMetricSink>) {
+fn new_delegating_queuing_buffered_udp_client(
+    prefix: &str,
+    addr: SocketAddr,
+) -> (StatsdClient, Arc<QueuingMetricSink>) {
     let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
-    let buffered = BufferedUdpMetricSink::with_capacity(TARGET_HOST, socket, BUFFER_SZ).unwrap();
+    let buffered = BufferedUdpMetricSink::with_capacity(addr, socket, BUFFER_SZ).unwrap();
     let queuing = Arc::new(QueuingMetricSink::with_capacity(buffered, QUEUE_SZ));
     let sink = DelegatingMetricSink::new(queuing.clone());
     let client = StatsdClient::from_sink(prefix, sink);
@@ -41,52 +44,123 @@ fn new_delegating_queuing_buffered_udp_client(prefix: &str) -> (StatsdClient, Ar
 
 #[test]
 fn test_statsd_client_udp_sink_single_threaded() {
-    let client = new_udp_client("cadence");
-    run_arc_threaded_test(client, 1, 1);
+    let harness = UdpServerHarness::new();
+    harness.run_quiet(|addr| {
+        let client = new_udp_client("cadence", addr);
+        run_arc_threaded_test(client, 1, 1);
+    });
 }
 
 #[test]
 fn test_statsd_client_buffered_udp_sink_single_threaded() {
-    let client = new_buffered_udp_client("cadence");
-    run_arc_threaded_test(client, 1, 1);
+    let harness = UdpServerHarness::new();
+    harness.run_quiet(|addr| {
+        let client = new_buffered_udp_client("cadence", addr);
+        run_arc_threaded_test(client, 1, 1);
+    });
 }
 
 #[test]
 fn test_statsd_client_queuing_buffered_udp_sink_single_threaded() {
-    let client = new_queuing_buffered_udp_client("cadence");
-    run_arc_threaded_test(client, 1, 1);
+    let harness = UdpServerHarness::new();
+    harness.run_quiet(|addr| {
+        let client = new_queuing_buffered_udp_client("cadence", addr);
+        run_arc_threaded_test(client, 1, 1);
+    });
+}
+
+#[test]
+fn test_statsd_client_udp_sink_datagram_per_metric() {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let received_ref = Arc::clone(&received);
+
+    let harness = UdpServerHarness::new();
+    harness.run(
+        move |s: String| received_ref.lock().unwrap().push(s),
+        |addr| {
+            let client = new_udp_client("cadence", addr);
+            client.count("some.counter", 1).unwrap();
+            client.gauge("some.gauge", 2).unwrap();
+        },
+    );
+
+    let received = received.lock().unwrap();
+    assert_eq!(vec!["cadence.some.counter:1|c", "cadence.some.gauge:2|g"], *received);
+}
+
+#[test]
+fn test_statsd_client_buffered_udp_sink_packet_boundaries() {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let received_ref = Arc::clone(&received);
+
+    let harness = UdpServerHarness::new();
+    harness.run(
+        move |s: String| received_ref.lock().unwrap().push(s),
+        |addr| {
+            // Each metric is 25 bytes with a trailing newline so exactly two of
+            // them fit in the buffer before it needs to be flushed.
+            let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+            let sink = BufferedUdpMetricSink::with_capacity(addr, socket, 50).unwrap();
+            let client = StatsdClient::from_sink("cadence", sink);
+
+            client.count("some.counter", 1).unwrap();
+            client.count("some.counter", 2).unwrap();
+            client.count("some.counter", 3).unwrap();
+        },
+    );
+
+    let received = received.lock().unwrap();
+    assert_eq!(
+        vec![
+            "cadence.some.counter:1|c\ncadence.some.counter:2|c\n",
+            "cadence.some.counter:3|c\n",
+        ],
+        *received
+    );
 }
 
 #[ignore]
 #[test]
 fn test_statsd_client_udp_sink_many_threaded() {
-    let client = new_udp_client("cadence");
-    run_arc_threaded_test(client, NUM_THREADS, NUM_ITERATIONS);
+    let harness = UdpServerHarness::new();
+    harness.run_quiet(|addr| {
+        let client = new_udp_client("cadence", addr);
+        run_arc_threaded_test(client, NUM_THREADS, NUM_ITERATIONS);
+    });
 }
 
 #[ignore]
 #[test]
 fn test_statsd_client_buffered_udp_sink_many_threaded() {
-    let client = new_buffered_udp_client("cadence");
-    run_arc_threaded_test(client, NUM_THREADS, NUM_ITERATIONS);
+    let harness = UdpServerHarness::new();
+    harness.run_quiet(|addr| {
+        let client = new_buffered_udp_client("cadence", addr);
+        run_arc_threaded_test(client, NUM_THREADS, NUM_ITERATIONS);
+    });
 }
 
 #[ignore]
 #[test]
 fn test_statsd_client_queuing_buffered_udp_sink_many_threaded() {
-    let client = new_queuing_buffered_udp_client("cadence");
-    run_arc_threaded_test(client, NUM_THREADS, NUM_ITERATIONS);
+    let harness = UdpServerHarness::new();
+    harness.run_quiet(|addr| {
+        let client = new_queuing_buffered_udp_client("cadence", addr);
+        run_arc_threaded_test(client, NUM_THREADS, NUM_ITERATIONS);
+    });
 }
 
 #[ignore]
 #[test]
 fn test_statsd_client_queuing_delegating_many_threaded() {
-    let (client, sink) = new_delegating_queuing_buffered_udp_client("cadence");
-    run_arc_threaded_test(client, NUM_THREADS, NUM_ITERATIONS);
-
-    let mut queued = sink.queued();
-    while queued > 0 {
-        queued = sink.queued();
-        thread::yield_now();
-    }
+    let harness = UdpServerHarness::new();
+    harness.run_quiet(|addr| {
+        let (client, sink) = new_delegating_queuing_buffered_udp_client("cadence", addr);
+        run_arc_threaded_test(client, NUM_THREADS, NUM_ITERATIONS);
+
+        let mut queued = sink.queued();
+        while queued > 0 {
+            queued = sink.queued();
+            thread::yield_now();
+        }
+    });
 }
@@ -0,0 +1,150 @@
+#![cfg(unix)]
+
+use cadence::prelude::*;
+use cadence::test::UnixStreamServerHarness;
+use cadence::{BufferedUnixStreamMetricSink, QueuingMetricSink, StatsdClient, UnixStreamMetricSink};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+mod utils;
+use utils::{run_arc_threaded_test, NUM_ITERATIONS, NUM_THREADS};
+
+fn new_unix_stream_client<P>(prefix: &str, path: P) -> StatsdClient
+where
+    P: AsRef<Path>,
+{
+    let sink = UnixStreamMetricSink::from(path);
+    StatsdClient::from_sink(prefix, sink)
+}
+
+fn new_buffered_unix_stream_client<P>(prefix: &str, path: P) -> StatsdClient
+where
+    P: AsRef<Path>,
+{
+    let sink = BufferedUnixStreamMetricSink::from(path);
+    StatsdClient::from_sink(prefix, sink)
+}
+
+fn new_queuing_buffered_unix_stream_client<P>(prefix: &str, path: P) -> StatsdClient
+where
+    P: AsRef<Path>,
+{
+    let buffered = BufferedUnixStreamMetricSink::from(path);
+    let sink = QueuingMetricSink::from(buffered);
+    StatsdClient::from_sink(prefix, sink)
+}
+
+#[test]
+fn test_statsd_client_unix_stream_sink_single_threaded() {
+    let harness = UnixStreamServerHarness::new("test_statsd_client_unix_stream_sink_single_threaded");
+    harness.run_quiet(|socket| {
+        let client = new_unix_stream_client("client.test", socket);
+        run_arc_threaded_test(client, 1, 1);
+    });
+}
+
+#[test]
+fn test_statsd_client_buffered_unix_stream_sink_single_threaded() {
+    let harness = UnixStreamServerHarness::new("test_statsd_client_buffered_unix_stream_sink_single_threaded");
+    harness.run_quiet(|socket| {
+        let client = new_buffered_unix_stream_client("client.test", socket);
+        run_arc_threaded_test(client, 1, 1);
+    });
+}
+
+#[test]
+fn test_statsd_client_queuing_buffered_unix_stream_sink_single_threaded() {
+    let harness = UnixStreamServerHarness::new("test_statsd_client_queuing_buffered_unix_stream_sink_single_threaded");
+    harness.run_quiet(|socket| {
+        let client = new_queuing_buffered_unix_stream_client("client.test", socket);
+        run_arc_threaded_test(client, 1, 1);
+    });
+}
+
+#[test]
+fn test_statsd_client_unix_stream_sink_frame_per_metric() {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let received_ref = Arc::clone(&received);
+
+    let harness = UnixStreamServerHarness::new("test_statsd_client_unix_stream_sink_frame_per_metric");
+    harness.run(
+        move |s: String| received_ref.lock().unwrap().push(s),
+        |socket| {
+            let client = new_unix_stream_client("cadence", socket);
+
+            client.count("some.counter", 1).unwrap();
+            client
+                .count_with_tags("some.counter", 2)
+                .with_tag("host", "app11.example.com")
+                .try_send()
+                .unwrap();
+        },
+    );
+
+    let received = received.lock().unwrap();
+    assert_eq!(
+        vec![
+            "cadence.some.counter:1|c",
+            "cadence.some.counter:2|c|#host:app11.example.com",
+        ],
+        *received
+    );
+}
+
+#[test]
+fn test_statsd_client_buffered_unix_stream_sink_frame_per_flush() {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let received_ref = Arc::clone(&received);
+
+    let harness = UnixStreamServerHarness::new("test_statsd_client_buffered_unix_stream_sink_frame_per_flush");
+    harness.run(
+        move |s: String| received_ref.lock().unwrap().push(s),
+        |socket| {
+            let sink = BufferedUnixStreamMetricSink::with_capacity(socket, 50);
+            let client = StatsdClient::from_sink("cadence", sink);
+
+            client.count("some.counter", 1).unwrap();
+            client.count("some.counter", 2).unwrap();
+            client.count("some.counter", 3).unwrap();
+        },
+    );
+
+    let received = received.lock().unwrap();
+    assert_eq!(
+        vec![
+            "cadence.some.counter:1|c\ncadence.some.counter:2|c\n",
+            "cadence.some.counter:3|c\n",
+        ],
+        *received
+    );
+}
+
+#[ignore]
+#[test]
+fn test_statsd_client_unix_stream_sink_many_threaded() {
+    let harness = UnixStreamServerHarness::new("test_statsd_client_unix_stream_sink_many_threaded");
+    harness.run_quiet(|socket| {
+        let client = new_unix_stream_client("client.test", socket);
+        run_arc_threaded_test(client, NUM_THREADS, NUM_ITERATIONS);
+    });
+}
+
+#[ignore]
+#[test]
+fn test_statsd_client_buffered_unix_stream_sink_many_threaded() {
+    let harness = UnixStreamServerHarness::new("test_statsd_client_buffered_unix_stream_sink_many_threaded");
+    harness.run_quiet(|socket| {
+        let client = new_buffered_unix_stream_client("client.test", socket);
+        run_arc_threaded_test(client, NUM_THREADS, NUM_ITERATIONS);
+    });
+}
+
+#[ignore]
+#[test]
+fn test_statsd_client_queuing_buffered_unix_stream_sink_many_threaded() {
+    let harness = UnixStreamServerHarness::new("test_statsd_client_queuing_buffered_unix_stream_sink_many_threaded");
+    harness.run_quiet(|socket| {
+        let client = new_queuing_buffered_unix_stream_client("client.test", socket);
+        run_arc_threaded_test(client, NUM_THREADS, NUM_ITERATIONS);
+    });
+}